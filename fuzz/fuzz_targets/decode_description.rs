@@ -0,0 +1,39 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/decode.rs"]
+mod decode;
+#[allow(dead_code)]
+#[path = "../../src/openmetrics.rs"]
+mod openmetrics;
+
+use crate::decode::{decode_description, Delimiters, extract_identifiers, field_types};
+
+
+#[derive(Arbitrary, Debug)]
+struct Input<'a> {
+    response: &'a [u8],
+    command: &'a str,
+    identifier_fields: Vec<&'a str>,
+    pair_delimiter: char,
+    key_value_delimiter: char,
+}
+
+
+fuzz_target!(|input: Input| {
+    let delimiters = Delimiters {
+        pair: input.pair_delimiter,
+        key_value: input.key_value_delimiter,
+    };
+
+    if let Some(fields) = decode_description(input.response, input.command, delimiters) {
+        let _ = field_types(&fields);
+        let identifiers = extract_identifiers(&fields, input.identifier_fields.iter().copied());
+        for (field, value) in &identifiers {
+            assert!(fields.iter().any(|f| &f.name == field && &f.value == value));
+        }
+    }
+});
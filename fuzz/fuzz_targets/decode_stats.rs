@@ -0,0 +1,54 @@
+#![no_main]
+
+use std::collections::{HashMap, HashSet};
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+#[allow(dead_code)]
+#[path = "../../src/decode.rs"]
+mod decode;
+#[allow(dead_code)]
+#[path = "../../src/openmetrics.rs"]
+mod openmetrics;
+
+use crate::decode::{decode_stats, Delimiters, ValueUnit};
+
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    response: Vec<u8>,
+    command: String,
+    field_types: HashMap<String, String>,
+    string_statistics: HashSet<String>,
+    value_units: Vec<u8>,
+    pair_delimiter: char,
+    key_value_delimiter: char,
+    statistic_prefix: Option<String>,
+}
+
+
+fuzz_target!(|input: Input| {
+    let value_units: Vec<ValueUnit> = input.value_units.iter()
+        .map(|vu| match vu % 3 {
+            0 => ValueUnit::Milliseconds,
+            1 => ValueUnit::Seconds,
+            _ => ValueUnit::Percent,
+        })
+        .collect();
+    let delimiters = Delimiters {
+        pair: input.pair_delimiter,
+        key_value: input.key_value_delimiter,
+    };
+
+    if let Some(statistics) = decode_stats(&input.response, &input.command, &input.field_types, &input.string_statistics, &value_units, delimiters, input.statistic_prefix.as_deref()) {
+        // every statistic ends up in exactly one of the maps
+        for name in statistics.numbers.keys() {
+            assert!(!statistics.strings.contains_key(name));
+        }
+        // output must not panic either
+        for number in statistics.numbers.values() {
+            let _ = number.to_string();
+        }
+    }
+});
@@ -0,0 +1,89 @@
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::body::Bytes;
+use tracing::{debug, warn};
+
+use crate::config::WwwConfig;
+
+
+const FILE_PREFIX: &str = "scrape-";
+const FILE_SUFFIX: &str = ".txt";
+
+
+// distinguishes responses served within the same millisecond
+static SEQUENCE: AtomicU64 = AtomicU64::new(0);
+
+
+/// Returns the name of the audit file for a response served at the given time to the given client.
+///
+/// Names sort chronologically.
+fn file_name(served_at: SystemTime, remote_addr: SocketAddr) -> String {
+    let unix_ms = served_at.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_millis();
+    let sequence = SEQUENCE.fetch_add(1, Ordering::Relaxed);
+
+    // keep the name free of colons (IPv6) and brackets
+    let address: String = remote_addr.ip().to_string().chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '.' { c } else { '_' })
+        .collect();
+
+    format!("{}{:020}-{:020}-{}_{}{}", FILE_PREFIX, unix_ms, sequence, address, remote_addr.port(), FILE_SUFFIX)
+}
+
+
+/// Deletes the oldest audit files until at most `max_files` remain, as well as all audit files older
+/// than `max_age`.
+fn prune(audit_dir: &Path, max_files: usize, max_age: Option<Duration>) -> Result<(), io::Error> {
+    let mut audit_files: Vec<(String, PathBuf)> = Vec::new();
+    for entry_res in std::fs::read_dir(audit_dir)? {
+        let entry = entry_res?;
+        let Ok(name) = entry.file_name().into_string() else { continue };
+        if name.starts_with(FILE_PREFIX) && name.ends_with(FILE_SUFFIX) {
+            audit_files.push((name, entry.path()));
+        }
+    }
+    audit_files.sort_unstable();
+
+    let excess = audit_files.len().saturating_sub(max_files);
+    let now = SystemTime::now();
+    for (i, (_name, path)) in audit_files.iter().enumerate() {
+        let too_many = i < excess;
+        let too_old = match max_age {
+            Some(ma) => {
+                let modified = std::fs::metadata(path)?.modified()?;
+                now.duration_since(modified).unwrap_or(Duration::ZERO) > ma
+            },
+            None => false,
+        };
+        if too_many || too_old {
+            debug!(event = "audit_pruned", path = %path.display(), "deleting old audit file");
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Stores a copy of a served metrics response in the audit directory, then prunes the directory.
+///
+/// Runs in the background so as not to delay the response.
+pub(crate) fn record_response(config: &WwwConfig, remote_addr: SocketAddr, body: Bytes) {
+    let Some(audit_dir) = config.audit_dir.clone() else { return };
+    let max_files = config.audit_max_files;
+    let max_age = config.audit_max_age_ms.map(Duration::from_millis);
+    let path = audit_dir.join(file_name(SystemTime::now(), remote_addr));
+
+    tokio::task::spawn_blocking(move || {
+        if let Err(e) = std::fs::write(&path, &body) {
+            warn!(event = "audit_failed", path = %path.display(), error = %e, "failed to write audit file");
+        }
+        if let Err(e) = prune(&audit_dir, max_files, max_age) {
+            warn!(event = "audit_failed", path = %audit_dir.display(), error = %e, "failed to prune audit directory");
+        }
+    });
+}
@@ -0,0 +1,237 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+use tracing::{error, warn};
+
+use crate::config::Config;
+use crate::decode::{decode_description, decode_stats, Delimiters, Field, Statistics, ValueUnit};
+use crate::radiator::{RadiatorTransport, wrap_command};
+use crate::self_metrics::PERMISSION_DENIED;
+
+
+/// The response to a command referring to an object that does not exist.
+pub(crate) const NO_SUCH_OBJECT_RESPONSE: &[u8] = b"NOSUCHOBJECT";
+
+/// Responses to a command that the logged-in user is not allowed to issue.
+///
+/// Different Radiator versions word this differently.
+const PERMISSION_DENIED_RESPONSES: [&[u8]; 3] = [b"ACCESSDENIED", b"NOPERMISSION", b"PERMISSIONDENIED"];
+
+
+/// The field in which Radiator reports the name of an object.
+pub(crate) const IDENTIFIER_FIELD: &str = "Identifier";
+
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Radiator(crate::radiator::Error),
+    NoSuchObject,
+    PermissionDenied,
+    Rejected(String),
+    UndecodableResponse,
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Radiator(e) => write!(f, "{}", e),
+            Self::NoSuchObject => write!(f, "no such object"),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::Rejected(response) => write!(f, "command rejected with {:?}", response),
+            Self::UndecodableResponse => write!(f, "failed to decode response"),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Radiator(e) => Some(e),
+            Self::NoSuchObject => None,
+            Self::PermissionDenied => None,
+            Self::Rejected(_) => None,
+            Self::UndecodableResponse => None,
+        }
+    }
+}
+impl From<crate::radiator::Error> for Error {
+    fn from(value: crate::radiator::Error) -> Self {
+        Self::Radiator(value)
+    }
+}
+
+
+/// Recognizes the responses with which Radiator refuses to execute a command.
+///
+/// Radiator refuses commands with a single upper-case word (e.g. `NOSUCHOBJECT`); such responses
+/// that have no specific meaning to the exporter are returned as [`Error::Rejected`]. Regular
+/// responses contain colons or the echoed command, so they cannot be mistaken for refusals.
+fn error_response(response: &[u8]) -> Option<Error> {
+    if response == NO_SUCH_OBJECT_RESPONSE {
+        Some(Error::NoSuchObject)
+    } else if PERMISSION_DENIED_RESPONSES.contains(&response) {
+        Some(Error::PermissionDenied)
+    } else if !response.is_empty() && response.iter().all(|b| b.is_ascii_uppercase()) {
+        Some(Error::Rejected(String::from_utf8_lossy(response).into_owned()))
+    } else {
+        None
+    }
+}
+
+
+/// Renames statistics according to the configured aliases.
+///
+/// If a statistic is renamed to the name of another statistic reported by Radiator, the renamed
+/// statistic wins. The statistics are renamed in the order of their original names, so the outcome
+/// does not depend on the order of the hash map.
+fn apply_aliases<V: fmt::Display>(statistics: HashMap<String, V>, aliases: &BTreeMap<String, String>) -> HashMap<String, V> {
+    if aliases.is_empty() {
+        return statistics;
+    }
+
+    let mut aliased_statistics = HashMap::with_capacity(statistics.len());
+    let mut renamed = Vec::new();
+    for (name, value) in statistics {
+        match aliases.get(&name) {
+            Some(canonical_name) => renamed.push((name, canonical_name, value)),
+            None => {
+                aliased_statistics.insert(name, value);
+            },
+        }
+    }
+    renamed.sort_unstable_by(|(name_a, _, _), (name_b, _, _)| name_a.cmp(name_b));
+    for (name, canonical_name, value) in renamed {
+        if let Some(old_value) = aliased_statistics.get(canonical_name) {
+            warn!(
+                "statistic {:?} is aliased to {:?}, which Radiator also reports; overwriting its value {} with {}",
+                name, canonical_name, old_value, value,
+            );
+        }
+        aliased_statistics.insert(canonical_name.clone(), value);
+    }
+    aliased_statistics
+}
+
+
+/// Issues commands to Radiator's management interface and decodes their responses.
+#[derive(Clone, Debug)]
+pub(crate) struct RadiatorClient<'t> {
+    transport: &'t dyn RadiatorTransport,
+    value_units: Vec<ValueUnit>,
+    delimiters: Delimiters,
+    statistic_aliases: BTreeMap<String, String>,
+    command_template: Option<String>,
+    statistic_prefix: Option<String>,
+    decode_offload_min_bytes: Option<usize>,
+
+    /// The statistics to be kept as strings, by the names under which Radiator reports them (i.e.
+    /// before applying the aliases).
+    string_statistics: Arc<HashSet<String>>,
+}
+impl<'t> RadiatorClient<'t> {
+    pub fn new(config: &Config, transport: &'t dyn RadiatorTransport) -> Self {
+        let string_statistics = config.string_statistics();
+        let mut radiator_string_statistics: HashSet<String> = string_statistics.iter()
+            .filter(|s| !config.statistic_aliases.contains_key(**s))
+            .map(|s| (*s).to_owned())
+            .collect();
+        radiator_string_statistics.extend(
+            config.statistic_aliases.iter()
+                .filter(|(_statistic, alias)| string_statistics.contains(alias.as_str()))
+                .map(|(statistic, _alias)| statistic.clone())
+        );
+
+        Self {
+            transport,
+            value_units: config.radiator.value_units.clone(),
+            statistic_aliases: config.statistic_aliases.clone(),
+            command_template: config.radiator.command_template.clone(),
+            statistic_prefix: config.radiator.strip_statistic_prefix.clone(),
+            decode_offload_min_bytes: config.radiator.decode_offload_min_bytes,
+            string_statistics: Arc::new(radiator_string_statistics),
+            delimiters: Delimiters {
+                pair: config.radiator.stats_pair_delimiter,
+                key_value: config.radiator.stats_kv_delimiter,
+            },
+        }
+    }
+
+    /// Issues the given command, returning the command as Radiator would echo it along with the
+    /// response.
+    async fn query(&self, verb: &str, object: &str) -> Result<(String, Vec<u8>), Error> {
+        let command = format!("{} {}", verb, object);
+        let response = self.transport.communicate(command.as_bytes()).await?;
+        match error_response(&response) {
+            Some(Error::PermissionDenied) => {
+                *PERMISSION_DENIED
+                    .lock().expect("PERMISSION_DENIED poisoned")
+                    .entry(verb.to_owned())
+                    .or_insert(0) += 1;
+                Err(Error::PermissionDenied)
+            },
+            Some(e) => Err(e),
+            None => {
+                let sent_command = wrap_command(command.as_bytes(), self.command_template.as_deref());
+                Ok((String::from_utf8_lossy(&sent_command).into_owned(), response))
+            },
+        }
+    }
+
+    /// Decodes the response to a `STATS` command, handing large responses to a blocking thread
+    /// (`radiator.decode_offload_min_bytes`) so that the runtime keeps serving other connections
+    /// in the meantime.
+    async fn decode_stats(
+        &self,
+        response: Vec<u8>,
+        sent_command: String,
+        field_types: &HashMap<String, String>,
+    ) -> Option<Statistics> {
+        let offload = self.decode_offload_min_bytes
+            .is_some_and(|min_bytes| response.len() >= min_bytes);
+        if !offload {
+            return decode_stats(&response, &sent_command, field_types, &self.string_statistics, &self.value_units, self.delimiters, self.statistic_prefix.as_deref());
+        }
+
+        let field_types = field_types.clone();
+        let string_statistics = Arc::clone(&self.string_statistics);
+        let value_units = self.value_units.clone();
+        let delimiters = self.delimiters;
+        let statistic_prefix = self.statistic_prefix.clone();
+        let decoded = tokio::task::spawn_blocking(move || {
+            decode_stats(&response, &sent_command, &field_types, &string_statistics, &value_units, delimiters, statistic_prefix.as_deref())
+        }).await;
+        match decoded {
+            Ok(statistics) => statistics,
+            Err(e) => {
+                error!("decoding statistics failed: {}", e);
+                None
+            },
+        }
+    }
+
+    /// Obtains the statistics of the given object (e.g. `.` or `Handler.0`).
+    ///
+    /// `field_types` are the types of the object's fields as returned by [`describe`](Self::describe)
+    /// and are used to parse the values; they may be empty if unknown. Statistics feeding info
+    /// metrics are returned as strings. The returned statistics are already renamed according to
+    /// `radiator.strip_statistic_prefix` and the statistic aliases.
+    pub async fn stats(
+        &self,
+        object: &str,
+        field_types: &HashMap<String, String>,
+    ) -> Result<Statistics, Error> {
+        let (sent_command, response) = self.query("STATS", object).await?;
+        let statistics = self.decode_stats(response, sent_command, field_types).await
+            .ok_or(Error::UndecodableResponse)?;
+        Ok(Statistics {
+            numbers: apply_aliases(statistics.numbers, &self.statistic_aliases),
+            strings: apply_aliases(statistics.strings, &self.statistic_aliases),
+        })
+    }
+
+    /// Obtains the fields of the given object (e.g. `.` or `Handler.0`).
+    pub async fn describe(&self, object: &str) -> Result<Vec<Field>, Error> {
+        let (sent_command, response) = self.query("DESCRIBE", object).await?;
+        decode_description(&response, &sent_command, self.delimiters)
+            .ok_or(Error::UndecodableResponse)
+    }
+}
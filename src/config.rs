@@ -1,22 +1,163 @@
 use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
-use std::sync::OnceLock;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
 
+use indexmap::IndexMap;
+use regex::Regex;
+use tokio::sync::Semaphore;
 use serde::{Deserialize, Serialize};
 
-use crate::openmetrics::MetricKind;
+use crate::client::IDENTIFIER_FIELD;
+use crate::decode::ValueUnit;
+use crate::openmetrics::{family_name, LabelOrder, MetricKind, MIN_MAX_LABEL_VALUE_LENGTH, NewlineEscaping, SampleOrder};
 
 
-pub(crate) static CONFIG: OnceLock<Config> = OnceLock::new();
+pub(crate) const OPENMETRICS_COUNTER_SUFFIX: &str = "_total";
+
+/// The largest number of decimal places to which values may be rounded; an `f64` has no more
+/// significant decimal digits than this anyway.
+const MAX_PRECISION: usize = 17;
+
+
+pub(crate) static CONFIG: ConfigCell = ConfigCell::new();
+
+
+/// Holds the configuration, which is replaced as a whole when it is reloaded.
+///
+/// Each request, scrape or command obtains the configuration once and keeps using it until it is
+/// done, so that it never sees a mixture of the old and the new configuration.
+#[derive(Debug)]
+pub(crate) struct ConfigCell {
+    config: RwLock<Option<Arc<Config>>>,
+}
+impl ConfigCell {
+    const fn new() -> Self {
+        Self {
+            config: RwLock::new(None),
+        }
+    }
+
+    /// Returns the current configuration, or `None` if it has not been set yet.
+    pub fn get(&self) -> Option<Arc<Config>> {
+        self.config
+            .read().expect("CONFIG poisoned")
+            .clone()
+    }
+
+    /// Sets the initial configuration, returning it if a configuration has already been set.
+    pub fn set(&self, config: Config) -> Result<(), Arc<Config>> {
+        let config = Arc::new(config);
+        let mut config_guard = self.config
+            .write().expect("CONFIG poisoned");
+        if config_guard.is_some() {
+            return Err(config);
+        }
+        *config_guard = Some(config);
+        Ok(())
+    }
+
+    /// Replaces the configuration; users that have already obtained the previous one keep it.
+    pub fn replace(&self, config: Config) {
+        *self.config.write().expect("CONFIG poisoned") = Some(Arc::new(config));
+    }
+}
 
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub(crate) struct Config {
     pub www: WwwConfig,
     pub radiator: RadiatorConfig,
-    pub metrics: Vec<MetricConfig>,
+    #[serde(default)] pub metrics: Vec<MetricConfig>,
     #[serde(default)] pub per_object_metrics: Vec<PerObjectMetricConfig>,
+    #[serde(default)] pub exporter: ExporterConfig,
+    #[serde(default)] pub statistic_aliases: BTreeMap<String, String>,
+    #[serde(default)] pub info_metrics: Vec<InfoMetricConfig>,
+    #[serde(default)] pub scrape_groups: Vec<ScrapeGroupConfig>,
+    #[serde(default)] pub remote_write: Option<RemoteWriteConfig>,
+    #[serde(default)] pub pushgateway: Option<PushgatewayConfig>,
+}
+impl Config {
+    /// Returns the names of all statistics that are exported as labels of info metrics rather than
+    /// as numbers.
+    pub fn string_statistics(&self) -> HashSet<&str> {
+        self.info_metrics.iter()
+            .flat_map(|im| im.labels.values())
+            .map(|statistic| statistic.as_str())
+            .collect()
+    }
+
+    /// Whether each scrape queries the statistics of the server as a whole (`STATS .`).
+    ///
+    /// This is unnecessary if only per-object metrics without preconditions are configured. Without
+    /// any metrics at all, the top-level statistics are still queried to find out whether Radiator
+    /// is reachable.
+    pub fn queries_top_level_statistics(&self) -> bool {
+        !self.metrics.is_empty()
+            || !self.info_metrics.is_empty()
+            || self.per_object_metrics.is_empty()
+            || self.per_object_metrics.iter().any(|pom| pom.precondition.is_some())
+    }
+
+    /// Returns a copy of this configuration that only contains the metrics of the given scrape group
+    /// (`None` being the default group of metrics without `group`).
+    pub fn for_scrape_group(&self, group: Option<&str>) -> Config {
+        let mut group_config = self.clone();
+        group_config.metrics.retain(|m| m.group.as_deref() == group);
+        group_config.per_object_metrics.retain(|pom| pom.group.as_deref() == group);
+        group_config.info_metrics.retain(|im| im.group.as_deref() == group);
+        group_config
+    }
+
+    /// Returns the names of the settings that differ from the given configuration but are only
+    /// taken into account when the exporter starts, so that reloading cannot change them.
+    ///
+    /// Settings applied whenever a connection to Radiator is established (such as
+    /// `radiator.tcp_nodelay`) are not among them; they take effect from the next connection.
+    pub fn startup_settings_changed(&self, other: &Config) -> Vec<&'static str> {
+        let comparisons = [
+            ("www.bind_address", self.www.bind_address != other.www.bind_address),
+            ("www.port", self.www.port != other.www.port),
+            ("www.tls", self.www.tls != other.www.tls),
+            ("www.http_version", self.www.http_version != other.www.http_version),
+            ("www.http_idle_timeout_ms", self.www.http_idle_timeout_ms != other.www.http_idle_timeout_ms),
+            ("www.max_connections", self.www.max_connections != other.www.max_connections),
+            ("www.tcp_nodelay", self.www.tcp_nodelay != other.www.tcp_nodelay),
+            ("radiator.target", self.radiator.target != other.radiator.target),
+            ("radiator.mgmt_port", self.radiator.mgmt_port != other.radiator.mgmt_port),
+            ("radiator.connection_mode", self.radiator.connection_mode != other.radiator.connection_mode),
+            ("radiator.background_interval_ms", self.radiator.background_interval_ms != other.radiator.background_interval_ms),
+            ("radiator.hot_standby", self.radiator.hot_standby != other.radiator.hot_standby),
+            ("radiator.warmup", self.radiator.warmup != other.radiator.warmup),
+            ("radiator.idle_timeout_ms", self.radiator.idle_timeout_ms != other.radiator.idle_timeout_ms),
+            ("radiator.binary_mode", self.radiator.binary_mode != other.radiator.binary_mode),
+            ("radiator.response_delimiter", self.radiator.response_delimiter != other.radiator.response_delimiter),
+            ("radiator.log_prefix", self.radiator.log_prefix != other.radiator.log_prefix),
+            ("scrape_groups", self.scrape_groups != other.scrape_groups),
+            ("remote_write", self.remote_write != other.remote_write),
+            ("pushgateway", self.pushgateway != other.pushgateway),
+        ];
+        comparisons.into_iter()
+            .filter(|(_name, differs)| *differs)
+            .map(|(name, _differs)| name)
+            .collect()
+    }
+
+    /// Converts the names of all configured metrics to `snake_case` (`www.normalize_names`).
+    pub fn normalize_metric_names(&mut self) {
+        let metric_names = self.metrics.iter_mut()
+            .chain(self.per_object_metrics.iter_mut().flat_map(|pom| pom.metrics.iter_mut()))
+            .map(|m| &mut m.metric)
+            .chain(self.info_metrics.iter_mut().map(|im| &mut im.metric));
+        for metric_name in metric_names {
+            *metric_name = to_snake_case(metric_name);
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -26,10 +167,89 @@ pub(crate) struct WwwConfig {
 
     #[serde(default = "WwwConfig::default_port")]
     pub port: u16,
+
+    #[serde(default = "WwwConfig::default_counter_suffix")]
+    pub counter_suffix: String,
+
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+
+    #[serde(default = "WwwConfig::default_ready_max_scrape_age_ms")]
+    pub ready_max_scrape_age_ms: u64,
+
+    #[serde(default)]
+    pub http_idle_timeout_ms: Option<u64>,
+
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+
+    #[serde(default)]
+    pub tcp_nodelay: bool,
+
+    #[serde(default)]
+    pub normalize_names: bool,
+
+    #[serde(default)]
+    pub counter_values: CounterValues,
+
+    #[serde(default)]
+    pub emit_scrape_timestamp: bool,
+
+    #[serde(default)]
+    pub max_label_value_len: Option<usize>,
+
+    #[serde(default)]
+    pub enable_debug: bool,
+
+    #[serde(default)]
+    pub enable_reload: bool,
+
+    #[serde(default)]
+    pub output_banner: bool,
+
+    #[serde(default)]
+    pub help_newlines: NewlineEscaping,
+
+    #[serde(default)]
+    pub label_order: LabelOrder,
+
+    #[serde(default)]
+    pub audit_dir: Option<PathBuf>,
+
+    #[serde(default = "WwwConfig::default_audit_max_files")]
+    pub audit_max_files: usize,
+
+    #[serde(default)]
+    pub audit_max_age_ms: Option<u64>,
+
+    #[serde(default)]
+    pub http_version: HttpVersion,
+
+    #[serde(default)]
+    pub max_series_per_metric: Option<usize>,
 }
 impl WwwConfig {
     const fn default_bind_address() -> IpAddr { IpAddr::V6(Ipv6Addr::UNSPECIFIED) }
     const fn default_port() -> u16 { 10014 }
+    const fn default_ready_max_scrape_age_ms() -> u64 { 5 * 60 * 1000 }
+    const fn default_audit_max_files() -> usize { 1000 }
+    fn default_counter_suffix() -> String { OPENMETRICS_COUNTER_SUFFIX.to_owned() }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct TlsConfig {
+    pub certificate: PathBuf,
+
+    pub private_key: PathBuf,
+
+    #[serde(default)]
+    pub client_ca: Option<PathBuf>,
+
+    #[serde(default)]
+    pub healthcheck_certificate: Option<PathBuf>,
+
+    #[serde(default)]
+    pub healthcheck_private_key: Option<PathBuf>,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -42,9 +262,163 @@ pub(crate) struct RadiatorConfig {
     pub username: String,
 
     pub password: String,
+
+    #[serde(default = "RadiatorConfig::default_empty_stats_retries")]
+    pub empty_stats_retries: usize,
+
+    #[serde(default)]
+    pub max_commands_per_scrape: Option<usize>,
+
+    #[serde(default)]
+    pub background_interval_ms: Option<u64>,
+
+    #[serde(default)]
+    pub connection_mode: ConnectionMode,
+
+    #[serde(default)]
+    pub hot_standby: bool,
+
+    #[serde(default)]
+    pub warmup: bool,
+
+    #[serde(default)]
+    pub value_units: Vec<ValueUnit>,
+
+    #[serde(default)]
+    pub reconnect_jitter_ms: Option<u64>,
+
+    #[serde(default)]
+    pub min_reconnect_interval_ms: Option<u64>,
+
+    #[serde(default)]
+    pub command_template: Option<String>,
+
+    #[serde(default)]
+    pub identifier_cache_max: Option<usize>,
+
+    #[serde(default)]
+    pub breaker_failure_threshold: Option<u32>,
+
+    #[serde(default = "RadiatorConfig::default_breaker_cooldown_ms")]
+    pub breaker_cooldown_ms: u64,
+
+    #[serde(default = "RadiatorConfig::default_connect_timeout_ms")]
+    pub connect_timeout_ms: u64,
+
+    #[serde(default = "RadiatorConfig::default_login_timeout_ms")]
+    pub login_timeout_ms: u64,
+
+    #[serde(default = "RadiatorConfig::default_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+
+    #[serde(default)]
+    pub tcp_keepalive_secs: Option<u64>,
+
+    #[serde(default = "RadiatorConfig::default_binary_mode")]
+    pub binary_mode: bool,
+
+    #[serde(default = "RadiatorConfig::default_response_delimiter")]
+    pub response_delimiter: char,
+
+    #[serde(default = "RadiatorConfig::default_log_prefix")]
+    pub log_prefix: String,
+
+    #[serde(default = "RadiatorConfig::default_stats_pair_delimiter")]
+    pub stats_pair_delimiter: char,
+
+    #[serde(default = "RadiatorConfig::default_stats_kv_delimiter")]
+    pub stats_kv_delimiter: char,
+
+    #[serde(default = "RadiatorConfig::default_logout_command")]
+    pub logout_command: String,
+
+    #[serde(default)]
+    pub idle_timeout_ms: Option<u64>,
+
+    #[serde(default = "RadiatorConfig::default_max_retries")]
+    pub max_retries: u32,
+
+    #[serde(default)]
+    pub strip_statistic_prefix: Option<String>,
+
+    #[serde(default)]
+    pub decode_offload_min_bytes: Option<usize>,
 }
 impl RadiatorConfig {
     const fn default_target() -> IpAddr { IpAddr::V4(Ipv4Addr::LOCALHOST) }
+    const fn default_empty_stats_retries() -> usize { 1 }
+    const fn default_breaker_cooldown_ms() -> u64 { 30 * 1000 }
+    const fn default_connect_timeout_ms() -> u64 { 10 * 1000 }
+    const fn default_login_timeout_ms() -> u64 { 10 * 1000 }
+    const fn default_tcp_nodelay() -> bool { true }
+    const fn default_binary_mode() -> bool { true }
+    const fn default_response_delimiter() -> char { '\0' }
+    const fn default_stats_pair_delimiter() -> char { '\u{0001}' }
+    const fn default_stats_kv_delimiter() -> char { ':' }
+    fn default_log_prefix() -> String { "LOG ".to_owned() }
+    fn default_logout_command() -> String { "QUIT".to_owned() }
+    const fn default_max_retries() -> u32 { 2 }
+
+    /// Returns the byte terminating each response from Radiator.
+    ///
+    /// `config::check` ensures that it is an ASCII character.
+    pub fn response_delimiter_byte(&self) -> u8 {
+        u8::try_from(self.response_delimiter).expect("response delimiter is not a single byte")
+    }
+
+    /// Returns the bytes terminating each command sent to Radiator: a NUL byte in binary mode and a
+    /// line break otherwise.
+    pub const fn command_terminator(&self) -> &'static [u8] {
+        if self.binary_mode {
+            b"\0"
+        } else {
+            b"\r\n"
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct ExporterConfig {
+    #[serde(default = "ExporterConfig::default_instance_label")]
+    pub instance_label: String,
+
+    #[serde(default)]
+    pub instance: Option<String>,
+
+    #[serde(default)]
+    pub process_metrics: bool,
+}
+impl ExporterConfig {
+    fn default_instance_label() -> String { "exporter_instance".to_owned() }
+
+    /// The value of the instance label; falls back to the hostname if not configured.
+    pub fn instance_value(&self) -> String {
+        match self.instance.as_ref() {
+            Some(i) => i.clone(),
+            None => gethostname::gethostname().to_string_lossy().into_owned(),
+        }
+    }
+}
+impl Default for ExporterConfig {
+    fn default() -> Self {
+        Self {
+            instance_label: Self::default_instance_label(),
+            instance: None,
+            process_metrics: false,
+        }
+    }
+}
+
+/// When the exporter connects to Radiator.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ConnectionMode {
+    /// Once at startup, reconnecting whenever the connection is lost.
+    #[default]
+    Persistent,
+
+    /// Anew for every scrape, disconnecting afterwards.
+    PerScrape,
 }
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -53,116 +427,1076 @@ pub(crate) struct MetricConfig {
     pub kind: MetricKind,
     #[serde(default)] pub help: Option<String>,
     #[serde(default)] pub unit: Option<String>,
+    #[serde(default)] pub is_timestamp: bool,
+    #[serde(default)] pub when: Option<LabelFilter>,
+    #[serde(default)] pub sum_statistic: Option<String>,
+    #[serde(default)] pub precision: Option<usize>,
+    #[serde(default)] pub non_negative: bool,
+    #[serde(default)] pub on_negative: NegativeValueAction,
+    #[serde(default)] pub max_series: Option<usize>,
+    #[serde(default)] pub sort_samples: Option<SampleOrder>,
+    #[serde(default)] pub group: Option<String>,
     pub samples: Vec<SampleConfig>,
 }
+impl MetricConfig {
+    /// Returns the names of the statistics from which the samples of this metric are taken.
+    pub fn statistics(&self) -> impl Iterator<Item = &str> {
+        self.samples.iter()
+            .map(|sample| sample.statistic.as_str())
+            .chain(self.sum_statistic.as_deref())
+    }
+}
+
+/// What to do with a negative value of a metric declared as non-negative.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum NegativeValueAction {
+    /// Leave out the sample.
+    #[default]
+    Skip,
+
+    /// Output 0 instead.
+    Clamp,
+}
+
+/// How values of counters that are negative or not whole numbers are handled.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum CounterValues {
+    /// Output them unchanged.
+    #[default]
+    AsIs,
+
+    /// Round fractional values down and output negative values as 0.
+    Coerce,
+
+    /// Leave out the sample.
+    Reject,
+}
+
+/// Which HTTP versions the exporter's web server speaks.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum HttpVersion {
+    /// Only HTTP/1.1 (and 1.0).
+    Http1,
+
+    /// Only HTTP/2.
+    Http2,
+
+    /// HTTP/1.1 and HTTP/2, whichever the client uses.
+    #[default]
+    Both,
+}
+impl HttpVersion {
+    /// Returns the protocols to offer via ALPN in TLS handshakes, most preferred first.
+    pub fn alpn_protocols(&self) -> Vec<Vec<u8>> {
+        match self {
+            Self::Http1 => vec![b"http/1.1".to_vec()],
+            Self::Http2 => vec![b"h2".to_vec()],
+            Self::Both => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        }
+    }
+
+    pub const fn description(&self) -> &'static str {
+        match self {
+            Self::Http1 => "HTTP/1.1",
+            Self::Http2 => "HTTP/2",
+            Self::Both => "HTTP/1.1 and HTTP/2",
+        }
+    }
+}
+
+/// Restricts a metric to the samples whose value for the given label matches a pattern.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct LabelFilter {
+    pub label: String,
+    pub matches: Pattern,
+}
+impl LabelFilter {
+    /// Whether a sample with the given labels passes the filter. A missing label is treated like an
+    /// empty value.
+    pub fn is_match(&self, labels: &BTreeMap<String, String>) -> bool {
+        let value = labels.get(&self.label)
+            .map(|v| v.as_str())
+            .unwrap_or("");
+        self.matches.is_match(value)
+    }
+}
+
+/// Labels mapped to their values (or statistics), remembering the order in which they were declared.
+#[derive(Clone, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(from = "IndexMap<String, String>", into = "IndexMap<String, String>")]
+pub(crate) struct LabelMap {
+    map: BTreeMap<String, String>,
+    declared_order: Vec<String>,
+}
+impl LabelMap {
+    /// Returns the labels in the order in which they were declared.
+    pub fn declared_keys(&self) -> impl Iterator<Item = &str> {
+        self.declared_order.iter()
+            .map(|k| k.as_str())
+    }
+}
+impl Deref for LabelMap {
+    type Target = BTreeMap<String, String>;
+    fn deref(&self) -> &Self::Target { &self.map }
+}
+impl From<IndexMap<String, String>> for LabelMap {
+    fn from(value: IndexMap<String, String>) -> Self {
+        let declared_order = value.keys().cloned().collect();
+        Self {
+            map: value.into_iter().collect(),
+            declared_order,
+        }
+    }
+}
+impl From<LabelMap> for IndexMap<String, String> {
+    fn from(mut value: LabelMap) -> Self {
+        value.declared_order.into_iter()
+            .filter_map(|k| value.map.remove(&k).map(|v| (k, v)))
+            .collect()
+    }
+}
+
+/// A regular expression that must match the whole value.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub(crate) struct Pattern {
+    source: String,
+    regex: Regex,
+}
+impl Pattern {
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+impl TryFrom<String> for Pattern {
+    type Error = regex::Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        // anchor the expression, as Prometheus does
+        let regex = Regex::new(&format!("^(?:{})$", value))?;
+        Ok(Self {
+            source: value,
+            regex,
+        })
+    }
+}
+impl From<Pattern> for String {
+    fn from(value: Pattern) -> Self {
+        value.source
+    }
+}
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.source == other.source
+    }
+}
+impl Eq for Pattern {}
+impl PartialOrd for Pattern {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Pattern {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.source.cmp(&other.source)
+    }
+}
+impl Hash for Pattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.source.hash(state);
+    }
+}
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub(crate) struct PerObjectMetricConfig {
-    pub kind: String,
-    pub identifier_label: String,
+    #[serde(default)] pub kind: Option<String>,
+    #[serde(default)] pub kinds: Vec<String>,
+    #[serde(default)] pub identifier_label: Option<String>,
+    #[serde(default)] pub identifier_fields: BTreeMap<String, String>,
+    #[serde(default)] pub kind_label: Option<String>,
+    #[serde(default)] pub gap_tolerance: usize,
+    #[serde(default)] pub object_keys: Option<Vec<String>>,
+    #[serde(default)] pub stats_every_scrapes: Option<usize>,
+    #[serde(default)] pub max_objects: Option<usize>,
+    #[serde(default)] pub object_selection: ObjectSelection,
+    #[serde(default)] pub precondition: Option<WalkPrecondition>,
+    #[serde(default)] pub group: Option<String>,
     pub metrics: Vec<MetricConfig>,
 }
+impl PerObjectMetricConfig {
+    /// Returns all object kinds to which these metrics apply.
+    pub fn all_kinds(&self) -> impl Iterator<Item = &str> {
+        self.kind.iter()
+            .chain(self.kinds.iter())
+            .map(|k| k.as_str())
+    }
+
+    /// Returns the labels identifying each object, mapped to the fields from which their values are
+    /// taken.
+    pub fn identifier_labels(&self) -> BTreeMap<&str, &str> {
+        let mut labels: BTreeMap<&str, &str> = self.identifier_fields.iter()
+            .map(|(label, field)| (label.as_str(), field.as_str()))
+            .collect();
+        if let Some(identifier_label) = self.identifier_label.as_ref() {
+            labels.insert(identifier_label.as_str(), IDENTIFIER_FIELD);
+        }
+        labels
+    }
+
+    /// Returns the name under which Radiator knows the object of the given kind at the given index
+    /// (e.g. `Handler.0`), or at the given position of `object_keys` if the objects are named.
+    pub fn object_name(&self, kind: &str, index: usize) -> String {
+        match self.object_keys.as_ref() {
+            Some(object_keys) => format!("{}.{}", kind, object_keys[index]),
+            None => format!("{}.{}", kind, index),
+        }
+    }
+
+    /// Substitutes `{kind}` and `{identifier_label}` in a per-object metric's help or unit template.
+    ///
+    /// If objects are identified by multiple labels, `{identifier_label}` becomes a comma-separated
+    /// list of them.
+    pub fn render_template(&self, template: &str) -> String {
+        template
+            .replace("{kind}", &self.all_kinds().collect::<Vec<_>>().join(", "))
+            .replace("{identifier_label}", &self.identifier_labels().into_keys().collect::<Vec<_>>().join(", "))
+    }
+}
+
+/// Which objects of a kind are queried if there are more than `max_objects` of them.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum ObjectSelection {
+    /// The objects with the lowest indexes.
+    #[default]
+    FirstN,
+
+    /// The objects with the lowest hashes of their identifiers, which remain selected even as other
+    /// objects come and go.
+    StableSample,
+}
+
+/// A top-level statistic that must reach a threshold for the objects of a kind to be queried.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct WalkPrecondition {
+    pub statistic: String,
+    #[serde(default = "WalkPrecondition::default_min")] pub min: i64,
+}
+impl WalkPrecondition {
+    const fn default_min() -> i64 { 1 }
+}
+
+/// An info metric whose labels are taken from string-valued statistics of the server as a whole.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct InfoMetricConfig {
+    pub metric: String,
+    #[serde(default)] pub help: Option<String>,
+    #[serde(default)] pub group: Option<String>,
+    pub labels: LabelMap,
+}
+
+/// A set of metrics that is scraped in the background at its own interval.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct ScrapeGroupConfig {
+    pub name: String,
+    pub interval_ms: u64,
+}
+
+/// Where to push the metrics using the Prometheus remote-write protocol.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct RemoteWriteConfig {
+    pub url: String,
+
+    #[serde(default = "RemoteWriteConfig::default_interval_ms")]
+    pub interval_ms: u64,
+
+    #[serde(default = "RemoteWriteConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+}
+impl RemoteWriteConfig {
+    const fn default_interval_ms() -> u64 { 60 * 1000 }
+    const fn default_timeout_ms() -> u64 { 30 * 1000 }
+}
+
+/// Where to push the metrics to a Prometheus Pushgateway.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) struct PushgatewayConfig {
+    pub url: String,
+
+    pub job: String,
+
+    #[serde(default)]
+    pub grouping_labels: BTreeMap<String, String>,
+
+    #[serde(default = "PushgatewayConfig::default_interval_ms")]
+    pub interval_ms: u64,
+
+    #[serde(default = "PushgatewayConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+
+    #[serde(default = "PushgatewayConfig::default_retries")]
+    pub retries: u32,
+
+    #[serde(default = "PushgatewayConfig::default_retry_interval_ms")]
+    pub retry_interval_ms: u64,
+
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub password: Option<String>,
+
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+
+    #[serde(default)]
+    pub ca_certificate: Option<PathBuf>,
+}
+impl PushgatewayConfig {
+    const fn default_interval_ms() -> u64 { 60 * 1000 }
+    const fn default_timeout_ms() -> u64 { 30 * 1000 }
+    const fn default_retries() -> u32 { 3 }
+    const fn default_retry_interval_ms() -> u64 { 5 * 1000 }
+}
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
 pub(crate) struct SampleConfig {
-    #[serde(default)] pub labels: BTreeMap<String, String>,
+    #[serde(default)] pub labels: LabelMap,
     pub statistic: String,
+    #[serde(default)] pub le: Option<String>,
+    #[serde(default)] pub default_value: Option<i64>,
 }
 
 
-pub(crate) fn check(config: &Config) -> Result<(), Cow<'static, str>> {
-    if config.radiator.username.contains(' ') || config.radiator.username.contains('\0') {
-        return Err(Cow::Borrowed("radiator.username must not contain spaces"));
+/// Converts a `CamelCase` name to `snake_case`, e.g. `AccessRequests` to `access_requests` and
+/// `radiator_EAPTimeouts` to `radiator_eap_timeouts`.
+///
+/// Underscores are inserted where a lowercase letter or digit is followed by an uppercase letter and
+/// before the last uppercase letter of a run that is followed by a lowercase letter.
+pub(crate) fn to_snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            let previous = chars[i-1];
+            let next_is_lowercase = chars.get(i+1).is_some_and(|n| n.is_ascii_lowercase());
+            let is_boundary = previous.is_ascii_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_ascii_uppercase() && next_is_lowercase);
+            if is_boundary {
+                snake.push('_');
+            }
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+
+/// Replaces `${VAR}` and `${VAR:-default}` in the raw configuration text by the value of the
+/// environment variable `VAR` (or `default` if it is unset or empty). `$${` stands for a literal
+/// `${`.
+pub(crate) fn interpolate_env(raw: &str) -> Result<String, Cow<'static, str>> {
+    let mut interpolated = String::with_capacity(raw.len());
+    let mut rest = raw;
+    while let Some(dollar_index) = rest.find('$') {
+        interpolated.push_str(&rest[..dollar_index]);
+        rest = &rest[dollar_index..];
+
+        if let Some(after_escape) = rest.strip_prefix("$${") {
+            interpolated.push_str("${");
+            rest = after_escape;
+            continue;
+        }
+        let Some(after_opening) = rest.strip_prefix("${") else {
+            // a lone dollar sign
+            interpolated.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let Some(closing_index) = after_opening.find('}') else {
+            return Err(Cow::Borrowed("unterminated ${ in configuration (write $${ for a literal ${)"));
+        };
+        let expression = &after_opening[..closing_index];
+        rest = &after_opening[closing_index+1..];
+
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        let name_is_valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !name_is_valid {
+            return Err(Cow::Owned(format!("invalid environment variable name {:?} in configuration", name)));
+        }
+
+        let value = std::env::var(name).ok()
+            .filter(|v| !v.is_empty());
+        match (value, default) {
+            (Some(v), _) => interpolated.push_str(&v),
+            (None, Some(d)) => interpolated.push_str(d),
+            (None, None) => return Err(Cow::Owned(format!("environment variable {} referenced in configuration is not set and has no default", name))),
+        }
+    }
+    interpolated.push_str(rest);
+    Ok(interpolated)
+}
+
+
+/// A problem with the configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct Problem {
+    /// The setting the problem concerns, such as `metrics[3].samples[0].le`, or `None` if the
+    /// configuration could not be read at all.
+    pub path: Option<String>,
+
+    /// The description of the problem, which usually mentions the path.
+    pub message: Cow<'static, str>,
+}
+impl Problem {
+    /// Creates a problem that does not concern a specific setting.
+    pub fn general<M: Into<Cow<'static, str>>>(message: M) -> Self {
+        Self {
+            path: None,
+            message: message.into(),
+        }
+    }
+}
+
+
+/// All the problems found with the configuration.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+#[serde(transparent)]
+pub(crate) struct Problems(Vec<Problem>);
+impl Problems {
+    /// Adds a problem reported by `check` concerning the setting at the given path.
+    fn push<P: Into<String>>(&mut self, path: P, message: Cow<'static, str>) {
+        self.0.push(Problem {
+            path: Some(path.into()),
+            message,
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Problem> {
+        self.0.iter()
+    }
+}
+impl From<Problem> for Problems {
+    fn from(problem: Problem) -> Self {
+        Self(vec![problem])
+    }
+}
+impl fmt::Display for Problems {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, problem) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", problem.message)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Parses the given contents of a configuration file, substituting environment variables,
+/// normalizing metric names (if requested) and checking the result.
+pub(crate) fn parse(config_string: &str) -> Result<Config, Problems> {
+    let config_string = interpolate_env(config_string)
+        .map_err(Problem::general)?;
+    let mut config: Config = toml::from_str(&config_string)
+        .map_err(|e| Problem::general(format!("failed to parse config file: {}", e)))?;
+    if config.www.normalize_names {
+        // before checking, so that the resulting names are validated
+        config.normalize_metric_names();
+    }
+    check(&config)?;
+    Ok(config)
+}
+
+
+pub(crate) fn check(config: &Config) -> Result<(), Problems> {
+    let mut problems = Problems::default();
+
+    if config.radiator.username.contains(' ') {
+        problems.push("radiator.username", Cow::Borrowed("radiator.username must not contain spaces"));
     }
     if config.radiator.username.contains('\0') {
-        return Err(Cow::Borrowed("radiator.username must not contain NUL characters"));
+        problems.push("radiator.username", Cow::Borrowed("radiator.username must not contain NUL characters"));
     }
     if config.radiator.password.contains(' ') {
-        return Err(Cow::Borrowed("radiator.password must not contain spaces"));
+        problems.push("radiator.password", Cow::Borrowed("radiator.password must not contain spaces"));
     }
     if config.radiator.password.contains('\0') {
-        return Err(Cow::Borrowed("radiator.password must not contain NUL characters"));
+        problems.push("radiator.password", Cow::Borrowed("radiator.password must not contain NUL characters"));
+    }
+    if !config.radiator.binary_mode {
+        // responses are lines, and so are commands (including the login)
+        if config.radiator.response_delimiter != '\n' {
+            problems.push("radiator.binary_mode", Cow::Borrowed("radiator.binary_mode = false requires radiator.response_delimiter = \"\\n\""));
+        }
+        if config.radiator.username.contains(['\r', '\n']) || config.radiator.password.contains(['\r', '\n']) {
+            let path = if config.radiator.username.contains(['\r', '\n']) { "radiator.username" } else { "radiator.password" };
+            problems.push(path, Cow::Borrowed("radiator.username and radiator.password must not contain line breaks if radiator.binary_mode = false"));
+        }
+    }
+
+    if let Some(command_template) = config.radiator.command_template.as_ref() {
+        if command_template.matches("{command}").count() != 1 {
+            problems.push("radiator.command_template", Cow::Borrowed("radiator.command_template must contain {command} exactly once"));
+        }
+        if command_template.contains('\0') {
+            problems.push("radiator.command_template", Cow::Borrowed("radiator.command_template must not contain NUL characters"));
+        }
+        if command_template.contains('\n') {
+            // Radiator echoes the command, followed by a newline, before the response
+            problems.push("radiator.command_template", Cow::Borrowed("radiator.command_template must not contain newlines"));
+        }
+    }
+
+    if config.radiator.strip_statistic_prefix.as_deref() == Some("") {
+        problems.push("radiator.strip_statistic_prefix", Cow::Borrowed("radiator.strip_statistic_prefix must not be empty if set"));
+    }
+
+    if config.radiator.logout_command.contains(['\0', '\r', '\n']) {
+        problems.push("radiator.logout_command", Cow::Borrowed("radiator.logout_command must not contain NUL characters or line breaks"));
+    }
+
+    if config.www.max_label_value_len.is_some_and(|mlvl| mlvl < MIN_MAX_LABEL_VALUE_LENGTH) {
+        problems.push("www.max_label_value_len", Cow::Owned(format!("www.max_label_value_len must be at least {} if set", MIN_MAX_LABEL_VALUE_LENGTH)));
+    }
+    if config.radiator.connect_timeout_ms == 0 {
+        problems.push("radiator.connect_timeout_ms", Cow::Borrowed("radiator.connect_timeout_ms must be at least 1"));
+    }
+    if config.radiator.login_timeout_ms == 0 {
+        problems.push("radiator.login_timeout_ms", Cow::Borrowed("radiator.login_timeout_ms must be at least 1"));
+    }
+    if let Some(tls) = config.www.tls.as_ref() {
+        if tls.healthcheck_certificate.is_some() != tls.healthcheck_private_key.is_some() {
+            let path = if tls.healthcheck_certificate.is_some() { "www.tls.healthcheck_private_key" } else { "www.tls.healthcheck_certificate" };
+            problems.push(path, Cow::Borrowed("www.tls.healthcheck_certificate and www.tls.healthcheck_private_key must be set together"));
+        }
+    }
+    if config.www.audit_max_files == 0 {
+        problems.push("www.audit_max_files", Cow::Borrowed("www.audit_max_files must be at least 1"));
+    }
+    if config.www.audit_max_age_ms == Some(0) {
+        problems.push("www.audit_max_age_ms", Cow::Borrowed("www.audit_max_age_ms must be at least 1 if set"));
+    }
+    if config.www.max_series_per_metric == Some(0) {
+        problems.push("www.max_series_per_metric", Cow::Borrowed("www.max_series_per_metric must be at least 1 if set"));
+    }
+    if config.www.http_idle_timeout_ms == Some(0) {
+        problems.push("www.http_idle_timeout_ms", Cow::Borrowed("www.http_idle_timeout_ms must be at least 1 if set"));
+    }
+    if config.www.max_connections == Some(0) {
+        problems.push("www.max_connections", Cow::Borrowed("www.max_connections must be at least 1 if set"));
+    }
+    if config.www.max_connections.is_some_and(|mc| mc > Semaphore::MAX_PERMITS) {
+        problems.push("www.max_connections", Cow::Owned(format!("www.max_connections must be at most {}", Semaphore::MAX_PERMITS)));
+    }
+
+    if config.radiator.max_commands_per_scrape == Some(0) {
+        problems.push("radiator.max_commands_per_scrape", Cow::Borrowed("radiator.max_commands_per_scrape must be at least 1"));
+    }
+
+    if config.radiator.stats_pair_delimiter == config.radiator.stats_kv_delimiter {
+        problems.push("radiator.stats_pair_delimiter", Cow::Borrowed("radiator.stats_pair_delimiter and radiator.stats_kv_delimiter must differ"));
+    }
+    if !config.radiator.response_delimiter.is_ascii() {
+        problems.push("radiator.response_delimiter", Cow::Borrowed("radiator.response_delimiter must be a single-byte (ASCII) character"));
+    }
+    for (delimiter_name, delimiter) in [("stats_pair_delimiter", config.radiator.stats_pair_delimiter), ("stats_kv_delimiter", config.radiator.stats_kv_delimiter)] {
+        // the response delimiter terminates messages and the first newline ends the echoed command
+        if delimiter == config.radiator.response_delimiter || delimiter == '\n' {
+            problems.push(format!("radiator.{}", delimiter_name), Cow::Owned(format!("radiator.{} must differ from radiator.response_delimiter and must not be a newline", delimiter_name)));
+        }
+    }
+    if config.radiator.breaker_failure_threshold == Some(0) {
+        problems.push("radiator.breaker_failure_threshold", Cow::Borrowed("radiator.breaker_failure_threshold must be at least 1 if set"));
+    }
+    if config.radiator.identifier_cache_max == Some(0) {
+        problems.push("radiator.identifier_cache_max", Cow::Borrowed("radiator.identifier_cache_max must be at least 1 if set"));
+    }
+    if config.radiator.hot_standby && config.radiator.connection_mode != ConnectionMode::Persistent {
+        problems.push("radiator.hot_standby", Cow::Borrowed("radiator.hot_standby requires radiator.connection_mode = \"persistent\""));
+    }
+    if config.radiator.tcp_keepalive_secs == Some(0) {
+        problems.push("radiator.tcp_keepalive_secs", Cow::Borrowed("radiator.tcp_keepalive_secs must be at least 1 if set"));
+    }
+    if let Some(idle_timeout_ms) = config.radiator.idle_timeout_ms {
+        if idle_timeout_ms == 0 {
+            problems.push("radiator.idle_timeout_ms", Cow::Borrowed("radiator.idle_timeout_ms must be at least 1 if set"));
+        }
+        if config.radiator.connection_mode != ConnectionMode::Persistent {
+            problems.push("radiator.idle_timeout_ms", Cow::Borrowed("radiator.idle_timeout_ms requires radiator.connection_mode = \"persistent\""));
+        }
+        // these keep the connection open, which is the opposite of what closing it when idle achieves
+        if config.radiator.tcp_keepalive_secs.is_some() {
+            problems.push("radiator.idle_timeout_ms", Cow::Borrowed("radiator.idle_timeout_ms and radiator.tcp_keepalive_secs are mutually exclusive"));
+        }
+        if config.radiator.hot_standby {
+            problems.push("radiator.idle_timeout_ms", Cow::Borrowed("radiator.idle_timeout_ms and radiator.hot_standby are mutually exclusive"));
+        }
+    }
+    if config.radiator.background_interval_ms == Some(0) {
+        problems.push("radiator.background_interval_ms", Cow::Borrowed("radiator.background_interval_ms must be at least 1"));
+    }
+
+    if !config.scrape_groups.is_empty() && config.radiator.background_interval_ms.is_none() {
+        problems.push("radiator.background_interval_ms", Cow::Borrowed("scrape_groups require radiator.background_interval_ms"));
+    }
+    let mut scrape_group_names = HashSet::new();
+    for (i, scrape_group) in config.scrape_groups.iter().enumerate() {
+        if scrape_group.name.is_empty() {
+            problems.push(format!("scrape_groups[{}].name", i), Cow::Owned(format!("scrape_groups[{}].name must not be empty", i)));
+        }
+        if !scrape_group_names.insert(scrape_group.name.as_str()) {
+            problems.push(format!("scrape_groups[{}].name", i), Cow::Owned(format!("scrape_groups[{}].name {:?} is not unique", i, scrape_group.name)));
+        }
+        if scrape_group.interval_ms == 0 {
+            problems.push(format!("scrape_groups[{}].interval_ms", i), Cow::Owned(format!("scrape_groups[{}].interval_ms must be at least 1", i)));
+        }
+    }
+    let check_group = |group: &Option<String>, base: &str, problems: &mut Problems| {
+        if let Some(g) = group {
+            if !scrape_group_names.contains(g.as_str()) {
+                problems.push(format!("{}.group", base), Cow::Owned(format!("{}.group {:?} is not defined in scrape_groups", base, g)));
+            }
+        }
+    };
+    for (i, metric) in config.metrics.iter().enumerate() {
+        check_group(&metric.group, &format!("metrics[{}]", i), &mut problems);
+    }
+    for (i, per_object_metric) in config.per_object_metrics.iter().enumerate() {
+        check_group(&per_object_metric.group, &format!("per_object_metrics[{}]", i), &mut problems);
+        for (j, metric) in per_object_metric.metrics.iter().enumerate() {
+            if metric.group.is_some() {
+                problems.push(format!("per_object_metrics[{}].metrics[{}].group", i, j), Cow::Owned(format!("per_object_metrics[{}].metrics[{}].group is not allowed; set group on per_object_metrics[{}] instead", i, j, i)));
+            }
+        }
+    }
+    for (i, info_metric) in config.info_metrics.iter().enumerate() {
+        check_group(&info_metric.group, &format!("info_metrics[{}]", i), &mut problems);
+    }
+
+    if let Some(remote_write) = config.remote_write.as_ref() {
+        if let Err(problem) = crate::push::check_url(&remote_write.url) {
+            problems.push("remote_write.url", Cow::Owned(format!("remote_write.url {}", problem)));
+        }
+        if remote_write.interval_ms == 0 {
+            problems.push("remote_write.interval_ms", Cow::Borrowed("remote_write.interval_ms must be at least 1"));
+        }
+        if remote_write.timeout_ms == 0 {
+            problems.push("remote_write.timeout_ms", Cow::Borrowed("remote_write.timeout_ms must be at least 1"));
+        }
+        check_push_credentials("remote_write", &remote_write.username, &remote_write.password, &remote_write.bearer_token, &mut problems);
+    }
+    if let Some(pushgateway) = config.pushgateway.as_ref() {
+        match crate::push::check_url(&pushgateway.url) {
+            Ok(uri) => {
+                // the grouping key is appended to the path
+                if uri.query().is_some() {
+                    problems.push("pushgateway.url", Cow::Borrowed("pushgateway.url must not contain a query"));
+                }
+            },
+            Err(problem) => problems.push("pushgateway.url", Cow::Owned(format!("pushgateway.url {}", problem))),
+        }
+        if pushgateway.job.is_empty() {
+            problems.push("pushgateway.job", Cow::Borrowed("pushgateway.job must not be empty"));
+        }
+        for name in pushgateway.grouping_labels.keys() {
+            if !is_valid_label_name(name) {
+                problems.push(format!("pushgateway.grouping_labels[{:?}]", name), Cow::Owned(format!("pushgateway.grouping_labels[{:?}] key must start with an ASCII letter or an underscore and consist only of ASCII letters, ASCII digits and underscores", name)));
+            }
+            if name == "job" {
+                problems.push("pushgateway.grouping_labels", Cow::Borrowed("pushgateway.grouping_labels must not contain \"job\"; set pushgateway.job instead"));
+            }
+        }
+        if pushgateway.interval_ms == 0 {
+            problems.push("pushgateway.interval_ms", Cow::Borrowed("pushgateway.interval_ms must be at least 1"));
+        }
+        if pushgateway.timeout_ms == 0 {
+            problems.push("pushgateway.timeout_ms", Cow::Borrowed("pushgateway.timeout_ms must be at least 1"));
+        }
+        check_push_credentials("pushgateway", &pushgateway.username, &pushgateway.password, &pushgateway.bearer_token, &mut problems);
+        if config.www.emit_scrape_timestamp {
+            problems.push("www.emit_scrape_timestamp", Cow::Borrowed("pushgateway requires www.emit_scrape_timestamp = false, as the Pushgateway rejects samples with timestamps"));
+        }
+    }
+
+    let counter_suffix_is_valid = config.www.counter_suffix.chars()
+        .all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_' || c == ':');
+    if !counter_suffix_is_valid {
+        problems.push("www.counter_suffix", Cow::Borrowed("www.counter_suffix must consist only of ASCII letters, ASCII digits, underscores and colons"));
+    }
+
+    if !is_valid_label_name(&config.exporter.instance_label) {
+        problems.push("exporter.instance_label", Cow::Borrowed("exporter.instance_label must start with an ASCII letter or an underscore and consist only of ASCII letters, ASCII digits and underscores"));
+    }
+
+    let mut alias_targets = HashSet::new();
+    for (statistic, alias) in &config.statistic_aliases {
+        if statistic.is_empty() || alias.is_empty() {
+            problems.push(format!("statistic_aliases[{:?}]", statistic), Cow::Borrowed("statistic_aliases must not contain empty statistic names"));
+        }
+        if !alias_targets.insert(alias) {
+            problems.push(format!("statistic_aliases[{:?}]", statistic), Cow::Owned(format!("statistic_aliases maps more than one statistic to {:?}", alias)));
+        }
     }
 
     let mut known_metrics = HashSet::new();
 
     for (i, metric) in config.metrics.iter().enumerate() {
         let base = format!("metrics[{}]", i);
-        check_metric(metric, &base, &mut known_metrics)?;
+        check_metric(metric, &base, None, &config.www.counter_suffix, &mut known_metrics, &mut problems);
     }
 
     let mut known_objects = HashSet::new();
 
     for (i, per_object_metric) in config.per_object_metrics.iter().enumerate() {
-        if !known_objects.insert(&per_object_metric.kind) {
-            return Err(Cow::Owned(format!("per_object_metrics[{}].kind is not unique", i)));
+        if per_object_metric.all_kinds().next().is_none() {
+            problems.push(format!("per_object_metrics[{}]", i), Cow::Owned(format!("per_object_metrics[{}] must specify kind or kinds", i)));
+        }
+        let kind_paths = per_object_metric.kind.iter()
+            .map(|kind| (format!("per_object_metrics[{}].kind", i), kind))
+            .chain(per_object_metric.kinds.iter().enumerate().map(|(k, kind)| (format!("per_object_metrics[{}].kinds[{}]", i, k), kind)));
+        for (kind_path, kind) in kind_paths {
+            if kind.is_empty() || kind.contains([' ', '.', '\0', '\r', '\n']) {
+                problems.push(kind_path.clone(), Cow::Owned(format!("per_object_metrics[{}] kind {:?} must be non-empty and must not contain spaces, periods, newlines or NUL characters", i, kind)));
+            }
+            if !known_objects.insert(kind.as_str()) {
+                problems.push(kind_path, Cow::Owned(format!("per_object_metrics[{}] kind {:?} is not unique", i, kind)));
+            }
+        }
+
+        if let Some(object_keys) = per_object_metric.object_keys.as_ref() {
+            if object_keys.is_empty() {
+                problems.push(format!("per_object_metrics[{}].object_keys", i), Cow::Owned(format!("per_object_metrics[{}].object_keys must not be empty if set", i)));
+            }
+            let mut known_keys = HashSet::new();
+            for (k, key) in object_keys.iter().enumerate() {
+                if key.is_empty() || key.contains([' ', '.', '\0', '\r', '\n']) {
+                    problems.push(format!("per_object_metrics[{}].object_keys[{}]", i, k), Cow::Owned(format!("per_object_metrics[{}] object key {:?} must be non-empty and must not contain spaces, periods, newlines or NUL characters", i, key)));
+                }
+                if !known_keys.insert(key) {
+                    problems.push(format!("per_object_metrics[{}].object_keys[{}]", i, k), Cow::Owned(format!("per_object_metrics[{}] object key {:?} is not unique", i, key)));
+                }
+            }
+            if per_object_metric.gap_tolerance > 0 {
+                problems.push(format!("per_object_metrics[{}].gap_tolerance", i), Cow::Owned(format!("per_object_metrics[{}].gap_tolerance is not allowed with object_keys", i)));
+            }
+        }
+
+        if per_object_metric.identifier_label.is_none() && per_object_metric.identifier_fields.is_empty() {
+            problems.push(format!("per_object_metrics[{}]", i), Cow::Owned(format!("per_object_metrics[{}] must specify identifier_label or identifier_fields", i)));
+        }
+        if let Some(identifier_label) = per_object_metric.identifier_label.as_ref() {
+            if per_object_metric.identifier_fields.contains_key(identifier_label) {
+                problems.push(format!("per_object_metrics[{}].identifier_fields", i), Cow::Owned(format!("per_object_metrics[{}].identifier_fields must not contain the identifier_label {:?}", i, identifier_label)));
+            }
+        }
+        for (label, field) in per_object_metric.identifier_labels() {
+            if !is_valid_label_name(label) {
+                let label_path = if per_object_metric.identifier_label.as_deref() == Some(label) {
+                    format!("per_object_metrics[{}].identifier_label", i)
+                } else {
+                    format!("per_object_metrics[{}].identifier_fields[{:?}]", i, label)
+                };
+                problems.push(label_path, Cow::Owned(format!("per_object_metrics[{}] identifier label {:?} must start with an ASCII letter or an underscore and consist only of ASCII letters, ASCII digits and underscores", i, label)));
+            }
+            if field.is_empty() {
+                problems.push(format!("per_object_metrics[{}].identifier_fields[{:?}]", i, label), Cow::Owned(format!("per_object_metrics[{}].identifier_fields[{:?}] must not be empty", i, label)));
+            }
+        }
+        if let Some(kind_label) = per_object_metric.kind_label.as_ref() {
+            if !is_valid_label_name(kind_label) {
+                problems.push(format!("per_object_metrics[{}].kind_label", i), Cow::Owned(format!("per_object_metrics[{}].kind_label must start with an ASCII letter or an underscore and consist only of ASCII letters, ASCII digits and underscores", i)));
+            }
+            if per_object_metric.identifier_labels().contains_key(kind_label.as_str()) {
+                problems.push(format!("per_object_metrics[{}].kind_label", i), Cow::Owned(format!("per_object_metrics[{}].kind_label {:?} must differ from the identifier labels", i, kind_label)));
+            }
+            for (j, metric) in per_object_metric.metrics.iter().enumerate() {
+                if let Some(k) = metric.samples.iter().position(|s| s.labels.contains_key(kind_label)) {
+                    problems.push(format!("per_object_metrics[{}].metrics[{}].samples[{}].labels[{:?}]", i, j, k, kind_label), Cow::Owned(format!("per_object_metrics[{}].metrics[{}].samples[{}].labels must not contain the kind_label {:?}", i, j, k, kind_label)));
+                }
+            }
+        }
+        if per_object_metric.stats_every_scrapes == Some(0) {
+            problems.push(format!("per_object_metrics[{}].stats_every_scrapes", i), Cow::Owned(format!("per_object_metrics[{}].stats_every_scrapes must be at least 1 if set", i)));
+        }
+        if per_object_metric.max_objects == Some(0) {
+            problems.push(format!("per_object_metrics[{}].max_objects", i), Cow::Owned(format!("per_object_metrics[{}].max_objects must be at least 1 if set", i)));
+        }
+        if per_object_metric.max_objects.is_none() && per_object_metric.object_selection != ObjectSelection::FirstN {
+            problems.push(format!("per_object_metrics[{}].object_selection", i), Cow::Owned(format!("per_object_metrics[{}].object_selection requires max_objects", i)));
+        }
+        if let Some(precondition) = per_object_metric.precondition.as_ref() {
+            if precondition.statistic.is_empty() {
+                problems.push(format!("per_object_metrics[{}].precondition.statistic", i), Cow::Owned(format!("per_object_metrics[{}].precondition.statistic must not be empty", i)));
+            }
         }
 
         for (j, metric) in per_object_metric.metrics.iter().enumerate() {
             let base = format!("per_object_metrics[{}].metrics[{}]", i, j);
-            check_metric(metric, &base, &mut known_metrics)?;
+            check_metric(metric, &base, Some(per_object_metric), &config.www.counter_suffix, &mut known_metrics, &mut problems);
+        }
+    }
+
+    for (i, info_metric) in config.info_metrics.iter().enumerate() {
+        let base = format!("info_metrics[{}]", i);
+        if !known_metrics.insert(family_name(&info_metric.metric, MetricKind::Info, &config.www.counter_suffix)) {
+            problems.push(format!("{}.metric", base), Cow::Owned(format!("{}.metric is not unique", base)));
+        }
+        check_metric_name(&info_metric.metric, &base, &mut problems);
+        if info_metric.help.as_ref().is_some_and(|h| h.is_empty()) {
+            problems.push(format!("{}.help", base), Cow::Owned(format!("{}.help must not be empty if set", base)));
+        }
+        if info_metric.labels.is_empty() {
+            problems.push(format!("{}.labels", base), Cow::Owned(format!("{}.labels must contain at least one label", base)));
+        }
+        for (label, statistic) in info_metric.labels.iter() {
+            if !is_valid_label_name(label) {
+                problems.push(format!("{}.labels[{:?}]", base, label), Cow::Owned(format!("{}.labels[{:?}] key must start with an ASCII letter or an underscore and consist only of ASCII letters, ASCII digits and underscores", base, label)));
+            }
+            if statistic.is_empty() {
+                problems.push(format!("{}.labels[{:?}]", base, label), Cow::Owned(format!("{}.labels[{:?}] must not be empty", base, label)));
+            }
         }
     }
 
-    Ok(())
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(problems)
+    }
 }
 
-fn check_metric<'a>(metric: &'a MetricConfig, base: &str, known_metrics: &mut HashSet<&'a String>) -> Result<(), Cow<'static, str>> {
-    if !known_metrics.insert(&metric.metric) {
-        return Err(Cow::Owned(format!("{}.metric is not unique", base)));
+fn check_gauge_histogram(metric: &MetricConfig, base: &str, problems: &mut Problems) {
+    let Some(first_sample) = metric.samples.first() else {
+        problems.push(format!("{}.samples", base), Cow::Owned(format!("{}.samples must contain at least one bucket", base)));
+        return;
+    };
+
+    let mut previous_upper_bound = f64::NEG_INFINITY;
+    for (j, sample) in metric.samples.iter().enumerate() {
+        let Some(le) = sample.le.as_ref() else {
+            problems.push(format!("{}.samples[{}].le", base, j), Cow::Owned(format!("{}.samples[{}].le must be set for kind \"gauge_histogram\"", base, j)));
+            continue;
+        };
+        let upper_bound: f64 = match le.parse() {
+            Ok(ub) if le == "+Inf" || f64::is_finite(ub) => ub,
+            _ => {
+                problems.push(format!("{}.samples[{}].le", base, j), Cow::Owned(format!("{}.samples[{}].le must be a finite number or \"+Inf\"", base, j)));
+                continue;
+            },
+        };
+        if upper_bound <= previous_upper_bound {
+            problems.push(format!("{}.samples[{}].le", base, j), Cow::Owned(format!("{}.samples[{}].le must be greater than the upper bound of the previous bucket", base, j)));
+        }
+        previous_upper_bound = upper_bound;
+
+        // each metric is one histogram (per object)
+        if *sample.labels != *first_sample.labels {
+            problems.push(format!("{}.samples[{}].labels", base, j), Cow::Owned(format!("{}.samples[{}].labels must equal the labels of the first bucket", base, j)));
+        }
+        if sample.labels.contains_key("le") {
+            problems.push(format!("{}.samples[{}].labels", base, j), Cow::Owned(format!("{}.samples[{}].labels must not contain \"le\"", base, j)));
+        }
     }
+    if previous_upper_bound != f64::INFINITY {
+        problems.push(format!("{}.samples", base), Cow::Owned(format!("{}.samples must end with a bucket whose le is \"+Inf\"", base)));
+    }
+}
 
-    if metric.metric.len() == 0 {
-        return Err(Cow::Owned(format!("{}.metric must not be empty", base)));
+/// Checks the credentials for pushing metrics in the given configuration section.
+fn check_push_credentials(
+    section: &str,
+    username: &Option<String>,
+    password: &Option<String>,
+    bearer_token: &Option<String>,
+    problems: &mut Problems,
+) {
+    if username.is_some() != password.is_some() {
+        let missing = if username.is_some() { "password" } else { "username" };
+        problems.push(format!("{}.{}", section, missing), Cow::Owned(format!("{0}.username and {0}.password must be set together", section)));
+    }
+    if username.is_some() && bearer_token.is_some() {
+        problems.push(format!("{}.bearer_token", section), Cow::Owned(format!("{0}.username and {0}.bearer_token are mutually exclusive", section)));
     }
+    if bearer_token.as_ref().is_some_and(|t| !t.chars().all(|c| c.is_ascii_graphic())) {
+        problems.push(format!("{}.bearer_token", section), Cow::Owned(format!("{}.bearer_token must consist only of printable ASCII characters other than spaces", section)));
+    }
+}
 
-    let metric_start = metric.metric.chars().nth(0).unwrap();
+fn is_valid_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    let Some(first_char) = chars.next() else { return false };
+    (first_char.is_ascii_alphabetic() || first_char == '_')
+        && chars.all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_')
+}
+
+fn check_metric_name(name: &str, base: &str, problems: &mut Problems) {
+    if name.is_empty() {
+        problems.push(format!("{}.metric", base), Cow::Owned(format!("{}.metric must not be empty", base)));
+        return;
+    }
+
+    let metric_start = name.chars().next().unwrap();
     if !(metric_start.is_ascii_alphabetic() || metric_start == '_' && metric_start == ':') {
-        return Err(Cow::Owned(format!("{}.metric must start with an ASCII letter, an underscore or a colon", base)));
+        problems.push(format!("{}.metric", base), Cow::Owned(format!("{}.metric must start with an ASCII letter, an underscore or a colon", base)));
     }
 
-    let metric_rest_is_valid = metric.metric.chars()
+    let metric_rest_is_valid = name.chars()
         .skip(1)
         .all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_' || c == ':');
     if !metric_rest_is_valid {
-        return Err(Cow::Owned(format!("{}.metric must consist only of ASCII letters, ASCII digits, underscores and colons", base)));
+        problems.push(format!("{}.metric", base), Cow::Owned(format!("{}.metric must consist only of ASCII letters, ASCII digits, underscores and colons", base)));
+    }
+}
+
+fn check_metric<'a>(
+    metric: &'a MetricConfig,
+    base: &str,
+    per_object: Option<&PerObjectMetricConfig>,
+    counter_suffix: &str,
+    known_metrics: &mut HashSet<&'a str>,
+    problems: &mut Problems,
+) {
+    // "requests" and "requests_total" are the same counter
+    if !known_metrics.insert(family_name(&metric.metric, metric.kind, counter_suffix)) {
+        problems.push(format!("{}.metric", base), Cow::Owned(format!("{}.metric is not unique", base)));
+    }
+
+    check_metric_name(&metric.metric, base, problems);
+    if metric.kind == MetricKind::Info {
+        problems.push(format!("{}.kind", base), Cow::Owned(format!("{}.kind \"info\" is only allowed in info_metrics", base)));
     }
 
     // help string may contain anything :-)
 
-    if let Some(unit) = metric.unit.as_ref() {
+    if metric.is_timestamp {
+        if metric.kind != MetricKind::Gauge {
+            problems.push(format!("{}.is_timestamp", base), Cow::Owned(format!("{}.is_timestamp requires kind to be \"gauge\"", base)));
+        }
+        if metric.unit.as_deref() != Some("seconds") {
+            problems.push(format!("{}.is_timestamp", base), Cow::Owned(format!("{}.is_timestamp requires unit to be \"seconds\"", base)));
+        }
+    }
+
+    if let Some(precision) = metric.precision {
+        if metric.kind == MetricKind::Counter {
+            problems.push(format!("{}.precision", base), Cow::Owned(format!("{}.precision is not allowed with kind \"counter\"", base)));
+        }
+        if precision > MAX_PRECISION {
+            problems.push(format!("{}.precision", base), Cow::Owned(format!("{}.precision must be at most {}", base, MAX_PRECISION)));
+        }
+    }
+
+    if metric.max_series == Some(0) {
+        problems.push(format!("{}.max_series", base), Cow::Owned(format!("{}.max_series must be at least 1 if set", base)));
+    }
+
+    if metric.non_negative && metric.kind == MetricKind::GaugeHistogram {
+        problems.push(format!("{}.non_negative", base), Cow::Owned(format!("{}.non_negative is not allowed with kind \"gauge_histogram\"", base)));
+    }
+    if !metric.non_negative && metric.on_negative != NegativeValueAction::Skip {
+        problems.push(format!("{}.on_negative", base), Cow::Owned(format!("{}.on_negative requires non_negative to be true", base)));
+    }
+
+    if let Some(unit_template) = metric.unit.as_ref() {
+        // per-object units are templates; validate what will actually be output
+        let unit = match per_object {
+            Some(po) => po.render_template(unit_template),
+            None => unit_template.clone(),
+        };
         let unit_is_valid = unit.chars()
             .all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_' || c == ':');
         if !unit_is_valid {
-            return Err(Cow::Owned(format!("{}.unit must be null or consist only of ASCII letters, ASCII digits, underscores and colons", base)));
+            problems.push(format!("{}.unit", base), Cow::Owned(format!("{}.unit must be null or consist only of ASCII letters, ASCII digits, underscores and colons (after substituting templates)", base)));
         }
     }
 
-    for (j, sample) in metric.samples.iter().enumerate() {
-        if sample.statistic.find(':').is_some() {
-            return Err(Cow::Owned(format!("{}.samples[{}].statistic must not contain a colon", base, j)));
+    if metric.kind == MetricKind::GaugeHistogram {
+        check_gauge_histogram(metric, base, problems);
+        if per_object.is_some_and(|po| po.identifier_labels().contains_key("le")) {
+            problems.push(base, Cow::Owned(format!("{} is a gauge histogram, so no identifier label may be \"le\"", base)));
+        }
+        if per_object.is_some_and(|po| po.kind_label.as_deref() == Some("le")) {
+            problems.push(base, Cow::Owned(format!("{} is a gauge histogram, so the kind_label may not be \"le\"", base)));
+        }
+    } else {
+        if metric.sum_statistic.is_some() {
+            problems.push(format!("{}.sum_statistic", base), Cow::Owned(format!("{}.sum_statistic is only allowed with kind \"gauge_histogram\"", base)));
+        }
+        if let Some(j) = metric.samples.iter().position(|s| s.le.is_some()) {
+            problems.push(format!("{}.samples[{}].le", base, j), Cow::Owned(format!("{}.samples[{}].le is only allowed with kind \"gauge_histogram\"", base, j)));
         }
+    }
+
+    if matches!(metric.kind, MetricKind::Counter | MetricKind::GaugeHistogram) {
+        if let Some(j) = metric.samples.iter().position(|s| s.default_value.is_some_and(|dv| dv < 0)) {
+            problems.push(format!("{}.samples[{}].default_value", base, j), Cow::Owned(format!("{}.samples[{}].default_value must not be negative for counters and gauge histograms", base, j)));
+        }
+    }
+
+    if let Some(sort_samples) = metric.sort_samples.as_ref() {
+        let is_sample_label = metric.samples.iter()
+            .any(|s| s.labels.contains_key(&sort_samples.label));
+        let is_object_label = per_object.is_some_and(|po| {
+            po.identifier_labels().contains_key(sort_samples.label.as_str())
+                || po.kind_label.as_deref() == Some(sort_samples.label.as_str())
+        });
+        if !is_sample_label && !is_object_label {
+            problems.push(format!("{}.sort_samples.label", base), Cow::Owned(format!("{}.sort_samples.label must be one of the labels of the metric", base)));
+        }
+    }
+
+    if let Some(when) = metric.when.as_ref() {
+        if !is_valid_label_name(&when.label) {
+            problems.push(format!("{}.when.label", base), Cow::Owned(format!("{}.when.label must start with an ASCII letter or an underscore and consist only of ASCII letters, ASCII digits and underscores", base)));
+        }
+    }
 
-        for (key, _value) in &sample.labels {
-            if key.len() == 0 {
-                return Err(Cow::Owned(format!("{}.samples[{}].labels[{:?}] key must not be empty", base, j, key)));
+    for (j, sample) in metric.samples.iter().enumerate() {
+        for key in sample.labels.keys() {
+            if key.is_empty() {
+                problems.push(format!("{}.samples[{}].labels[{:?}]", base, j, key), Cow::Owned(format!("{}.samples[{}].labels[{:?}] key must not be empty", base, j, key)));
+                continue;
             }
 
-            let key_start = key.chars().nth(0).unwrap();
+            let key_start = key.chars().next().unwrap();
             if !(key_start.is_ascii_alphabetic() || key_start == '_') {
-                return Err(Cow::Owned(format!("{}.samples[{}].labels[{:?}] key must start with an ASCII letter or an underscore", base, j, key)));
+                problems.push(format!("{}.samples[{}].labels[{:?}]", base, j, key), Cow::Owned(format!("{}.samples[{}].labels[{:?}] key must start with an ASCII letter or an underscore", base, j, key)));
             }
 
             let key_rest_is_valid = key.chars()
                 .skip(1)
                 .all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_');
             if !key_rest_is_valid {
-                return Err(Cow::Owned(format!("{}.samples[{}].labels[{:?}] key must consist only of ASCII letters, ASCII digits and underscores", base, j, key)));
+                problems.push(format!("{}.samples[{}].labels[{:?}]", base, j, key), Cow::Owned(format!("{}.samples[{}].labels[{:?}] key must consist only of ASCII letters, ASCII digits and underscores", base, j, key)));
             }
 
             // values may contain anything :-)
         }
     }
-
-    Ok(())
 }
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use http_body_util::Full;
+use hyper::{Request, Response};
+use hyper::body::{Bytes, Incoming};
+use serde::Serialize;
+use tracing::{error, info};
+
+use crate::client::{self, RadiatorClient};
+use crate::config::{Config, ConnectionMode};
+use crate::decode::{Field, field_types, Statistics};
+use crate::openmetrics::Number;
+use crate::radiator::{RadiatorTransport, ScrapeConnection};
+
+
+#[derive(Clone, Debug, Serialize)]
+struct DebugField {
+    #[serde(rename = "type")]
+    value_type: String,
+    value: String,
+}
+
+
+/// Everything Radiator reports about an object, regardless of the metric configuration.
+#[derive(Clone, Debug, Serialize)]
+struct DebugStats {
+    object: String,
+    fields: BTreeMap<String, DebugField>,
+    statistics: BTreeMap<String, serde_json::Value>,
+}
+
+
+fn number_to_json(number: Number) -> serde_json::Value {
+    match number {
+        Number::Integer(i) => serde_json::Value::from(i),
+        // NaN and infinities have no JSON representation and become null
+        Number::Float(f) => serde_json::Value::from(f),
+    }
+}
+
+
+pub(crate) fn plain_response(status: u16, body: String) -> Result<Response<Full<Bytes>>, Infallible> {
+    Ok(
+        Response::builder()
+            .status(status)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .body(Full::new(Bytes::from(body)))
+            .expect("cannot construct plain-text HTTP response")
+    )
+}
+
+
+/// Whether the client may access debug endpoints and reload the configuration: either it has
+/// authenticated itself using a TLS client certificate or it is connecting from the same host.
+pub(crate) fn is_authorized(remote_addr: SocketAddr, client_subject: Option<&str>) -> bool {
+    client_subject.is_some() || remote_addr.ip().to_canonical().is_loopback()
+}
+
+
+/// Obtains the fields and statistics of the given Radiator object or the error response to return.
+async fn query_object(
+    object: &str,
+    config: &Config,
+    transport: &dyn RadiatorTransport,
+) -> Result<(Vec<Field>, Statistics), Result<Response<Full<Bytes>>, Infallible>> {
+    let client = RadiatorClient::new(config, transport);
+    match client.describe(object).await {
+        Ok(fields) => {
+            let field_types = field_types(&fields);
+            match client.stats(object, &field_types).await {
+                Ok(statistics) => Ok((fields, statistics)),
+                Err(client::Error::NoSuchObject) => Err(plain_response(404, format!("Radiator object {} does not exist", object))),
+                Err(client::Error::PermissionDenied) => Err(plain_response(403, format!("Radiator denied permission to obtain statistics of {}", object))),
+                Err(e) => Err(plain_response(503, format!("failed to obtain statistics of {}: {}", object, e))),
+            }
+        },
+        Err(client::Error::NoSuchObject) => Err(plain_response(404, format!("Radiator object {} does not exist", object))),
+        Err(client::Error::PermissionDenied) => Err(plain_response(403, format!("Radiator denied permission to describe {}", object))),
+        Err(e) => Err(plain_response(503, format!("failed to describe {}: {}", object, e))),
+    }
+}
+
+
+/// Handles `/debug/stats?object=...`, returning the fields (`DESCRIBE`) and statistics (`STATS`) of
+/// the given Radiator object (`.` by default) as JSON.
+pub(crate) async fn handle_debug_stats(
+    request: &Request<Incoming>,
+    remote_addr: SocketAddr,
+    client_subject: Option<&str>,
+    config: &Config,
+    transport: &dyn RadiatorTransport,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !config.www.enable_debug {
+        return plain_response(404, "not found".to_owned());
+    }
+    if !is_authorized(remote_addr, client_subject) {
+        return plain_response(403, "debug endpoints require a TLS client certificate or a local connection".to_owned());
+    }
+
+    let query = request.uri().query().unwrap_or("");
+    let object = form_urlencoded::parse(query.as_bytes())
+        .find(|(key, _value)| key == "object")
+        .map(|(_key, value)| value.into_owned())
+        .unwrap_or_else(|| ".".to_owned());
+    if object.is_empty() || object.contains(['\0', '\n', ' ']) {
+        return plain_response(400, "invalid object".to_owned());
+    }
+    info!(event = "debug_stats", object = object.as_str());
+
+    let connection = match config.radiator.connection_mode {
+        ConnectionMode::Persistent => None,
+        ConnectionMode::PerScrape => match ScrapeConnection::open().await {
+            Ok(c) => Some(c),
+            Err(e) => return plain_response(503, format!("failed to connect to Radiator: {}", e)),
+        },
+    };
+    let query_result = query_object(&object, config, transport).await;
+    if let Some(connection) = connection {
+        connection.close().await;
+    }
+    let (fields, statistics) = match query_result {
+        Ok(fs) => fs,
+        Err(response) => return response,
+    };
+
+    let debug_stats = DebugStats {
+        object,
+        fields: fields.into_iter()
+            .map(|f| (f.name, DebugField { value_type: f.value_type, value: f.value }))
+            .collect(),
+        statistics: statistics.numbers.into_iter()
+            .map(|(name, value)| (name, number_to_json(value)))
+            .chain(statistics.strings.into_iter().map(|(name, value)| (name, serde_json::Value::from(value))))
+            .collect(),
+    };
+    let body = match serde_json::to_string_pretty(&debug_stats) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("failed to serialize debug statistics: {}", e);
+            return plain_response(500, "internal server error".to_owned());
+        },
+    };
+
+    let response_res = Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body)));
+    match response_res {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("failed to construct 200 response: {}", e);
+            plain_response(500, "internal server error".to_owned())
+        },
+    }
+}
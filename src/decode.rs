@@ -0,0 +1,523 @@
+// Decoding of the responses of Radiator's management interface.
+//
+// The decoders process untrusted input from the network; they must skip anything they do not
+// understand instead of panicking. This module must not depend on anything in this crate apart from
+// openmetrics, as both are compiled into the fuzz targets in fuzz/.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::openmetrics::Number;
+
+
+const FIELD_TYPE_INTEGER: &str = "integer";
+pub(crate) const FIELD_TYPE_STRING: &str = "string";
+
+
+/// A field of a Radiator object, as returned by `DESCRIBE`.
+#[derive(Clone, Debug)]
+pub(crate) struct Field {
+    pub name: String,
+    pub value_type: String,
+    pub value: String,
+}
+
+
+/// The statistics of an object, as returned by `STATS`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Statistics {
+    /// Numeric statistics.
+    pub numbers: HashMap<String, Number>,
+
+    /// Statistics exported as labels of info metrics, which are kept verbatim.
+    pub strings: HashMap<String, String>,
+}
+impl Statistics {
+    pub fn is_empty(&self) -> bool {
+        self.numbers.is_empty() && self.strings.is_empty()
+    }
+}
+
+
+/// The delimiters used in `STATS` and `DESCRIBE` responses.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Delimiters {
+    /// Delimits the key-value pairs (or key-type-value tuples) from each other.
+    pub pair: char,
+
+    /// Delimits keys, types and values within a pair (or tuple).
+    pub key_value: char,
+}
+
+
+/// A unit suffix that is stripped from statistic values, converting them to the base unit.
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub(crate) enum ValueUnit {
+    #[serde(rename = "ms")] Milliseconds,
+    #[serde(rename = "s")] Seconds,
+    #[serde(rename = "%")] Percent,
+}
+impl ValueUnit {
+    pub const fn suffix(&self) -> &'static str {
+        match self {
+            Self::Milliseconds => "ms",
+            Self::Seconds => "s",
+            Self::Percent => "%",
+        }
+    }
+
+    pub const fn scale_factor(&self) -> f64 {
+        match self {
+            Self::Milliseconds => 0.001,
+            Self::Seconds => 1.0,
+            Self::Percent => 0.01,
+        }
+    }
+}
+
+
+/// Number of key-value pairs (or key-type-value tuples) that were skipped because they were not
+/// valid UTF-8.
+///
+/// Kept here instead of in `self_metrics` so that this module stays usable from the fuzz targets.
+pub(crate) static INVALID_UTF8_FIELDS: AtomicU64 = AtomicU64::new(0);
+
+
+/// Skips the echoed command in the response to the given command if Radiator has echoed it.
+fn strip_echoed_command<'a>(response: &'a [u8], command: &str) -> &'a [u8] {
+    // the echoed command is separated from the actual response by a newline
+    // (some servers and proxies do not echo at all); the command itself may contain newlines, so
+    // the echo is matched as a whole instead of taking the first line
+    let unechoed_response = response.strip_prefix(command.as_bytes())
+        .map(|rest| {
+            let padding = rest.iter()
+                .take_while(|b| **b != b'\n' && b.is_ascii_whitespace())
+                .count();
+            &rest[padding..]
+        })
+        .and_then(|rest| rest.strip_prefix(b"\n"));
+    unechoed_response.unwrap_or(response)
+}
+
+
+/// Splits the (unechoed) response at the given delimiter and decodes each piece as UTF-8.
+///
+/// Pieces that are not valid UTF-8 (e.g. a statistic whose value was cut off in the middle of a
+/// character) are skipped and counted. Only if no piece at all can be decoded is the whole response
+/// rejected, since it is then most likely not a response to the command at all.
+fn split_and_decode(response: &[u8], delimiter: char) -> Option<Vec<&str>> {
+    let mut delimiter_buf = [0u8; 4];
+    let delimiter_bytes = delimiter.encode_utf8(&mut delimiter_buf).as_bytes();
+
+    let mut pieces = Vec::new();
+    let mut invalid_count: u64 = 0;
+    let mut rest = response;
+    loop {
+        let (piece, next) = match rest.windows(delimiter_bytes.len()).position(|w| w == delimiter_bytes) {
+            Some(index) => (&rest[..index], Some(&rest[index+delimiter_bytes.len()..])),
+            None => (rest, None),
+        };
+        match std::str::from_utf8(piece) {
+            Ok(p) => pieces.push(p),
+            Err(e) => {
+                warn!("Radiator response piece {:?} is not valid UTF-8 ({}); skipping", String::from_utf8_lossy(piece), e);
+                invalid_count += 1;
+            },
+        }
+        match next {
+            Some(n) => rest = n,
+            None => break,
+        }
+    }
+
+    INVALID_UTF8_FIELDS.fetch_add(invalid_count, Ordering::Relaxed);
+    if pieces.is_empty() && invalid_count > 0 {
+        error!("Radiator response {:?} is not valid UTF-8", String::from_utf8_lossy(response));
+        return None;
+    }
+    Some(pieces)
+}
+
+
+/// Splits a `key:value` statistic into key and value.
+///
+/// Keys may contain colons (e.g. `Auth:Total`), and so may the values of string statistics (e.g.
+/// `1:2`). If the key is known (from `DESCRIBE` or as the name of a string statistic), the longest
+/// known key that fits is taken; otherwise, the value is assumed to follow the last colon, since
+/// numeric values do not contain colons.
+fn split_statistic<'a>(
+    key_value_pair: &'a str,
+    known_keys: &HashSet<&str>,
+    delimiter: char,
+) -> Option<(&'a str, &'a str)> {
+    let known_key_length = known_keys.iter()
+        .filter(|k| key_value_pair.starts_with(**k) && key_value_pair[k.len()..].starts_with(delimiter))
+        .map(|k| k.len())
+        .max();
+    match known_key_length {
+        Some(key_length) => Some((&key_value_pair[..key_length], &key_value_pair[key_length+delimiter.len_utf8()..])),
+        None => key_value_pair.rsplit_once(delimiter),
+    }
+}
+
+
+/// Splits a `key:type:value` field description into key, type and value.
+///
+/// Keys and (string) values may contain colons, while types are lowercase words (e.g. `integer` or
+/// `string`). The type is therefore taken to be the first colon-delimited piece after the first one
+/// that consists only of lowercase ASCII letters; if there is no such piece, the first two colons
+/// are taken as delimiters.
+fn split_field_description(key_type_value_tuple: &str, delimiter: char) -> Option<(&str, &str, &str)> {
+    let delimiter_length = delimiter.len_utf8();
+    let mut colon_indexes = key_type_value_tuple.match_indices(delimiter).map(|(i, _)| i);
+    let first_colon = colon_indexes.next()?;
+    let second_colon = colon_indexes.next()?;
+
+    let mut type_start = first_colon + delimiter_length;
+    let mut type_end = second_colon;
+    loop {
+        let candidate = &key_type_value_tuple[type_start..type_end];
+        if !candidate.is_empty() && candidate.chars().all(|c| c.is_ascii_lowercase()) {
+            return Some((
+                &key_type_value_tuple[..type_start-delimiter_length],
+                candidate,
+                &key_type_value_tuple[type_end+delimiter_length..],
+            ));
+        }
+
+        match colon_indexes.next() {
+            Some(next_colon) => {
+                type_start = type_end + delimiter_length;
+                type_end = next_colon;
+            },
+            None => break,
+        }
+    }
+
+    // no plausible type found; fall back to the first two colons
+    Some((
+        &key_type_value_tuple[..first_colon],
+        &key_type_value_tuple[first_colon+delimiter_length..second_colon],
+        &key_type_value_tuple[second_colon+delimiter_length..],
+    ))
+}
+
+
+/// Strips the first matching unit suffix from a value, returning the remaining value and the factor
+/// by which it must be multiplied to obtain the value in the base unit.
+fn strip_value_unit<'a>(value: &'a str, value_units: &[ValueUnit]) -> (&'a str, Option<f64>) {
+    let mut best_match: Option<&ValueUnit> = None;
+    for value_unit in value_units {
+        if !value.ends_with(value_unit.suffix()) {
+            continue;
+        }
+        // prefer the longest suffix ("ms" over "s")
+        if best_match.map(|bm| bm.suffix().len() < value_unit.suffix().len()).unwrap_or(true) {
+            best_match = Some(value_unit);
+        }
+    }
+    match best_match {
+        Some(value_unit) => (
+            value[..value.len()-value_unit.suffix().len()].trim_end(),
+            Some(value_unit.scale_factor()),
+        ),
+        None => (value, None),
+    }
+}
+
+
+/// Removes the given prefix from a statistic name, unless nothing would remain of the name.
+pub(crate) fn strip_statistic_prefix<'a>(name: &'a str, prefix: Option<&str>) -> &'a str {
+    match prefix.and_then(|p| name.strip_prefix(p)) {
+        Some(stripped) if !stripped.is_empty() => stripped,
+        _ => name,
+    }
+}
+
+
+pub(crate) fn decode_stats(
+    response: &[u8],
+    command: &str,
+    field_types: &HashMap<String, String>,
+    string_statistics: &HashSet<String>,
+    value_units: &[ValueUnit],
+    delimiters: Delimiters,
+    statistic_prefix: Option<&str>,
+) -> Option<Statistics> {
+    // response format: b"STATS .\nkey1:value1\x01key2:value2\x01key3:value3"
+    let response_bytes = strip_echoed_command(response, command);
+    if response_bytes.is_empty() {
+        return Some(Statistics::default());
+    }
+
+    // key-value pairs are delimited by U+0001 characters (by default)
+    let mut statistics = HashMap::new();
+    let mut strings = HashMap::new();
+    // the key each statistic name was stripped from, to notice keys that become indistinguishable
+    let mut stripped_keys: HashMap<&str, &str> = HashMap::new();
+    // the values of string statistics may contain colons; knowing their names (as Radiator reports
+    // them, i.e. before stripping the prefix) splits them correctly even without DESCRIBE
+    let prefixed_string_statistics: Vec<String> = match statistic_prefix {
+        Some(prefix) => string_statistics.iter()
+            .map(|s| format!("{}{}", prefix, s))
+            .collect(),
+        None => Vec::new(),
+    };
+    let known_keys: HashSet<&str> = field_types.keys()
+        .chain(string_statistics.iter())
+        .chain(prefixed_string_statistics.iter())
+        .map(|k| k.as_str())
+        .collect();
+
+    let key_value_pairs = split_and_decode(response_bytes, delimiters.pair)?;
+    for key_value_pair in key_value_pairs {
+        // keys and values are delimited by a colon (by default), but keys may contain colons themselves
+        let (full_key, value) = match split_statistic(key_value_pair, &known_keys, delimiters.key_value) {
+            Some(kv) => kv,
+            None => {
+                warn!("statistics key-value pair {:?} does not contain {:?}; skipping", key_value_pair, delimiters.key_value);
+                continue;
+            },
+        };
+
+        // DESCRIBE reports the full names
+        let field_type = field_types.get(full_key).map(|t| t.as_str());
+        let key = strip_statistic_prefix(full_key, statistic_prefix);
+        if statistic_prefix.is_some() {
+            if let Some(other_key) = stripped_keys.insert(key, full_key) {
+                if other_key != full_key {
+                    warn!("statistics {:?} and {:?} are both called {:?} once the prefix is stripped; only one of them is kept", other_key, full_key, key);
+                }
+            }
+        }
+
+        // some Radiator versions pad values or append units
+        let value = value.trim();
+
+        if string_statistics.contains(key) {
+            // exported as a label; keep it as it is
+            if let Some(old_value) = strings.insert(key.to_owned(), value.to_owned()) {
+                warn!("duplicate statistic {:?}; overwriting old value {:?} with {:?}", key, old_value, value);
+            }
+            continue;
+        }
+
+        let (value, scale_factor) = strip_value_unit(value, value_units);
+
+        // parse value according to the type declared by DESCRIBE (if known)
+        let value = match field_type {
+            Some(FIELD_TYPE_STRING) => {
+                // not a number, even if it looks like one
+                continue;
+            },
+            Some(FIELD_TYPE_INTEGER) => match value.parse() {
+                Ok(v) => Number::Integer(v),
+                Err(e) => {
+                    warn!("failed to parse value {:?} for integer statistic {:?} (skipping it): {}", value, key, e);
+                    continue;
+                },
+            },
+            _ => match value.parse() {
+                Ok(v) => Number::Integer(v),
+                Err(_) => {
+                    // integer failed; try float
+                    match value.parse() {
+                        Ok(v) => Number::Float(v),
+                        Err(e) => {
+                            warn!("failed to parse value {:?} for statistic {:?} as an integer or floating-point value (skipping it): {}", value, key, e);
+                            continue;
+                        },
+                    }
+                },
+            },
+        };
+
+        let value = match scale_factor {
+            Some(factor) => Number::Float(value.as_f64() * factor),
+            None => value,
+        };
+
+        if let Some(old_value) = statistics.insert(key.to_owned(), value) {
+            warn!("duplicate statistic {:?}; overwriting old value {} with {}", key, old_value, value);
+        }
+    }
+
+    Some(Statistics {
+        numbers: statistics,
+        strings,
+    })
+}
+
+
+pub(crate) fn decode_description(response: &[u8], command: &str, delimiters: Delimiters) -> Option<Vec<Field>> {
+    // response format: b"DESCRIBE ObjectType.2\nkey1:type1:value1\x01key2:type2:value2\x01key3:type3:value3"
+    let response_bytes = strip_echoed_command(response, command);
+    if response_bytes.is_empty() {
+        return Some(Vec::new());
+    }
+
+    // key-type-value tuples are delimited by U+0001 characters (by default)
+    let mut fields = Vec::new();
+    let key_type_value_tuples = split_and_decode(response_bytes, delimiters.pair)?;
+    for key_type_value_tuple in key_type_value_tuples {
+        // keys, types and values are delimited by colons (by default), but keys and values may contain
+        // colons too
+        let (key, value_type, value) = match split_field_description(key_type_value_tuple, delimiters.key_value) {
+            Some(ktv) => ktv,
+            None => {
+                warn!("statistics key-type-value tuple {:?} does not contain two {:?}; skipping", key_type_value_tuple, delimiters.key_value);
+                continue;
+            },
+        };
+
+        fields.push(Field {
+            name: key.to_owned(),
+            value_type: value_type.to_owned(),
+            value: value.to_owned(),
+        });
+    }
+
+    Some(fields)
+}
+
+
+/// Extracts the values of the given identifying fields (e.g. `Identifier`), keyed by field name.
+///
+/// Fields that the object does not have are missing from the result.
+pub(crate) fn extract_identifiers<'a, I: IntoIterator<Item = &'a str>>(fields: &[Field], identifier_fields: I) -> BTreeMap<String, String> {
+    let mut identifiers = BTreeMap::new();
+    for identifier_field in identifier_fields {
+        let value = fields.iter()
+            .find(|f| f.name == identifier_field)
+            .map(|f| f.value.clone());
+        if let Some(v) = value {
+            identifiers.insert(identifier_field.to_owned(), v);
+        }
+    }
+    identifiers
+}
+
+
+pub(crate) fn field_types(fields: &[Field]) -> HashMap<String, String> {
+    fields.iter()
+        .map(|f| (f.name.clone(), f.value_type.clone()))
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use crate::openmetrics::Number;
+    use super::{decode_stats, Delimiters, split_field_description, split_statistic, strip_echoed_command};
+
+
+    const DELIMITERS: Delimiters = Delimiters {
+        pair: '\x01',
+        key_value: ':',
+    };
+
+    fn types(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter()
+            .map(|(k, t)| ((*k).to_owned(), (*t).to_owned()))
+            .collect()
+    }
+
+    fn keys<'a>(keys: &[&'a str]) -> HashSet<&'a str> {
+        keys.iter().copied().collect()
+    }
+
+    #[test]
+    fn split_statistic_with_colon_in_key() {
+        // without DESCRIBE, the value follows the last colon
+        assert_eq!(split_statistic("Auth:Total:5", &HashSet::new(), ':'), Some(("Auth:Total", "5")));
+
+        let known = keys(&["Auth:Total"]);
+        assert_eq!(split_statistic("Auth:Total:5", &known, ':'), Some(("Auth:Total", "5")));
+    }
+
+    #[test]
+    fn split_statistic_prefers_longest_known_key() {
+        let known = keys(&["Auth", "Auth:Total"]);
+        assert_eq!(split_statistic("Auth:Total:5", &known, ':'), Some(("Auth:Total", "5")));
+        assert_eq!(split_statistic("Auth:3", &known, ':'), Some(("Auth", "3")));
+
+        // a known key only matches up to the delimiter
+        let known = keys(&["Auth:Total"]);
+        assert_eq!(split_statistic("Auth:Totals:7", &known, ':'), Some(("Auth:Totals", "7")));
+    }
+
+    #[test]
+    fn split_statistic_without_delimiter() {
+        assert_eq!(split_statistic("Auth", &HashSet::new(), ':'), None);
+    }
+
+    #[test]
+    fn split_field_description_with_colons() {
+        assert_eq!(split_field_description("Auth:Total:integer:5", ':'), Some(("Auth:Total", "integer", "5")));
+        assert_eq!(split_field_description("Identifier:string:a:b", ':'), Some(("Identifier", "string", "a:b")));
+        assert_eq!(split_field_description("Name:string:", ':'), Some(("Name", "string", "")));
+
+        // no piece looks like a type; the first two colons are taken
+        assert_eq!(split_field_description("A:B1:C", ':'), Some(("A", "B1", "C")));
+
+        assert_eq!(split_field_description("Name:string", ':'), None);
+        assert_eq!(split_field_description("Name", ':'), None);
+    }
+
+    #[test]
+    fn decode_stats_with_colon_in_key() {
+        let response = b"STATS .\nAuth:Total:5\x01Access requests:7";
+        for field_types in [HashMap::new(), types(&[("Auth:Total", "integer"), ("Access requests", "integer")])] {
+            let statistics = decode_stats(response, "STATS .", &field_types, &HashSet::new(), &[], DELIMITERS, None)
+                .expect("failed to decode statistics");
+            assert_eq!(statistics.numbers.len(), 2);
+            assert!(matches!(statistics.numbers.get("Auth:Total"), Some(Number::Integer(5))));
+            assert!(matches!(statistics.numbers.get("Access requests"), Some(Number::Integer(7))));
+        }
+    }
+
+    #[test]
+    fn decode_string_statistic_with_colon_in_value() {
+        let string_statistics = HashSet::from(["Version".to_owned()]);
+        let response = b"STATS .\nVersion:1:2\x01Access requests:7";
+        let statistics = decode_stats(response, "STATS .", &HashMap::new(), &string_statistics, &[], DELIMITERS, None)
+            .expect("failed to decode statistics");
+        assert_eq!(statistics.strings.get("Version").map(|v| v.as_str()), Some("1:2"));
+        assert!(matches!(statistics.numbers.get("Access requests"), Some(Number::Integer(7))));
+
+        // Radiator reports the name before the prefix is stripped
+        let response = b"STATS .\nServer Version:1:2\x01Server Access requests:7";
+        let statistics = decode_stats(response, "STATS .", &HashMap::new(), &string_statistics, &[], DELIMITERS, Some("Server "))
+            .expect("failed to decode statistics");
+        assert_eq!(statistics.strings.get("Version").map(|v| v.as_str()), Some("1:2"));
+        assert!(matches!(statistics.numbers.get("Access requests"), Some(Number::Integer(7))));
+    }
+
+    #[test]
+    fn strip_multi_line_echo() {
+        let response = b"STATS Handler.0\nRealm=local\nAccess requests:5";
+        assert_eq!(strip_echoed_command(response, "STATS Handler.0\nRealm=local"), b"Access requests:5");
+
+        // only the first line matching the command is not enough
+        assert_eq!(strip_echoed_command(response, "STATS Handler.0\nRealm=remote"), response);
+    }
+
+    #[test]
+    fn strip_missing_echo() {
+        assert_eq!(strip_echoed_command(b"Access requests:5", "STATS ."), b"Access requests:5");
+
+        // a response that merely starts like the command is not an echo
+        assert_eq!(strip_echoed_command(b"STATS .5", "STATS ."), b"STATS .5");
+    }
+
+    #[test]
+    fn strip_echo_with_trailing_whitespace() {
+        assert_eq!(strip_echoed_command(b"STATS . \t\r\nAccess requests:5", "STATS ."), b"Access requests:5");
+        assert_eq!(strip_echoed_command(b"STATS .\r\nAccess requests:5", "STATS ."), b"Access requests:5");
+    }
+}
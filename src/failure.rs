@@ -0,0 +1,130 @@
+use std::convert::Infallible;
+
+use http_body_util::Full;
+use hyper::{Request, Response, StatusCode};
+use hyper::body::Bytes;
+use serde::Serialize;
+
+use crate::client;
+use crate::radiator;
+
+
+/// Why the exporter could not serve a request, for tooling that wants to classify failures.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum FailureCategory {
+    /// The exporter could not connect or log in to Radiator or lost the connection.
+    RadiatorUnreachable,
+
+    /// Connecting or logging in to Radiator took too long.
+    Timeout,
+
+    /// The connection to Radiator was lost during every attempt to send a command.
+    ConnectionUnstable,
+
+    /// A response from Radiator could not be understood.
+    ParseError,
+
+    /// Radiator refused a command or the command could not be sent to it.
+    RadiatorError,
+
+    /// The exporter has not completed a scrape yet or considers itself unready.
+    NotReady,
+}
+impl FailureCategory {
+    pub fn from_radiator_error(error: &radiator::Error) -> Self {
+        match error {
+            radiator::Error::HandshakeTimeout { .. } => Self::Timeout,
+            radiator::Error::UnexpectedLoginResponse { .. } => Self::ParseError,
+            radiator::Error::Io(_)
+                | radiator::Error::Handshake { .. }
+                | radiator::Error::InvalidCredentials
+                | radiator::Error::ReaderGone
+                | radiator::Error::CircuitOpen
+                => Self::RadiatorUnreachable,
+            radiator::Error::RetriesExhausted { .. } => Self::ConnectionUnstable,
+            radiator::Error::InvalidCommand { .. } => Self::RadiatorError,
+        }
+    }
+
+    pub fn from_client_error(error: &client::Error) -> Self {
+        match error {
+            client::Error::Radiator(e) => Self::from_radiator_error(e),
+            client::Error::UndecodableResponse => Self::ParseError,
+            client::Error::NoSuchObject
+                | client::Error::PermissionDenied
+                | client::Error::Rejected(_)
+                => Self::RadiatorError,
+        }
+    }
+}
+
+
+/// How to present error responses to the client.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum ErrorBodyFormat {
+    PlainText,
+    Json,
+}
+impl ErrorBodyFormat {
+    /// Chooses JSON if the client explicitly accepts it and plain text otherwise.
+    pub fn from_request<B>(request: &Request<B>) -> Self {
+        let accepts_json = request.headers()
+            .get_all("Accept")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media_range| media_range.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("application/json"));
+        if accepts_json {
+            Self::Json
+        } else {
+            Self::PlainText
+        }
+    }
+}
+
+
+#[derive(Clone, Debug, Serialize)]
+struct ErrorBody<'a> {
+    status: u16,
+    category: FailureCategory,
+    message: &'a str,
+}
+
+
+/// Constructs an error response in the given format.
+///
+/// The plain-text body consists of the lowercase reason phrase of the status code and the message,
+/// e.g. `service unavailable: warming up`.
+pub(crate) fn failure_response(
+    status: StatusCode,
+    category: FailureCategory,
+    message: &str,
+    format: ErrorBodyFormat,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let (content_type, body) = match format {
+        ErrorBodyFormat::PlainText => {
+            let reason = status.canonical_reason()
+                .unwrap_or("error")
+                .to_lowercase();
+            ("text/plain; charset=utf-8", format!("{}: {}", reason, message))
+        },
+        ErrorBodyFormat::Json => {
+            let error_body = ErrorBody {
+                status: status.as_u16(),
+                category,
+                message,
+            };
+            let json = serde_json::to_string(&error_body)
+                .expect("failed to serialize error body");
+            ("application/json", json)
+        },
+    };
+    Ok(
+        Response::builder()
+            .status(status)
+            .header("Content-Type", content_type)
+            .body(Full::new(Bytes::from(body)))
+            .expect("cannot construct HTTP error response")
+    )
+}
@@ -0,0 +1,130 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::client::{self, RadiatorClient};
+use crate::config::Config;
+use crate::decode::{Field, FIELD_TYPE_STRING, strip_statistic_prefix};
+use crate::radiator::RadiatorTransport;
+
+
+/// Converts a Radiator statistic name such as `Access requests` into a metric name fragment such
+/// as `access_requests`.
+fn to_metric_fragment(statistic: &str) -> String {
+    let mut ret = String::with_capacity(statistic.len());
+    for c in statistic.chars() {
+        if c.is_ascii_alphanumeric() {
+            ret.push(c.to_ascii_lowercase());
+        } else if !ret.ends_with('_') {
+            ret.push('_');
+        }
+    }
+    ret.trim_matches('_').to_owned()
+}
+
+fn guess_metric_kind(statistic: &str) -> &'static str {
+    if statistic.contains("Total") || statistic.contains("Count") {
+        "counter"
+    } else {
+        "gauge"
+    }
+}
+
+fn toml_string(value: &str) -> String {
+    toml::Value::String(value.to_owned()).to_string()
+}
+
+
+async fn describe(client: &RadiatorClient<'_>, object: &str) -> Result<Vec<Field>, Cow<'static, str>> {
+    match client.describe(object).await {
+        Ok(fields) => Ok(fields),
+        Err(client::Error::NoSuchObject) => Err(Cow::Owned(format!("Radiator object {} does not exist", object))),
+        Err(e) => Err(Cow::Owned(format!("failed to query Radiator info for {}: {}", object, e))),
+    }
+}
+
+fn write_metrics(
+    output: &mut String,
+    fields: &[Field],
+    statistic_aliases: &BTreeMap<String, String>,
+    statistic_prefix: Option<&str>,
+    table_prefix: &str,
+    metric_prefix: &str,
+) -> Result<(), std::fmt::Error> {
+    for field in fields {
+        if field.value_type == FIELD_TYPE_STRING {
+            // not a number; cannot be exported as a sample
+            continue;
+        }
+        // the statistics are known by their stripped names and aliases from now on
+        let name = strip_statistic_prefix(&field.name, statistic_prefix);
+        let statistic = statistic_aliases.get(name).map(|alias| alias.as_str()).unwrap_or(name);
+        let fragment = to_metric_fragment(statistic);
+        if fragment.is_empty() {
+            writeln!(output, "# skipped {} (cannot derive a metric name)", toml_string(statistic))?;
+            writeln!(output)?;
+            continue;
+        }
+
+        writeln!(output, "# Radiator type: {}", field.value_type)?;
+        writeln!(output, "[[{}]]", table_prefix)?;
+        writeln!(output, "metric = {}", toml_string(&format!("{}_{}", metric_prefix, fragment)))?;
+        writeln!(output, "kind = \"{}\"", guess_metric_kind(statistic))?;
+        writeln!(output, "help = {}", toml_string(statistic))?;
+        writeln!(output, "[[{}.samples]]", table_prefix)?;
+        writeln!(output, "labels = {{}}")?;
+        writeln!(output, "statistic = {}", toml_string(statistic))?;
+        writeln!(output)?;
+    }
+    Ok(())
+}
+
+
+/// Asks Radiator which statistics it offers and generates a skeleton configuration exporting all of
+/// them.
+///
+/// Top-level statistics are always included; per-object statistics are included for each of the
+/// given object kinds (e.g. `Client`), using the fields of the first object of that kind.
+pub(crate) async fn generate_config(
+    config: &Config,
+    transport: &dyn RadiatorTransport,
+    object_kinds: &[String],
+) -> Result<String, Cow<'static, str>> {
+    let client = RadiatorClient::new(config, transport);
+    let statistic_aliases = &config.statistic_aliases;
+    let statistic_prefix = config.radiator.strip_statistic_prefix.as_deref();
+    let mut output = String::new();
+
+    let top_level_fields = describe(&client, ".").await?;
+    writeln!(output, "## generated by prometheus-radiator-exporter {}", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(output, "## metric kinds are guessed from the statistic names; review them and the help texts before use").unwrap();
+    writeln!(output).unwrap();
+    writeln!(output, "## totals").unwrap();
+    writeln!(output).unwrap();
+    write_metrics(&mut output, &top_level_fields, statistic_aliases, statistic_prefix, "metrics", "radiator").unwrap();
+
+    for object_kind in object_kinds {
+        let object_fields = describe(&client, &format!("{}.0", object_kind)).await?;
+        let kind_fragment = to_metric_fragment(object_kind);
+
+        writeln!(output, "## per <{}>", object_kind).unwrap();
+        writeln!(output).unwrap();
+        writeln!(output, "[[per_object_metrics]]").unwrap();
+        writeln!(output, "kind = {}", toml_string(object_kind)).unwrap();
+        writeln!(output, "identifier_label = {}", toml_string(&kind_fragment)).unwrap();
+        writeln!(output).unwrap();
+        let object_fields: Vec<Field> = object_fields.into_iter()
+            .filter(|f| f.name != "Identifier")
+            .collect();
+        write_metrics(
+            &mut output,
+            &object_fields,
+            statistic_aliases,
+            statistic_prefix,
+            "per_object_metrics.metrics",
+            &format!("radiator_{}", kind_fragment),
+        ).unwrap();
+    }
+
+    Ok(output)
+}
@@ -0,0 +1,144 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::process::ExitCode;
+use std::time::Duration;
+
+use http_body_util::Empty;
+use hyper::{Request, StatusCode};
+use hyper::body::Bytes;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+use crate::config::{HttpVersion, WwwConfig};
+
+
+pub(crate) const HEALTHY_PATH: &str = "/-/healthy";
+
+// how long the whole health check may take
+const HEALTHCHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+
+#[derive(Debug)]
+enum Error {
+    Io(io::Error),
+    Tls(crate::tls::Error),
+    Http(hyper::Error),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Tls(e) => write!(f, "error setting up TLS: {}", e),
+            Self::Http(e) => write!(f, "HTTP error: {}", e),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Tls(e) => Some(e),
+            Self::Http(e) => Some(e),
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<crate::tls::Error> for Error {
+    fn from(value: crate::tls::Error) -> Self {
+        Self::Tls(value)
+    }
+}
+impl From<hyper::Error> for Error {
+    fn from(value: hyper::Error) -> Self {
+        Self::Http(value)
+    }
+}
+
+
+/// Returns the address at which the exporter running with the given configuration can be reached
+/// from the same host.
+fn local_address(config: &WwwConfig) -> SocketAddr {
+    let ip = match config.bind_address {
+        IpAddr::V4(a) if a.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(a) if a.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        other => other,
+    };
+    SocketAddr::new(ip, config.port)
+}
+
+
+/// Requests the health status over the given connection using the given HTTP version (HTTP/2 with
+/// prior knowledge if unencrypted).
+async fn request_status<I>(io: I, address: SocketAddr, scheme: &str, http_version: HttpVersion) -> Result<StatusCode, Error>
+    where I: AsyncRead + AsyncWrite + Send + Unpin + 'static
+{
+    let response = if http_version == HttpVersion::Http2 {
+        let (mut sender, connection) = hyper::client::conn::http2::handshake(TokioExecutor::new(), TokioIo::new(io)).await?;
+        tokio::spawn(connection);
+
+        // HTTP/2 takes the authority from the URI instead of the Host header
+        let request = Request::get(format!("{}://{}{}", scheme, address, HEALTHY_PATH))
+            .body(Empty::<Bytes>::new())
+            .expect("cannot construct health check request");
+        sender.send_request(request).await?
+    } else {
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+        tokio::spawn(connection);
+
+        let request = Request::get(HEALTHY_PATH)
+            .header("Host", address.to_string())
+            .body(Empty::<Bytes>::new())
+            .expect("cannot construct health check request");
+        sender.send_request(request).await?
+    };
+    Ok(response.status())
+}
+
+
+async fn check(config: &WwwConfig) -> Result<StatusCode, Error> {
+    let address = local_address(config);
+    // HTTP/1.1 unless the exporter does not speak it
+    let http_version = match config.http_version {
+        HttpVersion::Http2 => HttpVersion::Http2,
+        HttpVersion::Http1 | HttpVersion::Both => HttpVersion::Http1,
+    };
+    let stream = TcpStream::connect(address).await?;
+    match config.tls.as_ref() {
+        Some(tls_config) => {
+            let connector = crate::tls::make_healthcheck_connector(tls_config, http_version)?;
+            let tls_stream = connector.connect(ServerName::IpAddress(address.ip().into()), stream).await?;
+            request_status(tls_stream, address, "https", http_version).await
+        },
+        None => request_status(stream, address, "http", http_version).await,
+    }
+}
+
+
+/// Asks the exporter running with the given configuration on the same host whether it is healthy,
+/// e.g. for container health checks.
+///
+/// Outputs the reason if it is not.
+pub(crate) async fn run(config: &WwwConfig) -> ExitCode {
+    match tokio::time::timeout(HEALTHCHECK_TIMEOUT, check(config)).await {
+        Ok(Ok(status)) if status.is_success() => ExitCode::SUCCESS,
+        Ok(Ok(status)) => {
+            eprintln!("unhealthy: {} answered with {}", HEALTHY_PATH, status);
+            ExitCode::FAILURE
+        },
+        Ok(Err(e)) => {
+            eprintln!("unhealthy: {}", e);
+            ExitCode::FAILURE
+        },
+        Err(_) => {
+            eprintln!("unhealthy: no answer within {} seconds", HEALTHCHECK_TIMEOUT.as_secs());
+            ExitCode::FAILURE
+        },
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::Ordering;
+
+use crate::scrape::DescribedObject;
+use crate::self_metrics::{IDENTIFIER_CACHE_HITS, IDENTIFIER_CACHE_MISSES};
+
+
+pub(crate) static IDENTIFIER_CACHE: Mutex<IdentifierCache> = Mutex::new(IdentifierCache::new());
+
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    object: DescribedObject,
+    last_used: u64,
+}
+
+
+/// Remembers the identifiers and field types of Radiator objects by kind and index, sparing a
+/// `DESCRIBE` per object and scrape.
+///
+/// Once the cache is full, the least recently used entry is evicted. The cache is emptied whenever
+/// the connection to Radiator is re-established, since Radiator may have been restarted with a
+/// different configuration.
+#[derive(Debug)]
+pub(crate) struct IdentifierCache {
+    entries: BTreeMap<(String, usize), CacheEntry>,
+    use_counter: u64,
+    connection_generation: u64,
+}
+impl IdentifierCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            use_counter: 0,
+            connection_generation: 0,
+        }
+    }
+
+    fn check_generation(&mut self) {
+        let connection_generation = crate::radiator::connection_generation();
+        if self.connection_generation != connection_generation {
+            self.entries.clear();
+            self.connection_generation = connection_generation;
+        }
+    }
+
+    pub fn get(&mut self, kind: &str, index: usize) -> Option<DescribedObject> {
+        self.check_generation();
+        self.use_counter += 1;
+        match self.entries.get_mut(&(kind.to_owned(), index)) {
+            Some(entry) => {
+                entry.last_used = self.use_counter;
+                IDENTIFIER_CACHE_HITS.fetch_add(1, Ordering::Relaxed);
+                Some(entry.object.clone())
+            },
+            None => {
+                IDENTIFIER_CACHE_MISSES.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+        }
+    }
+
+    pub fn insert(&mut self, kind: &str, index: usize, object: DescribedObject, max_entries: usize) {
+        self.check_generation();
+        let key = (kind.to_owned(), index);
+        if !self.entries.contains_key(&key) {
+            while !self.entries.is_empty() && self.entries.len() >= max_entries {
+                let least_recently_used = self.entries.iter()
+                    .min_by_key(|(_key, entry)| entry.last_used)
+                    .map(|(key, _entry)| key.clone())
+                    .expect("no least recently used entry in non-empty cache?!");
+                self.entries.remove(&least_recently_used);
+            }
+        }
+        self.use_counter += 1;
+        self.entries.insert(key, CacheEntry {
+            object,
+            last_used: self.use_counter,
+        });
+    }
+
+    pub fn remove(&mut self, kind: &str, index: usize) {
+        self.entries.remove(&(kind.to_owned(), index));
+    }
+
+    /// Forgets all objects, e.g. because the configuration has been reloaded and the indexes may
+    /// now refer to different objects.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
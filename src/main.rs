@@ -1,40 +1,82 @@
+mod audit;
+mod client;
 mod config;
+mod debug;
+mod decode;
+mod failure;
+mod generate;
+mod healthcheck;
+mod identifier_cache;
+mod metadata;
 mod openmetrics;
+mod process_metrics;
+mod push;
+mod pushgateway;
 mod radiator;
+mod reload;
+mod remote_write;
+#[cfg(test)]
+mod replay;
+mod scrape;
+mod self_metrics;
+mod stats_cache;
+mod tls;
 
 
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::BTreeMap;
 use std::convert::Infallible;
 use std::ffi::OsString;
+use std::fmt::Write;
+use std::future::Future;
 use std::net::SocketAddr;
 use std::path::Path;
+use std::pin::Pin;
 use std::process::ExitCode;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use http_body_util::Full;
-use hyper::{Method, Request, Response};
+use hyper::{Method, Request, Response, StatusCode};
 use hyper::body::{Bytes, Incoming};
+use hyper::server::conn::{http1, http2};
 use hyper::service::service_fn;
 use hyper_util::rt::{TokioExecutor, TokioIo};
 use hyper_util::server::conn::auto::Builder;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpListener;
-use tokio::sync::Mutex;
-use toml;
-use tracing::{error, instrument, warn};
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{debug, error, info, instrument, warn};
 
-use crate::config::{CONFIG, Config};
-use crate::openmetrics::{MetricDatabase, Number};
-use crate::radiator::{connect_to_radiator, SOCKET_STATE, start_message_processor};
+use crate::config::{CONFIG, Config, ConnectionMode, HttpVersion, Problem, Problems};
+use crate::failure::{ErrorBodyFormat, failure_response, FailureCategory};
+use crate::openmetrics::{ExpositionFormat, MetricDatabase};
+use crate::radiator::{BreakerState, breaker_state, connect_to_radiator, delay_connection_attempt, RadiatorTransport, SOCKET_STATE, start_message_processor, TcpTransport};
+use crate::scrape::{run_background_scrapes, scrape_radiator, SNAPSHOTS};
+use crate::self_metrics::ScrapeInfo;
 
 
 const GIT_REVISION: &str = "<unknown git revision>";
 
+// upper bound for pre-allocating the output buffer, in case the previous scrape was an outlier
+const MAX_OUTPUT_PREALLOCATION: usize = 16 * 1024 * 1024;
+
+// how long to wait for a command to Radiator to finish when shutting down
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+
+// how long a client may take to complete the TLS handshake if www.http_idle_timeout_ms is not set
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// how the exporter talks to Radiator
+const TRANSPORT: &dyn RadiatorTransport = &TcpTransport;
+
+
+static LAST_OUTPUT_LENGTH: AtomicUsize = AtomicUsize::new(0);
 
-#[derive(Clone, Debug)]
-struct PerObjectStats {
-    pub identifier: String,
-    pub stats: HashMap<String, Number>,
-}
+
+// what serving an HTTP connection may fail with, whichever HTTP versions are spoken
+type ServerError = Box<dyn std::error::Error + Send + Sync>;
 
 
 fn return_500() -> Result<Response<Full<Bytes>>, Infallible> {
@@ -48,117 +90,157 @@ fn return_500() -> Result<Response<Full<Bytes>>, Infallible> {
 }
 
 
-fn decode_stats(response: &[u8]) -> Option<HashMap<String, Number>> {
-    // response format: b"STATS .\nkey1:value1\x01key2:value2\x01key3:value3"
-
-    // skip echoed command
-    let newline_index = match response.iter().position(|b| *b == b'\n') {
-        Some(i) => i,
-        None => {
-            error!("Radiator response {:?} does not contain a newline (splitting echoed command and actual response)", response);
-            return None;
-        },
-    };
-    let unechoed_response = &response[newline_index+1..];
-
-    // decode as UTF-8
-    let response_string = match std::str::from_utf8(unechoed_response) {
-        Ok(rs) => rs,
+fn handle_healthy() -> Result<Response<Full<Bytes>>, Infallible> {
+    // answering at all is proof enough
+    let response_res = Response::builder()
+        .status(200)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("healthy")));
+    match response_res {
+        Ok(r) => Ok(r),
         Err(e) => {
-            error!("Radiator response {:?} is not valid UTF-8: {}", response, e);
-            return None;
+            error!("failed to construct 200 response: {}", e);
+            return_500()
         },
-    };
-
-    // key-value pairs are delimited by U+0001 characters
-    let mut statistics = HashMap::new();
-    let key_value_pairs = response_string.split('\u{0001}');
-    for key_value_pair in key_value_pairs {
-        // keys and values are delimited by a colon (let's assume the first one)
-        let (key, value) = match key_value_pair.split_once(':') {
-            Some(kv) => kv,
-            None => {
-                warn!("statistics key-value pair {:?} does not contain colon; skipping", key_value_pair);
-                continue;
-            },
-        };
+    }
+}
 
-        // parse value
-        let value = match value.parse() {
-            Ok(v) => Number::Integer(v),
-            Err(_) => {
-                // integer failed; try float
-                match value.parse() {
-                    Ok(v) => Number::Float(v),
-                    Err(e) => {
-                        warn!("failed to parse value {:?} for statistic {:?} as an integer or floating-point value (skipping it): {}", value, key, e);
-                        continue;
-                    },
-                }
-            },
-        };
 
-        if let Some(old_value) = statistics.insert(key.to_owned(), value) {
-            warn!("duplicate statistic {:?}; overwriting old value {} with {}", key, old_value, value);
-        }
+fn handle_ready(config: &Config, error_format: ErrorBodyFormat) -> Result<Response<Full<Bytes>>, Infallible> {
+    // the connection may look fine while Radiator has stopped answering commands,
+    // so also take into account when the last scrape succeeded
+    if crate::scrape::warming_up() {
+        return failure_response(StatusCode::SERVICE_UNAVAILABLE, FailureCategory::NotReady, "warming up", error_format);
+    }
+    if crate::radiator::socket_gone() {
+        return failure_response(StatusCode::SERVICE_UNAVAILABLE, FailureCategory::RadiatorUnreachable, "connection to Radiator lost", error_format);
+    }
+    let last_success_age = crate::scrape::last_successful_scrape_age();
+    if last_success_age > Duration::from_millis(config.www.ready_max_scrape_age_ms) {
+        return failure_response(StatusCode::SERVICE_UNAVAILABLE, FailureCategory::NotReady, "last successful scrape is too old", error_format);
     }
 
-    Some(statistics)
+    let response_res = Response::builder()
+        .status(200)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Full::new(Bytes::from("ready")));
+    match response_res {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("failed to construct 200 response: {}", e);
+            return_500()
+        },
+    }
 }
 
 
-fn extract_identifier(response: &[u8]) -> Option<String> {
-    // response format: b"DESCRIBE ObjectType.2\nkey1:type1:value1\x01key2:type2:value2\x01key3:type3:value3"
+/// Writes comment lines describing the scrape, intended for humans reading the output.
+fn write_banner(output: &mut String, config: &Config, scrape_info: &ScrapeInfo) {
+    // background scrapes were taken some time ago
+    let scraped_at = SystemTime::now() - scrape_info.snapshot_age.unwrap_or(Duration::ZERO);
+    let scraped_at_unix = scraped_at.duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs_f64();
 
-    // skip echoed command
-    let newline_index = match response.iter().position(|b| *b == b'\n') {
-        Some(i) => i,
-        None => {
-            error!("Radiator response {:?} does not contain a newline (splitting echoed command and actual response)", response);
-            return None;
-        },
-    };
-    let unechoed_response = &response[newline_index+1..];
+    // credentials are deliberately left out
+    writeln!(output, "# prometheus-radiator-exporter {} ({})", env!("CARGO_PKG_VERSION"), GIT_REVISION).unwrap();
+    writeln!(output, "# Radiator: {}", SocketAddr::new(config.radiator.target, config.radiator.mgmt_port)).unwrap();
+    writeln!(output, "# scraped at: {:.3} (seconds since the Unix epoch)", scraped_at_unix).unwrap();
+}
 
-    // decode as UTF-8
-    let response_string = match std::str::from_utf8(unechoed_response) {
-        Ok(rs) => rs,
-        Err(e) => {
-            error!("Radiator response {:?} is not valid UTF-8: {}", response, e);
-            return None;
-        },
-    };
 
-    // key-type-value tuples are delimited by U+0001 characters
-    let key_type_value_tuples = response_string.split('\u{0001}');
-    for key_type_value_tuple in key_type_value_tuples {
-        // keys, types and values are delimited by colons (the first two)
-        let (key, type_value_pair) = match key_type_value_tuple.split_once(':') {
-            Some(ktvp) => ktvp,
-            None => {
-                warn!("statistics key-type-value tuple {:?} does not contain colon; skipping", key_type_value_tuple);
-                continue;
-            },
+/// Collects the metrics to expose: the most recent background scrapes or a fresh scrape of Radiator,
+/// along with the exporter's own metrics.
+///
+/// On failure, returns the HTTP status, failure category and message to report.
+pub(crate) async fn collect_metrics(
+    config: &Config,
+    transport: &dyn RadiatorTransport,
+) -> Result<(MetricDatabase, ScrapeInfo), (StatusCode, FailureCategory, &'static str)> {
+    let (mut metric_database, scrape_info) = if config.radiator.background_interval_ms.is_some() {
+        // serve the most recent background scrape
+        let snapshots_guard = SNAPSHOTS
+            .read().expect("SNAPSHOTS poisoned");
+        let Some(snapshot) = snapshots_guard.get(&None) else {
+            return Err((StatusCode::SERVICE_UNAVAILABLE, FailureCategory::NotReady, "no scrape has completed yet"));
         };
-        let (value_type, value) = match type_value_pair.split_once(':') {
-            Some(tv) => tv,
-            None => {
-                warn!("statistics key-type-value tuple {:?} does not contain second colon; skipping", key_type_value_tuple);
-                continue;
-            },
+        let mut metric_database = snapshot.scrape.database.clone();
+        let mut statistic_coverage = snapshot.scrape.statistic_coverage;
+        let mut scrape_info = ScrapeInfo {
+            truncated: snapshot.scrape.truncated,
+            snapshot_age: Some(snapshot.taken_at.elapsed()),
+            group_snapshot_ages: BTreeMap::new(),
+            radiator_up: breaker_state(&config.radiator) != BreakerState::Open,
+            statistic_coverage: None,
         };
 
-        if key == "Identifier" && value_type == "string" {
-            return Some(value.to_owned());
+        // add the scrape groups that have completed a scrape
+        for (group, group_snapshot) in snapshots_guard.iter() {
+            let Some(group) = group else { continue };
+            metric_database.merge(group_snapshot.scrape.database.clone());
+            scrape_info.truncated |= group_snapshot.scrape.truncated;
+            scrape_info.group_snapshot_ages.insert(group.clone(), group_snapshot.taken_at.elapsed());
+            statistic_coverage.add(group_snapshot.scrape.statistic_coverage);
         }
-    }
-
-    None
+        scrape_info.statistic_coverage = Some(statistic_coverage);
+        (metric_database, scrape_info)
+    } else {
+        // while the circuit breaker is open, do not even try
+        let scrape = if breaker_state(&config.radiator) == BreakerState::Open {
+            Err(FailureCategory::RadiatorUnreachable)
+        } else {
+            scrape_radiator(config, transport).await
+        };
+        match scrape {
+            Ok(scrape) => {
+                let scrape_info = ScrapeInfo {
+                    truncated: scrape.truncated,
+                    snapshot_age: None,
+                    group_snapshot_ages: BTreeMap::new(),
+                    radiator_up: true,
+                    statistic_coverage: Some(scrape.statistic_coverage),
+                };
+                (scrape.database, scrape_info)
+            },
+            Err(category) => {
+                if breaker_state(&config.radiator) != BreakerState::Open {
+                    // error already output
+                    return Err(match category {
+                        // Radiator may well be fine by the next scrape
+                        FailureCategory::ConnectionUnstable => (StatusCode::SERVICE_UNAVAILABLE, category, "connection to Radiator lost repeatedly; retries exhausted"),
+                        _ => (StatusCode::INTERNAL_SERVER_ERROR, category, "failed to scrape Radiator"),
+                    });
+                }
+                // report that Radiator is down instead of failing
+                let scrape_info = ScrapeInfo {
+                    truncated: false,
+                    snapshot_age: None,
+                    group_snapshot_ages: BTreeMap::new(),
+                    radiator_up: false,
+                    statistic_coverage: None,
+                };
+                (MetricDatabase::with_label_order(config.www.label_order), scrape_info)
+            },
+        }
+    };
+    crate::self_metrics::collect(&mut metric_database, config, Some(&scrape_info));
+    Ok((metric_database, scrape_info))
 }
 
 
-#[instrument(skip(request))]
-async fn handle_request(request: Request<Incoming>, remote_addr: SocketAddr) -> Result<Response<Full<Bytes>>, Infallible> {
+#[instrument(skip(request, transport))]
+async fn handle_request(
+    request: Request<Incoming>,
+    remote_addr: SocketAddr,
+    client_subject: Option<String>,
+    transport: &dyn RadiatorTransport,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if request.uri().path() == "/-/reload" {
+        let config = CONFIG
+            .get().expect("CONFIG not set?!");
+        return crate::reload::handle_reload(&request, remote_addr, client_subject.as_deref(), &config);
+    }
+
     if request.method() != Method::GET {
         let response_res = Response::builder()
             .status(405)
@@ -174,147 +256,66 @@ async fn handle_request(request: Request<Incoming>, remote_addr: SocketAddr) ->
         };
     }
 
-    let mut metric_database = MetricDatabase::new();
-
-    // ask Radiator for top-level statistics
-    let radiator_response = match crate::radiator::communicate(b"STATS .").await {
-        Ok(rr) => rr,
-        Err(e) => {
-            error!("failed to query Radiator global stats: {}", e);
-            return return_500();
-        },
-    };
-    let statistics = match decode_stats(&radiator_response) {
-        Some(s) => s,
-        None => {
-            // error already output
-            return return_500();
-        },
-    };
-
     let config = CONFIG
         .get().expect("CONFIG not set?!");
 
-    // run through per-object statistics
-    let mut object_type_to_statistics: HashMap<String, HashMap<usize, PerObjectStats>> = HashMap::new();
-    for per_object_statistic in &config.per_object_metrics {
-        // query the identifiers
-        let mut index_to_identifier: HashMap<usize, String> = HashMap::new();
-        for i in 0.. {
-            let command = format!("DESCRIBE {}.{}", per_object_statistic.kind, i);
-            let radiator_response = match crate::radiator::communicate(command.as_bytes()).await {
-                Ok(rr) => rr,
-                Err(e) => {
-                    error!("failed to query Radiator info for {}.{}: {}", per_object_statistic.kind, i, e);
-                    return return_500();
-                },
-            };
-            if radiator_response == b"NOSUCHOBJECT" {
-                // that is all
-                break;
-            }
-            let identifier = match extract_identifier(&radiator_response) {
-                Some(id) => id,
-                None => {
-                    warn!("Radiator object {}.{} does not have an identifier; skipping", per_object_statistic.kind, i);
-                    continue;
-                },
-            };
-            index_to_identifier.insert(i, identifier);
-        }
-
-        // pull statistics for each object
-        let mut index_to_statistics = HashMap::new();
-        for (&index, identifier) in &index_to_identifier {
-            let command = format!("STATS {}.{}", per_object_statistic.kind, index);
-            let radiator_response = match crate::radiator::communicate(command.as_bytes()).await {
-                Ok(rr) => rr,
-                Err(e) => {
-                    error!("failed to query Radiator stats for {}.{}: {}", per_object_statistic.kind, index, e);
-                    return return_500();
-                },
-            };
-            let stats = match decode_stats(&radiator_response) {
-                Some(s) => s,
-                None => {
-                    // error already output
-                    return return_500();
-                },
-            };
-            let per_object_stats = PerObjectStats {
-                identifier: identifier.clone(),
-                stats,
-            };
-            index_to_statistics.insert(index, per_object_stats);
-        }
-
-        object_type_to_statistics.insert(per_object_statistic.kind.clone(), index_to_statistics);
+    if request.uri().path() == crate::healthcheck::HEALTHY_PATH {
+        return handle_healthy();
     }
-
-    // populate metrics database
-    for metric_config in &config.metrics {
-        let metric = metric_database.get_or_insert(&metric_config.metric, metric_config.kind);
-        metric.set_unit(metric_config.unit.clone());
-        metric.set_help(metric_config.help.clone());
-        for sample in &metric_config.samples {
-            for label_name in sample.labels.keys() {
-                if !metric.has_label(label_name) {
-                    metric.add_label(label_name.to_owned());
-                }
-            }
-        }
-
-        for sample in &metric_config.samples {
-            let value = match statistics.get(&sample.statistic) {
-                Some(v) => v,
-                None => continue,
-            };
-            metric.add_sample(&sample.labels, value.clone());
-        }
+    let error_format = ErrorBodyFormat::from_request(&request);
+    if request.uri().path() == "/-/ready" {
+        return handle_ready(&config, error_format);
     }
-    for per_object_metrics in &config.per_object_metrics {
-        let Some(index_to_statistics) = object_type_to_statistics.get(&per_object_metrics.kind)
-            else { continue };
-        for metric_config in &per_object_metrics.metrics {
-            let metric = metric_database.get_or_insert(&metric_config.metric, metric_config.kind);
-            metric.set_unit(metric_config.unit.clone());
-            metric.set_help(metric_config.help.clone());
-            for sample in &metric_config.samples {
-                for label_name in sample.labels.keys() {
-                    if !metric.has_label(label_name) {
-                        metric.add_label(label_name.to_owned());
-                    }
-                }
-            }
-            metric.add_label(per_object_metrics.identifier_label.clone());
-
-            for per_object_statistics in index_to_statistics.values() {
-                for sample in &metric_config.samples {
-                    let mut all_labels = sample.labels.clone();
-                    all_labels.insert(per_object_metrics.identifier_label.clone(), per_object_statistics.identifier.clone());
-                    let value = match per_object_statistics.stats.get(&sample.statistic) {
-                        Some(v) => v,
-                        None => continue,
-                    };
-                    metric.add_sample(&all_labels, value.clone());
-                }
-            }
-        }
+    if request.uri().path() == "/self-metrics" {
+        return crate::self_metrics::handle_self_metrics(&config);
     }
+    if request.uri().path() == "/metadata" {
+        return crate::metadata::handle_metadata(&config);
+    }
+    if request.uri().path() == "/debug/stats" {
+        return crate::debug::handle_debug_stats(&request, remote_addr, client_subject.as_deref(), &config, transport).await;
+    }
+
+    let request_received_at = SystemTime::now();
+    let (mut metric_database, scrape_info) = match collect_metrics(&config, transport).await {
+        Ok(collected) => collected,
+        Err((status, category, message)) => return failure_response(status, category, message, error_format),
+    };
+    if config.www.emit_scrape_timestamp {
+        // the exporter's own metrics describe the time of the request
+        metric_database.set_timestamps(request_received_at);
+    }
+
+    // OpenMetrics is the only format so far
+    let format = ExpositionFormat::OpenMetrics;
 
     // collect the output
-    let mut output = String::new();
-    if let Err(e) = metric_database.write(&mut output) {
+    // pre-allocate based on the previous scrape (plus some headroom for growth)
+    let last_output_length = LAST_OUTPUT_LENGTH.load(Ordering::Relaxed);
+    let estimated_length = last_output_length
+        .saturating_add(last_output_length / 8)
+        .min(MAX_OUTPUT_PREALLOCATION);
+    let mut output = String::with_capacity(estimated_length);
+    if config.www.output_banner {
+        write_banner(&mut output, &config, &scrape_info);
+    }
+    if let Err(e) = metric_database.write(&mut output, format, &config.www.counter_suffix, config.www.help_newlines) {
         error!("error collecting metrics output: {}", e);
         return return_500();
     }
-    output.push_str("# EOF\n");
+
+    // if the config changed, the next estimate simply follows the new size
+    LAST_OUTPUT_LENGTH.store(output.len(), Ordering::Relaxed);
+
+    let output_length = output.len();
+    let body = Bytes::from(output);
+    crate::audit::record_response(&config.www, remote_addr, body.clone());
 
     let response_res = Response::builder()
         .status(200)
-        .header("Content-Type", crate::openmetrics::MIME_TYPE)
-        .header("Content-Length", &output.len().to_string())
-        .body(Full::new(Bytes::from(output)));
+        .header("Content-Type", format.content_type())
+        .header("Content-Length", &output_length.to_string())
+        .body(Full::new(body));
     match response_res {
         Ok(r) => Ok(r),
         Err(e) => {
@@ -325,26 +326,190 @@ async fn handle_request(request: Request<Incoming>, remote_addr: SocketAddr) ->
 }
 
 
+/// Keeps track of whether a connection is idle.
+#[derive(Debug)]
+struct ConnectionActivity {
+    requests_in_progress: AtomicUsize,
+    last_active: std::sync::Mutex<Instant>,
+}
+impl ConnectionActivity {
+    fn new() -> Self {
+        Self {
+            requests_in_progress: AtomicUsize::new(0),
+            last_active: std::sync::Mutex::new(Instant::now()),
+        }
+    }
+
+    fn request_started(&self) {
+        self.requests_in_progress.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn request_finished(&self) {
+        *self.last_active.lock().expect("last_active poisoned") = Instant::now();
+        self.requests_in_progress.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// When the connection will have been idle for the given timeout, assuming nothing happens in
+    /// the meantime.
+    fn idle_deadline(&self, idle_timeout: Duration) -> Instant {
+        if self.requests_in_progress.load(Ordering::SeqCst) > 0 {
+            // check again later
+            Instant::now() + idle_timeout
+        } else {
+            *self.last_active.lock().expect("last_active poisoned") + idle_timeout
+        }
+    }
+}
+
+
+async fn serve_connection<I>(
+    io: TokioIo<I>,
+    remote_addr: SocketAddr,
+    client_subject: Option<String>,
+    idle_timeout: Option<Duration>,
+    http_version: HttpVersion,
+    transport: &'static dyn RadiatorTransport,
+)
+        where I: AsyncRead + AsyncWrite + Send + Unpin + 'static {
+    let activity = Arc::new(ConnectionActivity::new());
+    let service_activity = Arc::clone(&activity);
+    let service = service_fn(move |req| {
+        let client_subject = client_subject.clone();
+        let activity = Arc::clone(&service_activity);
+        async move {
+            activity.request_started();
+            let response = handle_request(req, remote_addr, client_subject, transport).await;
+            activity.request_finished();
+            response
+        }
+    });
+    let auto_builder = Builder::new(TokioExecutor::new());
+    let mut connection: Pin<Box<dyn Future<Output = Result<(), ServerError>> + Send + '_>> = match http_version {
+        HttpVersion::Both => Box::pin(auto_builder.serve_connection(io, service)),
+        HttpVersion::Http1 => Box::pin(async {
+            http1::Builder::new()
+                .serve_connection(io, service).await
+                .map_err(ServerError::from)
+        }),
+        HttpVersion::Http2 => Box::pin(async {
+            http2::Builder::new(TokioExecutor::new())
+                .serve_connection(io, service).await
+                .map_err(ServerError::from)
+        }),
+    };
+
+    let connection_result = match idle_timeout {
+        None => connection.await,
+        Some(idle_timeout) => loop {
+            let idle_deadline = activity.idle_deadline(idle_timeout);
+            tokio::select! {
+                result = connection.as_mut() => break result,
+                _ = tokio::time::sleep_until(idle_deadline.into()) => {
+                    if activity.idle_deadline(idle_timeout) <= Instant::now() {
+                        // no request in progress; simply drop the connection
+                        debug!("closing connection from {} after being idle for {:?}", remote_addr, idle_timeout);
+                        break Ok(());
+                    }
+                },
+            }
+        },
+    };
+    if let Err(e) = connection_result {
+        error!("server error while handling connection from {}: {}", remote_addr, e);
+    }
+}
+
+
+/// Resolves once the exporter has been asked to shut down.
+#[cfg(unix)]
+async fn shutdown_requested() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())
+        .expect("failed to set up SIGTERM handler");
+    tokio::select! {
+        _ = sigterm.recv() => {},
+        result = tokio::signal::ctrl_c() => result.expect("failed to set up SIGINT handler"),
+    }
+}
+
+/// Resolves once the exporter has been asked to shut down.
+#[cfg(not(unix))]
+async fn shutdown_requested() {
+    // Windows has no SIGTERM; services and consoles deliver Ctrl+C (or Ctrl+Break) instead
+    tokio::signal::ctrl_c().await
+        .expect("failed to set up Ctrl+C handler");
+}
+
+
+/// Outputs the problems found with the configuration (the result of `--check`) and returns the
+/// corresponding exit code.
+fn report_problems(problems: &Problems, json: bool) -> ExitCode {
+    if json {
+        let output = serde_json::json!({
+            "valid": problems.is_empty(),
+            "problems": problems,
+        });
+        println!("{}", output);
+    } else if problems.is_empty() {
+        println!("configuration is valid");
+    } else {
+        for problem in problems.iter() {
+            println!("{}", problem.message);
+        }
+    }
+
+    if problems.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+
 #[tokio::main]
 async fn main() -> ExitCode {
     // parse args
     let args: Vec<OsString> = std::env::args_os().collect();
     let mut prog_name = Cow::Borrowed("prometheus-radiator-exporter");
-    if let Some(pn) = args.get(0) {
+    if let Some(pn) = args.first() {
         prog_name = pn.to_string_lossy();
     }
-    let output_usage =
-        args.len() < 1
+    let generate_config = args.get(1)
+        .map(|s| s == "--generate-config")
+        .unwrap_or(false);
+    let healthcheck = args.get(1)
+        .map(|s| s == "--healthcheck")
+        .unwrap_or(false);
+    let check = args.get(1)
+        .map(|s| s == "--check")
+        .unwrap_or(false);
+    let check_json = check && args.len() == 5 && args[4] == "json";
+    let output_usage = if generate_config {
+        args.len() < 3
+    } else if healthcheck {
+        args.len() != 3
+    } else if check {
+        args.len() != 3
+        && !(args.len() == 5 && args[3] == "--format" && (args[4] == "text" || args[4] == "json"))
+    } else {
+        args.is_empty()
         || args.len() > 2
         || args.get(1)
-            .map(|s| s.to_string_lossy().starts_with("-"))
-            .unwrap_or(false);
+            .map(|s| s.to_string_lossy().starts_with("-") && s != "-")
+            .unwrap_or(false)
+    };
     if output_usage {
         eprintln!("prometheus-radiator-exporter {} {}", env!("CARGO_PKG_VERSION"), GIT_REVISION);
         eprintln!("Usage: {} [CONFIG.TOML]", prog_name);
+        eprintln!("       (a CONFIG.TOML of - reads the configuration from standard input)");
+        eprintln!("       {} --generate-config CONFIG.TOML [OBJECTKIND...]", prog_name);
+        eprintln!("       {} --healthcheck CONFIG.TOML", prog_name);
+        eprintln!("       {} --check CONFIG.TOML [--format text|json]", prog_name);
         return ExitCode::FAILURE;
     }
-    let config_path = if let Some(config_path_os) = args.get(1) {
+    let config_path_os = if generate_config || healthcheck || check { args.get(2) } else { args.get(1) };
+    let config_path = if let Some(config_path_os) = config_path_os {
         Path::new(config_path_os)
     } else {
         Path::new("config.toml")
@@ -352,50 +517,204 @@ async fn main() -> ExitCode {
 
     // load config
     let config: Config = {
-        let config_string = std::fs::read_to_string(config_path)
-            .expect("failed to load config file");
-        toml::from_str(&config_string)
-            .expect("failed to parse config file")
+        let read_result = if config_path == Path::new("-") {
+            std::io::read_to_string(std::io::stdin())
+        } else {
+            std::fs::read_to_string(config_path)
+        };
+        let config_string = match read_result {
+            Ok(cs) => cs,
+            Err(e) => {
+                let message = format!("failed to read config file {}: {}", config_path.display(), e);
+                if check {
+                    return report_problems(&Problem::general(message).into(), check_json);
+                }
+                eprintln!("{}", message);
+                return ExitCode::FAILURE;
+            },
+        };
+        let parse_result = crate::config::parse(&config_string);
+        if check {
+            // nothing is started; only the configuration is examined
+            let problems = parse_result.err().unwrap_or_default();
+            return report_problems(&problems, check_json);
+        }
+        match parse_result {
+            Ok(c) => c,
+            Err(e) => {
+                for problem in e.iter() {
+                    eprintln!("error in configuration: {}", problem.message);
+                }
+                return ExitCode::FAILURE;
+            },
+        }
     };
-    if let Err(e) = crate::config::check(&config) {
-        panic!("error in configuration: {}", e);
-    }
     CONFIG
         .set(config.clone()).expect("CONFIG already set?!");
+    if config_path != Path::new("-") {
+        // standard input cannot be read again
+        crate::reload::CONFIG_PATH
+            .set(config_path.to_owned()).expect("CONFIG_PATH already set?!");
+    }
+
+    if healthcheck {
+        // the exporter being checked is a different process
+        return crate::healthcheck::run(&config.www).await;
+    }
 
     // enable tracing
-    let (non_blocking_stdout, _guard) = tracing_appender::non_blocking(std::io::stdout());
+    // (when generating a config, stdout is reserved for the output)
+    let (non_blocking_writer, _guard) = if generate_config {
+        tracing_appender::non_blocking(std::io::stderr())
+    } else {
+        tracing_appender::non_blocking(std::io::stdout())
+    };
     tracing_subscriber::fmt()
-        .with_writer(non_blocking_stdout)
+        .with_writer(non_blocking_writer)
         .init();
 
+    if config.www.counter_suffix != crate::config::OPENMETRICS_COUNTER_SUFFIX {
+        warn!(
+            "www.counter_suffix is {:?} instead of {:?}; the output does not conform to OpenMetrics",
+            config.www.counter_suffix, crate::config::OPENMETRICS_COUNTER_SUFFIX,
+        );
+    }
+
+    // give the first scrape some time before declaring the exporter unready
+    crate::scrape::record_successful_scrape();
+
     // launch the reader
-    let mut socket_state = start_message_processor();
+    let mut socket_state = start_message_processor(&config.radiator);
 
     // attempt initial connection to Radiator
-    connect_to_radiator(&config.radiator, &mut socket_state).await
-        .expect("failed to connect to Radiator management port");
+    // (a configuration generator run is interactive and should not wait;
+    // in per-scrape mode, each scrape connects by itself)
+    if generate_config || config.radiator.connection_mode == ConnectionMode::Persistent {
+        if !generate_config {
+            delay_connection_attempt(&config.radiator).await;
+        }
+        connect_to_radiator(&config.radiator, &mut socket_state).await
+            .expect("failed to connect to Radiator management port");
+    }
     SOCKET_STATE
         .set(Mutex::new(socket_state)).expect("SOCKET_STATE already set?!");
 
+    if generate_config {
+        let object_kinds: Vec<String> = args[3..].iter()
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        return match crate::generate::generate_config(&config, TRANSPORT, &object_kinds).await {
+            Ok(generated) => {
+                print!("{}", generated);
+                ExitCode::SUCCESS
+            },
+            Err(e) => {
+                eprintln!("failed to generate config: {}", e);
+                ExitCode::FAILURE
+            },
+        };
+    }
+
+    if config.radiator.hot_standby {
+        crate::radiator::start_standby();
+    }
+    if let Some(idle_timeout_ms) = config.radiator.idle_timeout_ms {
+        crate::radiator::start_idle_reaper(Duration::from_millis(idle_timeout_ms));
+    }
+
+    if config.radiator.warmup {
+        crate::scrape::start_warm_up();
+        tokio::spawn(crate::scrape::warm_up(TRANSPORT));
+    }
+
+    if let Some(background_interval_ms) = config.radiator.background_interval_ms {
+        tokio::spawn(async move {
+            run_background_scrapes(Duration::from_millis(background_interval_ms), None, TRANSPORT).await
+        });
+        for scrape_group in &config.scrape_groups {
+            let interval = Duration::from_millis(scrape_group.interval_ms);
+            let group = Some(scrape_group.name.clone());
+            tokio::spawn(async move {
+                run_background_scrapes(interval, group, TRANSPORT).await
+            });
+        }
+    }
+
+    if let Some(remote_write) = config.remote_write.as_ref() {
+        let target = crate::remote_write::make_target(remote_write)
+            .expect("failed to set up TLS for remote-write");
+        tokio::spawn(crate::remote_write::run_pushes(target, TRANSPORT));
+    }
+    if let Some(pushgateway) = config.pushgateway.as_ref() {
+        let target = crate::pushgateway::make_target(pushgateway)
+            .expect("failed to set up TLS for the Pushgateway");
+        tokio::spawn(crate::pushgateway::run_pushes(target, TRANSPORT));
+    }
+
     // listen for HTTP
     let bind_addr = SocketAddr::from((config.www.bind_address, config.www.port));
     let listener = TcpListener::bind(bind_addr).await
         .expect("failed to create TCP listening socket");
+    let http_version = config.www.http_version;
+    let tls_acceptor = config.www.tls.as_ref()
+        .map(|tls_config| crate::tls::make_acceptor(tls_config, http_version).expect("failed to set up TLS"));
+    info!(event = "listening", address = %bind_addr, tls = tls_acceptor.is_some(), "serving metrics over {}", http_version.description());
+    let idle_timeout = config.www.http_idle_timeout_ms.map(Duration::from_millis);
+    let connection_limit = Arc::new(Semaphore::new(config.www.max_connections.unwrap_or(Semaphore::MAX_PERMITS)));
+    let shutdown = shutdown_requested();
+    tokio::pin!(shutdown);
     loop {
-        let (stream, remote_addr) = listener.accept().await
-            .expect("failed to accept incoming TCP connection");
-        let io = TokioIo::new(stream);
+        // if too many connections are open, wait for one to close before accepting the next
+        // (further clients wait in the listen backlog)
+        let accept_permit_and_connection = async {
+            let permit = Arc::clone(&connection_limit)
+                .acquire_owned().await
+                .expect("connection limit semaphore closed");
+            let accepted = listener.accept().await;
+            (permit, accepted)
+        };
+        let (connection_permit, (stream, remote_addr)) = tokio::select! {
+            (permit, accepted) = accept_permit_and_connection => (
+                permit,
+                accepted.expect("failed to accept incoming TCP connection"),
+            ),
+            _ = &mut shutdown => {
+                info!(event = "shutdown", "shutdown requested; exiting");
+                crate::radiator::shut_down_connection(SHUTDOWN_DEADLINE).await;
+                return ExitCode::SUCCESS;
+            },
+        };
+        if config.www.tcp_nodelay {
+            if let Err(e) = stream.set_nodelay(true) {
+                warn!("failed to set TCP_NODELAY on connection from {}: {}", remote_addr, e);
+            }
+        }
+        let tls_acceptor = tls_acceptor.clone();
         tokio::task::spawn(async move {
-            let connection_result = Builder::new(TokioExecutor::new())
-                .http1()
-                .http2()
-                .serve_connection(io, service_fn(move |req| async move {
-                    handle_request(req, remote_addr).await
-                }))
-                .await;
-            if let Err(e) = connection_result {
-                error!("server error while handling connection from {}: {}", remote_addr, e);
+            // hold the permit until the connection is done
+            let _connection_permit = connection_permit;
+            match tls_acceptor {
+                Some(acceptor) => {
+                    // clients without a valid certificate (if one is required) are rejected here
+                    // otherwise, a client that never completes the handshake holds its connection slot forever
+                    let handshake_timeout = idle_timeout.unwrap_or(TLS_HANDSHAKE_TIMEOUT);
+                    let tls_stream = match tokio::time::timeout(handshake_timeout, acceptor.accept(stream)).await {
+                        Ok(Ok(ts)) => ts,
+                        Ok(Err(e)) => {
+                            warn!("TLS handshake with {} failed: {}", remote_addr, e);
+                            return;
+                        },
+                        Err(_) => {
+                            warn!("TLS handshake with {} did not complete within {:?}; closing connection", remote_addr, handshake_timeout);
+                            return;
+                        },
+                    };
+                    let client_subject = crate::tls::client_subject(tls_stream.get_ref().1);
+                    serve_connection(TokioIo::new(tls_stream), remote_addr, client_subject, idle_timeout, http_version, TRANSPORT).await;
+                },
+                None => {
+                    serve_connection(TokioIo::new(stream), remote_addr, None, idle_timeout, http_version, TRANSPORT).await;
+                },
             }
         });
     }
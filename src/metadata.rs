@@ -0,0 +1,100 @@
+use std::convert::Infallible;
+
+use http_body_util::Full;
+use hyper::Response;
+use hyper::body::Bytes;
+use serde::Serialize;
+use tracing::error;
+
+use crate::config::{Config, MetricConfig, PerObjectMetricConfig};
+use crate::openmetrics::MetricKind;
+
+
+/// How the exporter is configured to produce a metric.
+#[derive(Clone, Debug, Serialize)]
+struct MetricMetadata<'a> {
+    metric: &'a str,
+    kind: MetricKind,
+    help: Option<String>,
+    unit: Option<String>,
+
+    /// The object kinds whose statistics are queried; empty for statistics of the server as a whole.
+    object_kinds: Vec<&'a str>,
+
+    statistics: Vec<&'a str>,
+}
+impl<'a> MetricMetadata<'a> {
+    fn from_metric_config(metric_config: &'a MetricConfig, per_object_metrics: Option<&'a PerObjectMetricConfig>) -> Self {
+        // per-object help and unit texts are templates
+        let render = |template: &String| match per_object_metrics {
+            Some(pom) => pom.render_template(template),
+            None => template.clone(),
+        };
+
+        let mut statistics: Vec<&str> = metric_config.samples.iter()
+            .map(|sample| sample.statistic.as_str())
+            .collect();
+        if let Some(sum_statistic) = metric_config.sum_statistic.as_ref() {
+            statistics.push(sum_statistic.as_str());
+        }
+
+        Self {
+            metric: &metric_config.metric,
+            kind: metric_config.kind,
+            help: metric_config.help.as_ref().map(render),
+            unit: metric_config.unit.as_ref().map(render),
+            object_kinds: per_object_metrics
+                .map(|pom| pom.all_kinds().collect())
+                .unwrap_or_default(),
+            statistics,
+        }
+    }
+}
+
+
+/// Collects the metadata of all configured metrics, in configuration order.
+fn collect(config: &Config) -> Vec<MetricMetadata<'_>> {
+    let mut metadata: Vec<MetricMetadata> = config.metrics.iter()
+        .map(|metric_config| MetricMetadata::from_metric_config(metric_config, None))
+        .collect();
+    for per_object_metrics in &config.per_object_metrics {
+        for metric_config in &per_object_metrics.metrics {
+            metadata.push(MetricMetadata::from_metric_config(metric_config, Some(per_object_metrics)));
+        }
+    }
+    for info_metric in &config.info_metrics {
+        metadata.push(MetricMetadata {
+            metric: &info_metric.metric,
+            kind: MetricKind::Info,
+            help: info_metric.help.clone(),
+            unit: None,
+            object_kinds: Vec::new(),
+            statistics: info_metric.labels.values().map(|s| s.as_str()).collect(),
+        });
+    }
+    metadata
+}
+
+
+/// Handles `/metadata`, returning the configured metrics as JSON without querying Radiator.
+pub(crate) fn handle_metadata(config: &Config) -> Result<Response<Full<Bytes>>, Infallible> {
+    let body = match serde_json::to_string_pretty(&collect(config)) {
+        Ok(b) => b,
+        Err(e) => {
+            error!("failed to serialize metric metadata: {}", e);
+            return crate::return_500();
+        },
+    };
+
+    let response_res = Response::builder()
+        .status(200)
+        .header("Content-Type", "application/json")
+        .body(Full::new(Bytes::from(body)));
+    match response_res {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("failed to construct 200 response: {}", e);
+            crate::return_500()
+        },
+    }
+}
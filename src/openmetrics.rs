@@ -1,37 +1,91 @@
-use std::collections::{BTreeMap, BTreeSet};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::btree_map::Entry;
 use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use serde::{Deserialize, Serialize};
 
 
-pub const MIME_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+/// The suffix of the name of the sample of an info metric.
+const INFO_SUFFIX: &str = "_info";
+
+/// The shortest maximum label value length that leaves room for a prefix next to the hash suffix
+/// appended by [`truncate_label_value`].
+pub const MIN_MAX_LABEL_VALUE_LENGTH: usize = 16;
 
 
 #[derive(Clone, Debug, Default)]
 pub struct MetricDatabase {
     name_to_metric: BTreeMap<String, Metric>,
+    label_order: LabelOrder,
 }
 impl MetricDatabase {
-    pub fn new() -> Self {
+    /// Creates a database whose metrics output their labels in the given order.
+    pub fn with_label_order(label_order: LabelOrder) -> Self {
         Self {
             name_to_metric: BTreeMap::new(),
+            label_order,
         }
     }
 
     pub fn get_or_insert(&mut self, name: &str, kind: MetricKind) -> &mut Metric {
+        let label_order = self.label_order;
         self.name_to_metric.entry(name.to_owned())
-            .or_insert_with(|| Metric::new(name.to_owned(), kind))
+            .or_insert_with(|| Metric::new(name.to_owned(), kind, label_order))
+    }
+
+    /// Adds the metrics of another database to this one.
+    ///
+    /// The samples of metrics contained in both are combined, which requires them to be of the same
+    /// kind and to have the same labels.
+    pub fn merge(&mut self, other: MetricDatabase) {
+        for (name, other_metric) in other.name_to_metric {
+            match self.name_to_metric.entry(name) {
+                Entry::Vacant(vacant) => {
+                    vacant.insert(other_metric);
+                },
+                Entry::Occupied(mut occupied) => occupied.get_mut().merge_samples(other_metric),
+            }
+        }
+    }
+
+    /// Attaches the given timestamp to all samples that do not have one yet.
+    ///
+    /// Prometheus treats samples with explicit timestamps differently: series that disappear are not
+    /// marked stale but linger for up to five minutes, and samples that are too old for the head
+    /// block of the TSDB are rejected. This is only meant for setups that need samples aligned
+    /// exactly to the time of the scrape.
+    pub fn set_timestamps(&mut self, timestamp: SystemTime) {
+        for metric in self.name_to_metric.values_mut() {
+            for (_value, sample_timestamp) in metric.samples.values_mut() {
+                sample_timestamp.get_or_insert(timestamp);
+            }
+        }
     }
 
-    pub fn metrics(&self) -> impl Iterator<Item = (&String, &Metric)> {
-        self.name_to_metric.iter()
+    /// Returns all samples as individual series, e.g. for pushing them via remote-write.
+    pub fn flat_samples(&self, counter_suffix: &str) -> Vec<FlatSample> {
+        let mut flat_samples = Vec::new();
+        for metric in self.name_to_metric.values() {
+            metric.flatten_into(counter_suffix, &mut flat_samples);
+        }
+        flat_samples
     }
 
-    pub fn write<W: fmt::Write>(&self, mut writer: W) -> Result<(), fmt::Error> {
+    /// Writes all metrics in the given format, including the trailer demanded by the format.
+    pub fn write<W: fmt::Write>(
+        &self,
+        mut writer: W,
+        format: ExpositionFormat,
+        counter_suffix: &str,
+        help_newlines: NewlineEscaping,
+    ) -> Result<(), fmt::Error> {
         for metric in self.name_to_metric.values() {
-            metric.write(&mut writer)?;
+            metric.write(&mut writer, format, counter_suffix, help_newlines)?;
         }
-        Ok(())
+        write!(writer, "{}", format.trailer())
     }
 }
 
@@ -41,16 +95,19 @@ pub struct Metric {
     kind: MetricKind,
     help: Option<String>,
     unit: Option<String>,
-    label_names: BTreeSet<String>,
-    samples: BTreeMap<Vec<String>, Number>,
+    label_names: Vec<String>,
+    label_order: LabelOrder,
+    samples: BTreeMap<Vec<String>, (Value, Option<SystemTime>)>,
+    precision: Option<usize>,
+    sample_order: Option<SampleOrder>,
 }
 impl Metric {
-    pub fn new(name: String, kind: MetricKind) -> Self {
+    pub fn new(name: String, kind: MetricKind, label_order: LabelOrder) -> Self {
         // metricname = metricname-initial-char 0*metricname-char
         // metricname-char = metricname-initial-char / DIGIT
         // metricname-initial-char = ALPHA / "_" / ":"
-        assert!(name.len() > 0);
-        let first_name_char = name.chars().nth(0).unwrap();
+        assert!(!name.is_empty());
+        let first_name_char = name.chars().next().unwrap();
         assert!(first_name_char.is_ascii_alphabetic() || first_name_char == '_' || first_name_char == ':');
         assert!(name.chars().skip(1).all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_' || c == ':'));
 
@@ -59,14 +116,29 @@ impl Metric {
             kind,
             help: None,
             unit: None,
-            label_names: BTreeSet::new(),
+            label_names: Vec::new(),
+            label_order,
             samples: BTreeMap::new(),
+            precision: None,
+            sample_order: None,
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn help(&self) -> Option<&str> {
+        self.help.as_deref()
+    }
+
+    pub fn unit(&self) -> Option<&str> {
+        self.unit.as_deref()
+    }
+
     pub fn set_help(&mut self, help: Option<String>) {
         if let Some(help_str) = help.as_ref() {
-            assert!(help_str.len() > 0);
+            assert!(!help_str.is_empty());
             // otherwise, help string may be anything
         }
 
@@ -76,15 +148,26 @@ impl Metric {
     pub fn set_unit(&mut self, unit: Option<String>) {
         // all are metricname-char
         if let Some(unit_str) = unit.as_ref() {
-            assert!(unit_str.len() > 0);
+            assert!(!unit_str.is_empty());
             assert!(unit_str.chars().all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_' || c == ':'));
         }
 
         self.unit = unit;
     }
 
+    /// Sets the number of decimal places to which floating-point values are rounded on output.
+    pub fn set_precision(&mut self, precision: Option<usize>) {
+        self.precision = precision;
+    }
+
+    /// Sets the order in which the samples are output; by default, they are ordered by their label
+    /// values.
+    pub fn set_sample_order(&mut self, sample_order: Option<SampleOrder>) {
+        self.sample_order = sample_order;
+    }
+
     pub fn has_label(&self, label: &str) -> bool {
-        self.label_names.contains(label)
+        self.label_names.iter().any(|l| l == label)
     }
 
     pub fn add_label(&mut self, label: String) {
@@ -93,14 +176,45 @@ impl Metric {
         // label-name = label-name-initial-char *label-name-char
         // label-name-char = label-name-initial-char / DIGIT
         // label-name-initial-char = ALPHA / "_"
-        let first_label_char = label.chars().nth(0).unwrap();
+        let first_label_char = label.chars().next().unwrap();
         assert!(first_label_char.is_ascii_alphabetic() || first_label_char == '_');
         assert!(label.chars().skip(1).all(|c| c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '_'));
 
-        self.label_names.insert(label);
+        match self.label_order {
+            LabelOrder::Sorted => {
+                if let Err(index) = self.label_names.binary_search(&label) {
+                    self.label_names.insert(index, label);
+                }
+            },
+            LabelOrder::Declared => {
+                if !self.has_label(&label) {
+                    self.label_names.push(label);
+                }
+            },
+        }
+    }
+
+    /// Takes over the samples of another metric of the same name.
+    fn merge_samples(&mut self, other: Metric) {
+        assert_eq!(self.kind, other.kind);
+        assert_eq!(self.label_names, other.label_names);
+        self.samples.extend(other.samples);
     }
 
     pub fn add_sample(&mut self, labels: &BTreeMap<String, String>, value: Number) {
+        self.add_value(labels, Value::Number(value));
+    }
+
+    pub fn add_value(&mut self, labels: &BTreeMap<String, String>, value: Value) {
+        self.add_value_at(labels, value, None);
+    }
+
+    /// Adds a sample along with the time its value was obtained, if it differs from that of the
+    /// other samples.
+    pub fn add_value_at(&mut self, labels: &BTreeMap<String, String>, value: Value, timestamp: Option<SystemTime>) {
+        // histograms and only histograms go into gauge histogram metrics
+        assert_eq!(matches!(value, Value::Histogram(_)), self.kind == MetricKind::GaugeHistogram);
+
         let mut label_values = Vec::with_capacity(self.label_names.len());
         for label_name in &self.label_names {
             let label_value = match labels.get(label_name) {
@@ -110,50 +224,297 @@ impl Metric {
             label_values.push(label_value.clone());
         }
         for key in labels.keys() {
-            if !self.label_names.contains(key) {
+            if !self.has_label(key) {
                 panic!("unknown label {:?}", key);
             }
         }
-        self.samples.insert(label_values, value);
+        self.samples.insert(label_values, (value, timestamp));
+    }
+
+    /// The name of the metric family, i.e. without the counter suffix if the name already ends with
+    /// it (people tend to name their counters `something_total`).
+    pub fn family_name<'a>(&'a self, counter_suffix: &str) -> &'a str {
+        family_name(&self.name, self.kind, counter_suffix)
     }
 
-    pub fn write<W: fmt::Write>(&self, mut writer: W) -> Result<(), fmt::Error> {
-        write!(writer, "# TYPE {} {}\n", self.name, self.kind.as_openmetrics())?;
+    pub fn write<W: fmt::Write>(
+        &self,
+        mut writer: W,
+        format: ExpositionFormat,
+        counter_suffix: &str,
+        help_newlines: NewlineEscaping,
+    ) -> Result<(), fmt::Error> {
+        if self.samples.is_empty() {
+            // do not output a family header without any samples
+            return Ok(());
+        }
+
+        let family_name = self.family_name(counter_suffix);
+
+        // the Prometheus text format has no metric families; its headers refer to the samples of the
+        // same name, and gauge histograms (whose samples have different names) get none at all
+        if let Some(type_name) = format.type_name(self.kind) {
+            let header_name = match format {
+                ExpositionFormat::OpenMetrics => Cow::Borrowed(family_name),
+                ExpositionFormat::PrometheusText => Cow::Owned(format!("{}{}", family_name, self.kind.openmetrics_metric_suffix(counter_suffix))),
+            };
+
+            writeln!(writer, "# TYPE {} {}", header_name, type_name)?;
 
-        if let Some(unit) = self.unit.as_ref() {
-            write!(writer, "# UNIT {} {}\n", self.name, unit)?;
+            if let Some(unit) = self.unit.as_ref().filter(|_| format == ExpositionFormat::OpenMetrics) {
+                writeln!(writer, "# UNIT {} {}", header_name, unit)?;
+            }
+
+            if let Some(help) = self.help.as_ref() {
+                write!(writer, "# HELP {} ", header_name)?;
+                match format {
+                    ExpositionFormat::OpenMetrics => escape_openmetrics_into(help, help_newlines, &mut writer)?,
+                    ExpositionFormat::PrometheusText => escape_text_help_into(help, help_newlines, &mut writer)?,
+                }
+                writeln!(writer)?;
+            }
         }
 
-        if let Some(help) = self.help.as_ref() {
-            write!(writer, "# HELP {} ", self.name)?;
-            escape_openmetrics_into(help, &mut writer)?;
-            write!(writer, "\n")?;
+        let mut samples: Vec<_> = self.samples.iter().collect();
+        if let Some(sample_order) = self.sample_order.as_ref() {
+            // the samples are already ordered by all label values, which breaks ties
+            if let Some(label_index) = self.label_names.iter().position(|l| l == &sample_order.label) {
+                samples.sort_by(|(values_a, _), (values_b, _)| sample_order.compare(&values_a[label_index], &values_b[label_index]));
+            }
         }
 
-        for (label_values, sample_value) in &self.samples {
+        for (label_values, (sample_value, timestamp)) in samples {
             assert_eq!(self.label_names.len(), label_values.len());
+            let timestamp = TimestampSuffix(*timestamp, format);
 
-            write!(writer, "{}{}", self.name, self.kind.openmetrics_metric_suffix())?;
-            if self.label_names.len() > 0 {
-                write!(writer, "{}", '{')?;
-                let mut first_label = true;
-                for (label_key, label_value) in self.label_names.iter().zip(label_values.iter()) {
-                    if first_label {
-                        first_label = false;
-                    } else {
-                        write!(writer, ",")?;
+            match sample_value {
+                Value::Number(number) => {
+                    write!(writer, "{}{}", family_name, self.kind.openmetrics_metric_suffix(counter_suffix))?;
+                    self.write_labels(&mut writer, label_values, None)?;
+                    writeln!(writer, " {}{}", number.rounded(self.precision), timestamp)?;
+                },
+                Value::Histogram(histogram) => {
+                    for (upper_bound, bucket_value) in &histogram.buckets {
+                        write!(writer, "{}_bucket", family_name)?;
+                        self.write_labels(&mut writer, label_values, Some(upper_bound))?;
+                        writeln!(writer, " {}{}", bucket_value.rounded(self.precision), timestamp)?;
                     }
-                    write!(writer, "{}=\"", label_key)?;
-                    escape_openmetrics_into(label_value, &mut writer)?;
-                    write!(writer, "\"")?;
-                }
-                write!(writer, "{}", '}')?;
+                    write!(writer, "{}_gcount", family_name)?;
+                    self.write_labels(&mut writer, label_values, None)?;
+                    writeln!(writer, " {}{}", histogram.count.rounded(self.precision), timestamp)?;
+                    if let Some(sum) = histogram.sum {
+                        write!(writer, "{}_gsum", family_name)?;
+                        self.write_labels(&mut writer, label_values, None)?;
+                        writeln!(writer, " {}{}", sum.rounded(self.precision), timestamp)?;
+                    }
+                },
             }
-            write!(writer, " {}\n", sample_value)?;
         }
 
         Ok(())
     }
+
+    fn flatten_into(&self, counter_suffix: &str, flat_samples: &mut Vec<FlatSample>) {
+        let family_name = self.family_name(counter_suffix);
+        for (label_values, (sample_value, timestamp)) in &self.samples {
+            let make_sample = |suffix: &str, le: Option<&str>, number: Number| {
+                let mut labels: Vec<(String, String)> = self.label_names.iter()
+                    .cloned()
+                    .zip(label_values.iter().cloned())
+                    .collect();
+                if let Some(le) = le {
+                    labels.push(("le".to_owned(), le.to_owned()));
+                }
+                labels.push(("__name__".to_owned(), format!("{}{}", family_name, suffix)));
+                labels.sort_unstable();
+                FlatSample {
+                    labels,
+                    value: number.as_f64(),
+                    timestamp: *timestamp,
+                }
+            };
+
+            match sample_value {
+                Value::Number(number) => {
+                    flat_samples.push(make_sample(self.kind.openmetrics_metric_suffix(counter_suffix), None, *number));
+                },
+                Value::Histogram(histogram) => {
+                    for (upper_bound, bucket_value) in &histogram.buckets {
+                        flat_samples.push(make_sample("_bucket", Some(upper_bound), *bucket_value));
+                    }
+                    flat_samples.push(make_sample("_gcount", None, histogram.count));
+                    if let Some(sum) = histogram.sum {
+                        flat_samples.push(make_sample("_gsum", None, sum));
+                    }
+                },
+            }
+        }
+    }
+
+    fn write_labels<W: fmt::Write>(&self, mut writer: W, label_values: &[String], le: Option<&str>) -> Result<(), fmt::Error> {
+        if self.label_names.is_empty() && le.is_none() {
+            return Ok(());
+        }
+
+        write!(writer, "{{")?;
+        let mut first_label = true;
+        let le_label = le.map(|le_value| ("le", le_value));
+        let all_labels = self.label_names.iter()
+            .map(|name| name.as_str())
+            .zip(label_values.iter().map(|value| value.as_str()))
+            .chain(le_label);
+        for (label_key, label_value) in all_labels {
+            if first_label {
+                first_label = false;
+            } else {
+                write!(writer, ",")?;
+            }
+            write!(writer, "{}=\"", label_key)?;
+            escape_openmetrics_into(label_value, NewlineEscaping::Escape, &mut writer)?;
+            write!(writer, "\"")?;
+        }
+        write!(writer, "}}")
+    }
+}
+
+/// A single sample under its full name (including any suffix), for protocols that do not group
+/// samples into metric families.
+#[derive(Clone, Debug)]
+pub struct FlatSample {
+    /// The names and values of the labels, including `__name__`, sorted by name.
+    pub labels: Vec<(String, String)>,
+    pub value: f64,
+    pub timestamp: Option<SystemTime>,
+}
+
+/// How newlines in text are output.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewlineEscaping {
+    /// As `\n`, as mandated by OpenMetrics.
+    #[default]
+    Escape,
+
+    /// As a space, for consumers that mishandle `\n`.
+    Space,
+}
+
+/// The order in which the labels of a metric are output.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LabelOrder {
+    /// Alphabetically.
+    #[default]
+    Sorted,
+
+    /// In the order in which they were added to the metric.
+    Declared,
+}
+
+/// The order in which the samples of a metric are output, if not by their label values.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct SampleOrder {
+    /// The label by whose value the samples are ordered.
+    pub label: String,
+
+    /// Whether runs of digits are compared by their numeric value, e.g. to output `nas-2` before
+    /// `nas-10`.
+    #[serde(default)] pub numeric: bool,
+}
+impl SampleOrder {
+    pub fn compare(&self, value_a: &str, value_b: &str) -> Ordering {
+        if self.numeric {
+            compare_numeric_aware(value_a, value_b)
+        } else {
+            value_a.cmp(value_b)
+        }
+    }
+}
+
+/// Compares two strings, comparing runs of ASCII digits by their numeric value and everything else
+/// byte by byte.
+///
+/// Runs of digits that only differ in leading zeroes (`7` and `007`) are considered equal.
+fn compare_numeric_aware(a: &str, b: &str) -> Ordering {
+    let mut rest_a = a.as_bytes();
+    let mut rest_b = b.as_bytes();
+    loop {
+        match (rest_a.first(), rest_b.first()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(byte_a), Some(byte_b)) if byte_a.is_ascii_digit() && byte_b.is_ascii_digit() => {
+                let digits_a = rest_a.iter().take_while(|b| b.is_ascii_digit()).count();
+                let digits_b = rest_b.iter().take_while(|b| b.is_ascii_digit()).count();
+                let number_a = trim_leading_zeroes(&rest_a[..digits_a]);
+                let number_b = trim_leading_zeroes(&rest_b[..digits_b]);
+
+                // with leading zeroes gone, the longer number is the larger one
+                let ordering = number_a.len().cmp(&number_b.len())
+                    .then_with(|| number_a.cmp(number_b));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                rest_a = &rest_a[digits_a..];
+                rest_b = &rest_b[digits_b..];
+            },
+            (Some(byte_a), Some(byte_b)) => {
+                if byte_a != byte_b {
+                    return byte_a.cmp(byte_b);
+                }
+                rest_a = &rest_a[1..];
+                rest_b = &rest_b[1..];
+            },
+        }
+    }
+}
+
+fn trim_leading_zeroes(digits: &[u8]) -> &[u8] {
+    let zeroes = digits.iter().take_while(|b| **b == b'0').count();
+    &digits[zeroes..]
+}
+
+/// A format in which metrics can be exposed.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum ExpositionFormat {
+    /// OpenMetrics 1.0.0 text format.
+    OpenMetrics,
+
+    /// Prometheus text format 0.0.4, for consumers that do not understand OpenMetrics (such as the
+    /// Pushgateway).
+    PrometheusText,
+}
+impl ExpositionFormat {
+    /// The value of the `Content-Type` header for output in this format.
+    pub const fn content_type(&self) -> &'static str {
+        match self {
+            Self::OpenMetrics => "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            Self::PrometheusText => "text/plain; version=0.0.4; charset=utf-8",
+        }
+    }
+
+    /// The text that must follow the last metric.
+    pub const fn trailer(&self) -> &'static str {
+        match self {
+            Self::OpenMetrics => "# EOF\n",
+            Self::PrometheusText => "",
+        }
+    }
+
+    /// The name of the given kind of metric in `# TYPE` lines, or `None` if metrics of this kind are
+    /// output without `# TYPE` (and `# HELP`) lines.
+    pub const fn type_name(&self, kind: MetricKind) -> Option<&'static str> {
+        match self {
+            Self::OpenMetrics => Some(kind.as_openmetrics()),
+            Self::PrometheusText => match kind {
+                MetricKind::Counter => Some("counter"),
+                MetricKind::Gauge => Some("gauge"),
+                // by convention, info metrics are gauges with the value 1
+                MetricKind::Info => Some("gauge"),
+                MetricKind::GaugeHistogram => None,
+            },
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
@@ -161,28 +522,83 @@ impl Metric {
 pub enum MetricKind {
     Counter,
     Gauge,
+    GaugeHistogram,
+    Info,
 }
 impl MetricKind {
     pub const fn as_openmetrics(&self) -> &'static str {
         match self {
             Self::Counter => "counter",
             Self::Gauge => "gauge",
+            Self::GaugeHistogram => "gaugehistogram",
+            Self::Info => "info",
         }
     }
 
-    pub const fn openmetrics_metric_suffix(&self) -> &'static str {
+    pub const fn openmetrics_metric_suffix<'a>(&self, counter_suffix: &'a str) -> &'a str {
         match self {
-            Self::Counter => "_total",
+            Self::Counter => counter_suffix,
             Self::Gauge => "",
+            // gauge histograms have multiple suffixed samples per series; see Histogram
+            Self::GaugeHistogram => "",
+            Self::Info => INFO_SUFFIX,
         }
     }
 }
 
+/// The value of a sample of a metric.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Number(Number),
+    Histogram(Histogram),
+}
+
+/// The value of a gauge histogram sample.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    /// The upper bound (the value of the `le` label, e.g. `"0.5"` or `"+Inf"`) and cumulative count
+    /// of each bucket, in ascending order of upper bound.
+    pub buckets: Vec<(String, Number)>,
+    pub count: Number,
+    pub sum: Option<Number>,
+}
+
 #[derive(Clone, Copy, Debug)]
 pub enum Number {
     Integer(i64),
     Float(f64),
 }
+impl Number {
+    pub fn as_f64(&self) -> f64 {
+        match self {
+            Self::Integer(v) => *v as f64,
+            Self::Float(v) => *v,
+        }
+    }
+
+    /// Returns a displayable version of this number whose floating-point value is rounded to the
+    /// given number of decimal places (if any). Integers are displayed unchanged.
+    pub fn rounded(self, precision: Option<usize>) -> RoundedNumber {
+        RoundedNumber {
+            number: self,
+            precision,
+        }
+    }
+}
+impl std::ops::Add for Number {
+    type Output = Number;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        // remain an integer only if both are integers and the sum fits
+        match (self, rhs) {
+            (Self::Integer(a), Self::Integer(b)) => match a.checked_add(b) {
+                Some(sum) => Self::Integer(sum),
+                None => Self::Float(self.as_f64() + rhs.as_f64()),
+            },
+            _ => Self::Float(self.as_f64() + rhs.as_f64()),
+        }
+    }
+}
 impl fmt::Display for Number {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -193,15 +609,174 @@ impl fmt::Display for Number {
 }
 
 
-pub(crate) fn escape_openmetrics_into<W: fmt::Write>(source: &str, mut writer: W) -> Result<(), fmt::Error> {
+/// The timestamp of a sample as output after its value: a space followed by the seconds since the
+/// Unix epoch (with millisecond precision, as Prometheus stores no more) or, in the Prometheus text
+/// format, the milliseconds since the Unix epoch; nothing if the sample has no timestamp.
+#[derive(Clone, Copy, Debug)]
+struct TimestampSuffix(Option<SystemTime>, ExpositionFormat);
+impl fmt::Display for TimestampSuffix {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(timestamp) = self.0 else { return Ok(()) };
+        let since_epoch = timestamp.duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO);
+        match self.1 {
+            ExpositionFormat::OpenMetrics => write!(f, " {}.{:03}", since_epoch.as_secs(), since_epoch.subsec_millis()),
+            ExpositionFormat::PrometheusText => write!(f, " {}", since_epoch.as_millis()),
+        }
+    }
+}
+
+
+/// A [`Number`] displayed with limited precision; see [`Number::rounded`].
+#[derive(Clone, Copy, Debug)]
+pub struct RoundedNumber {
+    number: Number,
+    precision: Option<usize>,
+}
+impl fmt::Display for RoundedNumber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.number, self.precision) {
+            (Number::Float(v), Some(precision)) if v.is_finite() => {
+                let formatted = format!("{:.*}", precision, v);
+                // 0.50 => 0.5, 2.00 => 2
+                let trimmed = if formatted.contains('.') {
+                    formatted.trim_end_matches('0').trim_end_matches('.')
+                } else {
+                    formatted.as_str()
+                };
+                // -0.00 => -0 => 0
+                if trimmed == "-0" {
+                    write!(f, "0")
+                } else {
+                    write!(f, "{}", trimmed)
+                }
+            },
+            (number, _) => write!(f, "{}", number),
+        }
+    }
+}
+
+
+/// Returns the name of the metric family with the given name, stripping the counter suffix from
+/// counters and `_info` from info metrics whose names already end with it.
+pub(crate) fn family_name<'a>(name: &'a str, kind: MetricKind, counter_suffix: &str) -> &'a str {
+    let suffix = kind.openmetrics_metric_suffix(counter_suffix);
+    if !suffix.is_empty() {
+        if let Some(stripped) = name.strip_suffix(suffix) {
+            if !stripped.is_empty() {
+                return stripped;
+            }
+        }
+    }
+    name
+}
+
+
+/// Hashes the given bytes using FNV-1a, which (unlike std's `DefaultHasher`) is stable across Rust
+/// versions.
+pub(crate) fn stable_hash(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in bytes {
+        hash ^= u32::from(*b);
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+
+/// Truncates a label value to at most `max_length` characters.
+///
+/// To keep truncated values distinct, the end of a truncated value is replaced by `~` and a hash of
+/// the whole value.
+pub(crate) fn truncate_label_value(value: &str, max_length: usize) -> Cow<'_, str> {
+    assert!(max_length >= MIN_MAX_LABEL_VALUE_LENGTH);
+    if value.chars().count() <= max_length {
+        return Cow::Borrowed(value);
+    }
+
+    let suffix = format!("~{:08x}", stable_hash(value.as_bytes()));
+
+    let mut truncated: String = value.chars()
+        .take(max_length - suffix.len())
+        .collect();
+    truncated.push_str(&suffix);
+    Cow::Owned(truncated)
+}
+
+
+pub(crate) fn escape_openmetrics_into<W: fmt::Write>(source: &str, newlines: NewlineEscaping, mut writer: W) -> Result<(), fmt::Error> {
     for c in source.chars() {
         if c == '\\' || c == '"' {
             write!(writer, "\\{}", c)?;
         } else if c == '\n' {
-            write!(writer, "\\n")?;
+            match newlines {
+                NewlineEscaping::Escape => write!(writer, "\\n")?,
+                NewlineEscaping::Space => write!(writer, " ")?,
+            }
+        } else {
+            write!(writer, "{}", c)?;
+        }
+    }
+    Ok(())
+}
+
+
+/// Escapes the text of a `# HELP` line in the Prometheus text format, which (unlike OpenMetrics)
+/// only escapes backslashes and newlines.
+pub(crate) fn escape_text_help_into<W: fmt::Write>(source: &str, newlines: NewlineEscaping, mut writer: W) -> Result<(), fmt::Error> {
+    for c in source.chars() {
+        if c == '\\' {
+            write!(writer, "\\\\")?;
+        } else if c == '\n' {
+            match newlines {
+                NewlineEscaping::Escape => write!(writer, "\\n")?,
+                NewlineEscaping::Space => write!(writer, " ")?,
+            }
         } else {
             write!(writer, "{}", c)?;
         }
     }
     Ok(())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::{ExpositionFormat, LabelOrder, MetricDatabase, MetricKind, NewlineEscaping, Number};
+
+
+    fn render(database: &MetricDatabase, counter_suffix: &str) -> String {
+        let mut output = String::new();
+        database.write(&mut output, ExpositionFormat::OpenMetrics, counter_suffix, NewlineEscaping::Escape)
+            .expect("failed to write metrics");
+        output
+    }
+
+    fn counter_database(name: &str) -> MetricDatabase {
+        let mut database = MetricDatabase::with_label_order(LabelOrder::Sorted);
+        database.get_or_insert(name, MetricKind::Counter)
+            .add_sample(&BTreeMap::new(), Number::Integer(3));
+        database
+    }
+
+    #[test]
+    fn counter_suffix_is_not_doubled() {
+        let expected = "# TYPE radiator_requests counter\nradiator_requests_total 3\n# EOF\n";
+        assert_eq!(render(&counter_database("radiator_requests"), "_total"), expected);
+        assert_eq!(render(&counter_database("radiator_requests_total"), "_total"), expected);
+    }
+
+    #[test]
+    fn counter_suffix_override() {
+        assert_eq!(
+            render(&counter_database("radiator_requests"), ""),
+            "# TYPE radiator_requests counter\nradiator_requests 3\n# EOF\n",
+        );
+        assert_eq!(
+            render(&counter_database("radiator_requests_count"), "_count"),
+            "# TYPE radiator_requests counter\nradiator_requests_count 3\n# EOF\n",
+        );
+    }
+}
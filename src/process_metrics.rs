@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+
+use crate::openmetrics::MetricDatabase;
+
+
+/// Adds the standard `process_*` metrics describing the exporter process to the database.
+///
+/// These are only available on Linux, where they are read from `/proc/self`; elsewhere, nothing is
+/// added.
+#[cfg(target_os = "linux")]
+pub(crate) fn collect(database: &mut MetricDatabase, instance_labels: &BTreeMap<String, String>) {
+    use tracing::debug;
+
+    use crate::openmetrics::{MetricKind, Number};
+    use crate::self_metrics::add_simple;
+
+    let stats = match linux::ProcessStats::read() {
+        Ok(s) => s,
+        Err(e) => {
+            debug!("failed to read process statistics: {}", e);
+            return;
+        },
+    };
+
+    add_simple(
+        database,
+        instance_labels,
+        "process_cpu_seconds",
+        MetricKind::Counter,
+        "Total user and system CPU time spent in seconds.",
+        Number::Float(stats.cpu_seconds),
+    );
+    add_simple(
+        database,
+        instance_labels,
+        "process_start_time_seconds",
+        MetricKind::Gauge,
+        "Start time of the process since unix epoch in seconds.",
+        Number::Float(stats.start_time_seconds),
+    );
+    add_simple(
+        database,
+        instance_labels,
+        "process_virtual_memory_bytes",
+        MetricKind::Gauge,
+        "Virtual memory size in bytes.",
+        Number::Integer(stats.virtual_memory_bytes),
+    );
+    if let Some(resident_memory_bytes) = stats.resident_memory_bytes {
+        add_simple(
+            database,
+            instance_labels,
+            "process_resident_memory_bytes",
+            MetricKind::Gauge,
+            "Resident memory size in bytes.",
+            Number::Integer(resident_memory_bytes),
+        );
+    }
+    if let Some(open_fds) = stats.open_fds {
+        add_simple(
+            database,
+            instance_labels,
+            "process_open_fds",
+            MetricKind::Gauge,
+            "Number of open file descriptors.",
+            Number::Integer(open_fds),
+        );
+    }
+    if let Some(max_fds) = stats.max_fds {
+        add_simple(
+            database,
+            instance_labels,
+            "process_max_fds",
+            MetricKind::Gauge,
+            "Maximum number of open file descriptors.",
+            Number::Integer(max_fds),
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn collect(_database: &mut MetricDatabase, _instance_labels: &BTreeMap<String, String>) {
+}
+
+
+#[cfg(target_os = "linux")]
+mod linux {
+    use std::io;
+
+    /// The unit of the CPU times in `/proc/self/stat`, in ticks per second.
+    ///
+    /// This is 100 on all platforms supported by Linux (it is part of the userspace ABI), which is
+    /// also what other Prometheus exporters assume.
+    const USER_HZ: f64 = 100.0;
+
+
+    fn invalid_data(message: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, message.to_owned())
+    }
+
+
+    #[derive(Clone, Debug)]
+    pub(super) struct ProcessStats {
+        pub cpu_seconds: f64,
+        pub start_time_seconds: f64,
+        pub virtual_memory_bytes: i64,
+        pub resident_memory_bytes: Option<i64>,
+        pub open_fds: Option<i64>,
+        pub max_fds: Option<i64>,
+    }
+    impl ProcessStats {
+        pub fn read() -> Result<Self, io::Error> {
+            let stat = std::fs::read_to_string("/proc/self/stat")?;
+
+            // the process name (field 2) is parenthesized and may contain anything, including spaces
+            // and parentheses; all other fields follow the last closing parenthesis
+            let (_pid_and_name, after_name) = stat.rsplit_once(')')
+                .ok_or_else(|| invalid_data("/proc/self/stat does not contain the process name"))?;
+            let fields: Vec<&str> = after_name.split_whitespace().collect();
+            // after_name starts with field 3 (state)
+            let field = |number: usize| -> Result<i64, io::Error> {
+                fields.get(number - 3)
+                    .and_then(|f| f.parse().ok())
+                    .ok_or_else(|| invalid_data("/proc/self/stat contains too few or invalid fields"))
+            };
+            let user_ticks = field(14)?;
+            let system_ticks = field(15)?;
+            let start_ticks_since_boot = field(22)?;
+            let virtual_memory_bytes = field(23)?;
+
+            Ok(Self {
+                cpu_seconds: (user_ticks + system_ticks) as f64 / USER_HZ,
+                start_time_seconds: read_boot_time()? as f64 + start_ticks_since_boot as f64 / USER_HZ,
+                virtual_memory_bytes,
+                resident_memory_bytes: read_resident_memory_bytes(),
+                open_fds: count_open_fds(),
+                max_fds: read_max_fds(),
+            })
+        }
+    }
+
+
+    /// Reads the time at which the system was booted, in seconds since the Unix epoch.
+    fn read_boot_time() -> Result<i64, io::Error> {
+        let stat = std::fs::read_to_string("/proc/stat")?;
+        stat.lines()
+            .filter_map(|line| line.strip_prefix("btime "))
+            .filter_map(|btime| btime.trim().parse().ok())
+            .next()
+            .ok_or_else(|| invalid_data("/proc/stat does not contain the boot time"))
+    }
+
+    fn read_resident_memory_bytes() -> Option<i64> {
+        // VmRSS:	    1234 kB
+        let status = std::fs::read_to_string("/proc/self/status").ok()?;
+        let kibibytes: i64 = status.lines()
+            .filter_map(|line| line.strip_prefix("VmRSS:"))
+            .filter_map(|rss| rss.trim().strip_suffix(" kB"))
+            .filter_map(|rss| rss.trim().parse().ok())
+            .next()?;
+        kibibytes.checked_mul(1024)
+    }
+
+    fn count_open_fds() -> Option<i64> {
+        let entries = std::fs::read_dir("/proc/self/fd").ok()?;
+        entries.count().try_into().ok()
+    }
+
+    fn read_max_fds() -> Option<i64> {
+        // Limit                     Soft Limit           Hard Limit           Units
+        // Max open files            1024                 524288               files
+        let limits = std::fs::read_to_string("/proc/self/limits").ok()?;
+        limits.lines()
+            .filter_map(|line| line.strip_prefix("Max open files"))
+            .filter_map(|values| values.split_whitespace().next())
+            // "unlimited" does not parse and is skipped
+            .filter_map(|soft_limit| soft_limit.parse().ok())
+            .next()
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use http_body_util::{BodyExt, Full};
+use hyper::{Method, Request, StatusCode, Uri};
+use hyper::body::Bytes;
+use hyper_util::rt::TokioIo;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::pki_types::ServerName;
+
+
+// how much of the body of an error response is logged
+const MAX_LOGGED_ERROR_BODY: usize = 512;
+
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Io(io::Error),
+    Http(hyper::Error),
+    InvalidHostName,
+    Rejected { status: StatusCode, body: String },
+}
+impl Error {
+    /// Whether trying again might help, i.e. the endpoint was unreachable, overloaded or failed
+    /// internally rather than refusing the pushed metrics.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Self::Io(_) | Self::Http(_) => true,
+            Self::InvalidHostName => false,
+            Self::Rejected { status, .. } => status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS,
+        }
+    }
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Http(e) => write!(f, "HTTP error: {}", e),
+            Self::InvalidHostName => write!(f, "host name is not valid for TLS"),
+            Self::Rejected { status, body } => write!(f, "endpoint answered with {}: {:?}", status, body),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Http(e) => Some(e),
+            Self::InvalidHostName => None,
+            Self::Rejected { .. } => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<hyper::Error> for Error {
+    fn from(value: hyper::Error) -> Self {
+        Self::Http(value)
+    }
+}
+
+
+/// How the exporter authenticates itself to an endpoint it pushes metrics to.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct PushCredentials<'a> {
+    pub username: Option<&'a str>,
+    pub password: Option<&'a str>,
+    pub bearer_token: Option<&'a str>,
+}
+
+
+/// An HTTP(S) endpoint to which the exporter pushes metrics (remote-write or Pushgateway).
+pub(crate) struct PushTarget {
+    uri: Uri,
+    tls_connector: Option<TlsConnector>,
+    authorization: Option<String>,
+}
+impl PushTarget {
+    /// Prepares pushing to the given URL, which must have been checked to be an `http` or `https` URL
+    /// with a host name.
+    ///
+    /// For HTTPS, the server certificate is verified against the given CA certificates or those
+    /// trusted by the operating system.
+    pub fn new(uri: Uri, ca_certificate: Option<&Path>, credentials: PushCredentials<'_>) -> Result<Self, crate::tls::Error> {
+        let tls_connector = if uri.scheme_str() == Some("https") {
+            Some(crate::tls::make_push_connector(ca_certificate)?)
+        } else {
+            None
+        };
+        let authorization = match (credentials.username, credentials.password, credentials.bearer_token) {
+            (Some(username), Some(password), _) => {
+                let encoded = BASE64.encode(format!("{}:{}", username, password));
+                Some(format!("Basic {}", encoded))
+            },
+            (_, _, Some(bearer_token)) => Some(format!("Bearer {}", bearer_token)),
+            _ => None,
+        };
+        Ok(Self {
+            uri,
+            tls_connector,
+            authorization,
+        })
+    }
+
+    pub fn uri(&self) -> &Uri {
+        &self.uri
+    }
+
+    /// Sends the given body to the endpoint with the given method and additional headers.
+    ///
+    /// A new connection is opened for each push; pushes are rare enough that keeping the connection
+    /// alive is not worth the trouble.
+    pub async fn send(&self, method: Method, headers: &[(&str, &str)], body: Vec<u8>) -> Result<(), Error> {
+        let host = self.uri.host().expect("push URL not checked?!");
+        let port = self.uri.port_u16()
+            .unwrap_or(if self.tls_connector.is_some() { 443 } else { 80 });
+        // IPv6 addresses are bracketed in URLs
+        let bare_host = host.trim_start_matches('[').trim_end_matches(']');
+        let stream = TcpStream::connect((bare_host, port)).await?;
+        match self.tls_connector.as_ref() {
+            Some(connector) => {
+                let server_name = ServerName::try_from(bare_host.to_owned())
+                    .map_err(|_| Error::InvalidHostName)?;
+                let tls_stream = connector.connect(server_name, stream).await?;
+                self.send_over(tls_stream, method, headers, body).await
+            },
+            None => self.send_over(stream, method, headers, body).await,
+        }
+    }
+
+    async fn send_over<I>(&self, io: I, method: Method, headers: &[(&str, &str)], body: Vec<u8>) -> Result<(), Error>
+        where I: AsyncRead + AsyncWrite + Send + Unpin + 'static
+    {
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(io)).await?;
+        tokio::spawn(connection);
+
+        let path = self.uri.path_and_query()
+            .map(|pq| pq.as_str())
+            .unwrap_or("/");
+        let host = self.uri.authority()
+            .expect("push URL not checked?!")
+            .as_str();
+        let mut request_builder = Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Host", host)
+            .header("User-Agent", concat!("prometheus-radiator-exporter/", env!("CARGO_PKG_VERSION")));
+        for (name, value) in headers {
+            request_builder = request_builder.header(*name, *value);
+        }
+        if let Some(authorization) = self.authorization.as_ref() {
+            request_builder = request_builder.header("Authorization", authorization);
+        }
+        let request = request_builder
+            .body(Full::new(Bytes::from(body)))
+            .expect("cannot construct push request");
+
+        let response = sender.send_request(request).await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        // the endpoint usually explains why
+        let response_body = response.into_body().collect().await?.to_bytes();
+        let logged_length = response_body.len().min(MAX_LOGGED_ERROR_BODY);
+        Err(Error::Rejected {
+            status,
+            body: String::from_utf8_lossy(&response_body[..logged_length]).into_owned(),
+        })
+    }
+}
+
+
+/// Checks that the given URL is suitable for pushing metrics to, returning a description of the
+/// problem otherwise.
+pub(crate) fn check_url(url: &str) -> Result<Uri, &'static str> {
+    let uri: Uri = url.parse()
+        .map_err(|_| "is not a valid URL")?;
+    if !matches!(uri.scheme_str(), Some("http") | Some("https")) {
+        return Err("must start with http:// or https://");
+    }
+    if uri.host().is_none() {
+        return Err("must contain a host name");
+    }
+    Ok(uri)
+}
@@ -0,0 +1,128 @@
+use std::fmt::Write;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE as BASE64_URL_SAFE;
+use hyper::{Method, Uri};
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, warn};
+
+use crate::config::{CONFIG, PushgatewayConfig};
+use crate::openmetrics::ExpositionFormat;
+use crate::push::{PushCredentials, PushTarget};
+use crate::radiator::RadiatorTransport;
+use crate::self_metrics::{PUSHGATEWAY_FAILURES, PUSHGATEWAY_PUSHES};
+
+
+/// Appends a label of the grouping key to the given Pushgateway URL path.
+///
+/// Values that are empty or contain characters with a special meaning in URLs are base64-encoded,
+/// as the Pushgateway expects.
+fn append_grouping_label(path: &mut String, name: &str, value: &str) {
+    let is_plain = !value.is_empty()
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.' || c == '_' || c == '~');
+    if is_plain {
+        write!(path, "/{}/{}", name, value).unwrap();
+    } else if value.is_empty() {
+        write!(path, "/{}@base64/=", name).unwrap();
+    } else {
+        write!(path, "/{}@base64/{}", name, BASE64_URL_SAFE.encode(value)).unwrap();
+    }
+}
+
+
+/// Prepares pushing to the grouping key in the given (checked) configuration.
+pub(crate) fn make_target(config: &PushgatewayConfig) -> Result<PushTarget, crate::tls::Error> {
+    let mut url = config.url.trim_end_matches('/').to_owned();
+    url.push_str("/metrics");
+    append_grouping_label(&mut url, "job", &config.job);
+    for (name, value) in &config.grouping_labels {
+        append_grouping_label(&mut url, name, value);
+    }
+    let uri: Uri = url.parse()
+        .expect("pushgateway.url not checked?!");
+
+    let credentials = PushCredentials {
+        username: config.username.as_deref(),
+        password: config.password.as_deref(),
+        bearer_token: config.bearer_token.as_deref(),
+    };
+    PushTarget::new(uri, config.ca_certificate.as_deref(), credentials)
+}
+
+
+/// Pushes the given metrics to the Pushgateway, trying again after transient failures.
+async fn push_with_retries(target: &PushTarget, config: &PushgatewayConfig, body: &[u8]) {
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let headers = [("Content-Type", ExpositionFormat::PrometheusText.content_type())];
+    let mut attempt = 0;
+    loop {
+        // PUT replaces all metrics of the grouping key, so metrics that are gone disappear as well
+        let error_message = match tokio::time::timeout(timeout, target.send(Method::PUT, &headers, body.to_vec())).await {
+            Ok(Ok(())) => {
+                debug!("pushed metrics to {}", target.uri());
+                PUSHGATEWAY_PUSHES.fetch_add(1, Ordering::Relaxed);
+                return;
+            },
+            Ok(Err(e)) => {
+                PUSHGATEWAY_FAILURES.fetch_add(1, Ordering::Relaxed);
+                if !e.is_transient() {
+                    warn!(event = "pushgateway_failed", "failed to push metrics to {}: {}; not retrying", target.uri(), e);
+                    return;
+                }
+                e.to_string()
+            },
+            Err(_) => {
+                PUSHGATEWAY_FAILURES.fetch_add(1, Ordering::Relaxed);
+                "timed out".to_owned()
+            },
+        };
+
+        if attempt >= config.retries {
+            warn!(event = "pushgateway_failed", "failed to push metrics to {}: {}; giving up after {} attempts", target.uri(), error_message, attempt + 1);
+            return;
+        }
+        attempt += 1;
+        warn!(
+            event = "pushgateway_retry",
+            "failed to push metrics to {}: {}; retrying in {} ms", target.uri(), error_message, config.retry_interval_ms,
+        );
+        tokio::time::sleep(Duration::from_millis(config.retry_interval_ms)).await;
+    }
+}
+
+
+/// Collects the metrics at the configured interval and pushes them to the Pushgateway.
+pub(crate) async fn run_pushes(target: PushTarget, transport: &dyn RadiatorTransport) {
+    let config = CONFIG
+        .get().expect("CONFIG not set?!");
+    let pushgateway = config.pushgateway.as_ref()
+        .expect("pushgateway not configured?!");
+    let mut ticker = tokio::time::interval(Duration::from_millis(pushgateway.interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+
+        // the metrics may have been reloaded in the meantime
+        let config = CONFIG
+            .get().expect("CONFIG not set?!");
+        let (metric_database, _scrape_info) = match crate::collect_metrics(&config, transport).await {
+            Ok(collected) => collected,
+            Err((_status, _category, message)) => {
+                warn!(event = "pushgateway_skipped", "not pushing metrics: {}", message);
+                continue;
+            },
+        };
+
+        // the Pushgateway does not understand OpenMetrics
+        let mut output = String::new();
+        if let Err(e) = metric_database.write(&mut output, ExpositionFormat::PrometheusText, &config.www.counter_suffix, config.www.help_newlines) {
+            error!("error collecting metrics output: {}", e);
+            continue;
+        }
+
+        push_with_retries(&target, pushgateway, output.as_bytes()).await;
+    }
+}
+
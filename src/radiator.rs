@@ -1,83 +1,415 @@
 use std::fmt;
+use std::future::Future;
 use std::io;
+use std::net::SocketAddr;
 use std::sync::OnceLock;
-use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
+use async_trait::async_trait;
+use socket2::{SockRef, TcpKeepalive};
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::TcpStream;
+use tokio::net::{lookup_host, TcpStream};
 use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
-use tokio::sync::Mutex;
-use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
-use tracing::{error, warn};
+use tokio::sync::{Mutex, MutexGuard, Notify};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::oneshot;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
 
 use crate::config::{CONFIG, RadiatorConfig};
+use crate::self_metrics::{BYTES_RECEIVED, COMMANDS, record_command_duration, record_reconnect};
 
 
 pub(crate) static SOCKET_STATE: OnceLock<Mutex<SocketState>> = OnceLock::new();
 static SOCKET_GONE: AtomicBool = AtomicBool::new(false);
+// wakes up a command waiting for its response when the reader notices that the connection is gone
+static SOCKET_GONE_NOTIFY: Notify = Notify::const_new();
+static CONNECTION_GENERATION: AtomicU64 = AtomicU64::new(0);
+static LAST_CONNECTION_ATTEMPT: std::sync::Mutex<Option<Instant>> = std::sync::Mutex::new(None);
+// when the current connection was logged into; None while there is no connection
+static CONNECTED_AT: std::sync::Mutex<Option<Instant>> = std::sync::Mutex::new(None);
+// when the last command was sent or the current connection was established, whichever is later
+static LAST_ACTIVITY: std::sync::Mutex<Option<Instant>> = std::sync::Mutex::new(None);
+static LAST_DISCONNECT_REASON: std::sync::Mutex<Option<DisconnectReason>> = std::sync::Mutex::new(None);
+static CIRCUIT_BREAKER: std::sync::Mutex<CircuitBreaker> = std::sync::Mutex::new(CircuitBreaker::new());
+// the command (as sent) whose response has not been received yet
+static OUTSTANDING_COMMAND: std::sync::Mutex<Option<Vec<u8>>> = std::sync::Mutex::new(None);
+// held by the scrape that owns the connection if radiator.connection_mode is "per_scrape"
+static SCRAPE_CONNECTION: Mutex<()> = Mutex::const_new(());
+// hands out the standby connection if radiator.hot_standby is set
+static STANDBY_REQUESTS: OnceLock<UnboundedSender<oneshot::Sender<LoggedInConnection>>> = OnceLock::new();
+static STANDBY_READY: AtomicBool = AtomicBool::new(false);
+
+// how long to wait after failing to establish the standby connection
+const STANDBY_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// how long to wait for the standby connection to be handed over, which is delayed while a message
+// on it has only partially arrived
+const STANDBY_HANDOVER_TIMEOUT: Duration = Duration::from_secs(1);
+
+// how long logging out of an idle connection may take
+const IDLE_LOGOUT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// how long a connection attempt may take before the next address is tried in parallel
+// (the value recommended by RFC 8305)
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// commands are sent one at a time, so more than one response is only queued if earlier responses
+// have gone unclaimed (e.g. by a scrape that was cancelled while waiting); once the queue is full,
+// the reader stops reading from the socket until a response has been taken out
+const MESSAGE_QUEUE_CAPACITY: usize = 16;
+
+// a new socket is only handed over after the reader has been told to abandon the previous one
+const NEW_SOCKET_QUEUE_CAPACITY: usize = 1;
 
 
 #[derive(Debug)]
 pub struct SocketState {
     pub socket_writer: Option<OwnedWriteHalf>,
-    pub new_socket_sender: UnboundedSender<BufReader<OwnedReadHalf>>,
-    pub message_receiver: UnboundedReceiver<Vec<u8>>,
+    pub new_socket_sender: Sender<(BufReader<OwnedReadHalf>, oneshot::Receiver<()>)>,
+    pub message_receiver: Receiver<Vec<u8>>,
+
+    /// Makes the reader abandon the current socket when dropped.
+    pub reader_closer: Option<oneshot::Sender<()>>,
+}
+
+
+/// Why the connection to Radiator was lost.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum DisconnectReason {
+    /// Radiator closed the connection.
+    ReaderEof,
+
+    /// Reading from the connection failed.
+    ReaderError,
+
+    /// Sending a command failed.
+    WriteFailed,
+
+    /// The operating system gave up on the connection, e.g. because keepalive probes went
+    /// unanswered.
+    Timeout,
+
+    /// The exporter closed the connection after it had been idle (`radiator.idle_timeout_ms`).
+    Idle,
+}
+impl DisconnectReason {
+    pub const ALL: [Self; 5] = [Self::ReaderEof, Self::ReaderError, Self::WriteFailed, Self::Timeout, Self::Idle];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::ReaderEof => "reader_eof",
+            Self::ReaderError => "reader_error",
+            Self::WriteFailed => "write_failed",
+            Self::Timeout => "timeout",
+            Self::Idle => "idle",
+        }
+    }
+
+    /// Returns the reason for losing the connection because of the given error, which occurred
+    /// while reading if `reading` is true and while writing otherwise.
+    fn from_io_error(error: &io::Error, reading: bool) -> Self {
+        if error.kind() == io::ErrorKind::TimedOut {
+            Self::Timeout
+        } else if reading {
+            Self::ReaderError
+        } else {
+            Self::WriteFailed
+        }
+    }
+}
+
+
+/// The state of the circuit breaker guarding reconnection attempts.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum BreakerState {
+    /// Reconnection attempts are allowed.
+    Closed,
+
+    /// Too many reconnection attempts have failed; no attempts are made until the cooldown is over.
+    Open,
+
+    /// The cooldown is over; the next reconnection attempt decides whether the breaker closes or
+    /// opens again.
+    HalfOpen,
+}
+impl BreakerState {
+    pub const fn as_number(&self) -> i64 {
+        match self {
+            Self::Closed => 0,
+            Self::Open => 1,
+            Self::HalfOpen => 2,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+struct CircuitBreaker {
+    consecutive_failures: u32,
+    opened_at: Option<Instant>,
+}
+impl CircuitBreaker {
+    const fn new() -> Self {
+        Self {
+            consecutive_failures: 0,
+            opened_at: None,
+        }
+    }
+
+    fn state(&self, config: &RadiatorConfig) -> BreakerState {
+        match self.opened_at {
+            None => BreakerState::Closed,
+            Some(opened_at) => if opened_at.elapsed() < Duration::from_millis(config.breaker_cooldown_ms) {
+                BreakerState::Open
+            } else {
+                BreakerState::HalfOpen
+            },
+        }
+    }
+
+    fn record_failure(&mut self, config: &RadiatorConfig) {
+        let Some(failure_threshold) = config.breaker_failure_threshold else { return };
+        self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+
+        // a failed probe in the half-open state opens the breaker again immediately
+        if self.opened_at.is_some() || self.consecutive_failures >= failure_threshold {
+            warn!(
+                event = "breaker_open",
+                consecutive_failures = self.consecutive_failures,
+                cooldown_ms = config.breaker_cooldown_ms,
+                "too many failed connection attempts; pausing reconnection",
+            );
+            self.opened_at = Some(Instant::now());
+        }
+    }
+
+    fn record_success(&mut self) {
+        if self.opened_at.is_some() {
+            info!(event = "breaker_closed", "connection to Radiator re-established; resuming normal operation");
+        }
+        self.consecutive_failures = 0;
+        self.opened_at = None;
+    }
 }
 
 
 async fn message_processor(
-    mut new_socket_receiver: UnboundedReceiver<BufReader<OwnedReadHalf>>,
-    message_sender: UnboundedSender<Vec<u8>>,
+    mut new_socket_receiver: Receiver<(BufReader<OwnedReadHalf>, oneshot::Receiver<()>)>,
+    message_sender: Sender<Vec<u8>>,
+    response_delimiter: u8,
+    log_prefix: Vec<u8>,
 ) {
     loop {
         // obtain a socket
         // if no new socket will ever come, break out
-        let Some(mut socket) = new_socket_receiver.recv().await else { break };
+        let Some((mut socket, mut close_receiver)) = new_socket_receiver.recv().await else { break };
 
         let mut buf = Vec::new();
         loop {
             // read out a packet
             buf.clear();
-            if let Err(e) = socket.read_until(b'\0', &mut buf).await {
-                error!("error reading from Radiator management socket: {}", e);
+            let read_result = tokio::select! {
+                rr = socket.read_until(response_delimiter, &mut buf) => rr,
+                _ = &mut close_receiver => {
+                    // the connection has been closed (or replaced) deliberately
+                    debug!(event = "reader_closed", "stopped reading from Radiator management socket");
+                    break;
+                },
+            };
+            if let Err(e) = read_result {
+                error!(event = "reader_error", "error reading from Radiator management socket: {}", e);
 
                 // break out, waiting for a new socket
-                SOCKET_GONE.store(true, Ordering::SeqCst);
+                mark_socket_gone(DisconnectReason::from_io_error(&e, true));
                 break;
             }
-            if buf.len() == 0 {
+            if buf.is_empty() {
                 // EOF
-                warn!("end-of-file encountered while reading from Radiator management socket");
+                warn!(event = "reader_eof", "end-of-file encountered while reading from Radiator management socket");
 
                 // again, wait for a new socket
-                SOCKET_GONE.store(true, Ordering::SeqCst);
+                mark_socket_gone(DisconnectReason::ReaderEof);
+                break;
+            }
+            BYTES_RECEIVED.fetch_add(buf.len().try_into().unwrap_or(u64::MAX), Ordering::Relaxed);
+            if buf.last() != Some(&response_delimiter) {
+                // the connection was closed in the middle of a response
+                warn!(event = "reader_eof", "end-of-file encountered within a response from Radiator management socket");
+                mark_socket_gone(DisconnectReason::ReaderEof);
                 break;
             }
-            assert!(buf.last() == Some(&b'\0'));
             buf.pop();
 
-            // if it starts with "LOG ", ignore it
-            // otherwise, pass it on
-            if !buf.starts_with(b"LOG ") {
-                message_sender.send(buf.clone())
-                    .expect("sending received message failed");
+            if !is_response(&buf, &log_prefix) {
+                continue;
+            }
+            let message = match message_sender.try_send(buf.clone()) {
+                Ok(()) => continue,
+                Err(TrySendError::Full(message)) => message,
+                Err(TrySendError::Closed(_)) => {
+                    // nobody is left to take the responses
+                    debug!(event = "reader_closed", "stopped reading from Radiator management socket as responses are no longer taken");
+                    return;
+                },
+            };
+
+            // apply backpressure: stop reading until there is room again
+            warn!(event = "message_queue_full", capacity = MESSAGE_QUEUE_CAPACITY, "too many unclaimed responses from Radiator; pausing reading");
+            tokio::select! {
+                send_result = message_sender.send(message) => if send_result.is_err() {
+                    debug!(event = "reader_closed", "stopped reading from Radiator management socket as responses are no longer taken");
+                    return;
+                },
+                _ = &mut close_receiver => {
+                    debug!(event = "reader_closed", "stopped reading from Radiator management socket");
+                    break;
+                },
             }
         }
     }
 }
 
 
-pub fn start_message_processor() -> SocketState {
-    let (new_socket_sender, new_socket_receiver) = mpsc::unbounded_channel();
-    let (message_sender, message_receiver) = mpsc::unbounded_channel();
+/// Decides whether a message from Radiator is the response to the outstanding command, marking the
+/// command as answered if so.
+///
+/// Messages arriving while no command is outstanding are discarded, as are messages starting with
+/// the log prefix (`radiator.log_prefix`) unless they start with the echo of the outstanding
+/// command. If the response delimiter is a newline, the echo arrives as a message of its own; it is
+/// discarded and the command stays outstanding.
+fn is_response(message: &[u8], log_prefix: &[u8]) -> bool {
+    let mut outstanding_guard = OUTSTANDING_COMMAND
+        .lock().expect("OUTSTANDING_COMMAND poisoned");
+    let Some(outstanding_command) = outstanding_guard.as_ref() else {
+        debug!(event = "unsolicited_message", message = %hex_dump(message), "discarding message from Radiator received while no command was outstanding");
+        return false;
+    };
+    // (with binary_mode = false, the echo keeps the carriage return of the command terminator)
+    if message.trim_ascii_end() == outstanding_command.as_slice() {
+        debug!(event = "echo_message", "discarding echo of the outstanding command received as a message of its own");
+        return false;
+    }
+    let is_echoed = message.strip_prefix(outstanding_command.as_slice())
+        .is_some_and(|rest| rest.starts_with(b"\n"));
+    if !is_echoed && !log_prefix.is_empty() && message.starts_with(log_prefix) {
+        // a log message arriving while waiting for the response
+        return false;
+    }
+    *outstanding_guard = None;
+    true
+}
+
+
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut ret = String::with_capacity(bytes.len() * 3);
+    for (i, b) in bytes.iter().enumerate() {
+        if i > 0 {
+            ret.push(' ');
+        }
+        ret.push_str(&format!("{:02x}", b));
+    }
+    ret
+}
+
+
+/// Whether the reader has noticed that the connection to Radiator has been lost (and no command has
+/// been sent since to restore it).
+pub(crate) fn socket_gone() -> bool {
+    SOCKET_GONE.load(Ordering::SeqCst)
+}
+
+
+/// Records that the connection to Radiator has been lost for the given reason.
+fn record_disconnect(reason: DisconnectReason) {
+    *CONNECTED_AT.lock().expect("CONNECTED_AT poisoned") = None;
+    *LAST_DISCONNECT_REASON.lock().expect("LAST_DISCONNECT_REASON poisoned") = Some(reason);
+}
+
+
+/// Records that the reader has noticed that the connection to Radiator has been lost.
+fn mark_socket_gone(reason: DisconnectReason) {
+    record_disconnect(reason);
+    SOCKET_GONE.store(true, Ordering::SeqCst);
+    SOCKET_GONE_NOTIFY.notify_one();
+}
+
+
+/// Returns how long ago the current connection to Radiator was logged into, or `None` if there is
+/// no connection.
+pub(crate) fn connection_age() -> Option<Duration> {
+    CONNECTED_AT
+        .lock().expect("CONNECTED_AT poisoned")
+        .map(|connected_at| connected_at.elapsed())
+}
+
+
+/// Returns why the connection to Radiator was most recently lost, or `None` if it never was.
+pub(crate) fn last_disconnect_reason() -> Option<DisconnectReason> {
+    *LAST_DISCONNECT_REASON
+        .lock().expect("LAST_DISCONNECT_REASON poisoned")
+}
+
+
+/// Returns the current state of the circuit breaker guarding reconnection attempts.
+pub(crate) fn breaker_state(config: &RadiatorConfig) -> BreakerState {
+    CIRCUIT_BREAKER
+        .lock().expect("CIRCUIT_BREAKER poisoned")
+        .state(config)
+}
+
+
+/// Counts the successful logins to Radiator; changes whenever the connection has been
+/// re-established.
+pub(crate) fn connection_generation() -> u64 {
+    CONNECTION_GENERATION.load(Ordering::SeqCst)
+}
+
+
+pub fn start_message_processor(config: &RadiatorConfig) -> SocketState {
+    let (new_socket_sender, new_socket_receiver) = mpsc::channel(NEW_SOCKET_QUEUE_CAPACITY);
+    let (message_sender, message_receiver) = mpsc::channel(MESSAGE_QUEUE_CAPACITY);
+    let response_delimiter = config.response_delimiter_byte();
+    let log_prefix = config.log_prefix.as_bytes().to_vec();
 
     tokio::spawn(async move {
-        message_processor(new_socket_receiver, message_sender).await
+        message_processor(new_socket_receiver, message_sender, response_delimiter, log_prefix).await
     });
     SocketState {
         socket_writer: None,
         new_socket_sender,
         message_receiver,
+        reader_closer: None,
+    }
+}
+
+
+/// A phase of establishing a connection to Radiator.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub(crate) enum HandshakePhase {
+    /// Establishing the TCP connection.
+    Connect,
+
+    /// Sending the login credentials.
+    WriteCredentials,
+
+    /// Waiting for Radiator to accept or reject the login.
+    ReadLoginResponse,
+}
+impl HandshakePhase {
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            Self::Connect => "connect",
+            Self::WriteCredentials => "write_credentials",
+            Self::ReadLoginResponse => "read_login_response",
+        }
+    }
+}
+impl fmt::Display for HandshakePhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
     }
 }
 
@@ -85,17 +417,27 @@ pub fn start_message_processor() -> SocketState {
 #[derive(Debug)]
 pub(crate) enum Error {
     Io(io::Error),
+    Handshake { phase: HandshakePhase, error: io::Error },
+    HandshakeTimeout { phase: HandshakePhase },
     InvalidCredentials,
     UnexpectedLoginResponse { response: Vec<u8> },
     ReaderGone,
+    RetriesExhausted { attempts: u32 },
+    CircuitOpen,
+    InvalidCommand { command: Vec<u8> },
 }
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::Handshake { phase, error } => write!(f, "I/O error during {} phase of handshake: {}", phase, error),
+            Self::HandshakeTimeout { phase } => write!(f, "timed out during {} phase of handshake", phase),
             Self::InvalidCredentials => write!(f, "invalid credentials"),
-            Self::UnexpectedLoginResponse { .. } => write!(f, "unexpected login response"),
+            Self::UnexpectedLoginResponse { response } => write!(f, "unexpected login response {:?}", response),
             Self::ReaderGone => write!(f, "the reader has disappeared"),
+            Self::RetriesExhausted { attempts } => write!(f, "the connection was lost during each of {} attempts to send the command", attempts),
+            Self::CircuitOpen => write!(f, "not reconnecting to Radiator while the circuit breaker is open"),
+            Self::InvalidCommand { command } => write!(f, "command \"{}\" contains a NUL byte or line break", command.escape_ascii()),
         }
     }
 }
@@ -103,9 +445,14 @@ impl std::error::Error for Error {
     fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
             Self::Io(e) => Some(e),
+            Self::Handshake { error, .. } => Some(error),
+            Self::HandshakeTimeout { .. } => None,
             Self::InvalidCredentials => None,
             Self::UnexpectedLoginResponse { .. } => None,
             Self::ReaderGone => None,
+            Self::RetriesExhausted { .. } => None,
+            Self::CircuitOpen => None,
+            Self::InvalidCommand { .. } => None,
         }
     }
 }
@@ -116,94 +463,1013 @@ impl From<io::Error> for Error {
 }
 
 
-pub(crate) async fn connect_to_radiator(config: &RadiatorConfig, state: &mut SocketState) -> Result<(), Error> {
+/// Waits before a connection attempt, if so configured.
+///
+/// The wait consists of a random jitter of up to `radiator.reconnect_jitter_ms` and, on
+/// reconnection, of whatever remains of `radiator.min_reconnect_interval_ms` since the previous
+/// attempt. This keeps multiple exporters from reconnecting to a restarted Radiator in lockstep.
+pub(crate) async fn delay_connection_attempt(config: &RadiatorConfig) {
+    let previous_attempt = *LAST_CONNECTION_ATTEMPT
+        .lock().expect("LAST_CONNECTION_ATTEMPT poisoned");
+
+    let mut rate_limit_delay = Duration::ZERO;
+    if let (Some(min_interval_ms), Some(previous_attempt)) = (config.min_reconnect_interval_ms, previous_attempt) {
+        rate_limit_delay = Duration::from_millis(min_interval_ms)
+            .saturating_sub(previous_attempt.elapsed());
+    }
+    let jitter_delay = match config.reconnect_jitter_ms {
+        Some(jitter_ms) => Duration::from_millis(fastrand::u64(0..=jitter_ms)),
+        None => Duration::ZERO,
+    };
+
+    let delay = rate_limit_delay + jitter_delay;
+    if !delay.is_zero() {
+        info!(
+            event = "connection_delayed",
+            delay_ms = u64::try_from(delay.as_millis()).unwrap_or(u64::MAX),
+            rate_limit_ms = u64::try_from(rate_limit_delay.as_millis()).unwrap_or(u64::MAX),
+            jitter_ms = u64::try_from(jitter_delay.as_millis()).unwrap_or(u64::MAX),
+            "delaying connection attempt to Radiator",
+        );
+        tokio::time::sleep(delay).await;
+    }
+
+    *LAST_CONNECTION_ATTEMPT
+        .lock().expect("LAST_CONNECTION_ATTEMPT poisoned") = Some(Instant::now());
+}
+
+
+/// Runs one phase of the handshake with Radiator, failing if it is not done by the deadline.
+async fn handshake_phase<T, F: Future<Output = Result<T, io::Error>>>(
+    phase: HandshakePhase,
+    deadline: Instant,
+    future: F,
+) -> Result<T, Error> {
+    match tokio::time::timeout_at(deadline.into(), future).await {
+        Ok(Ok(value)) => Ok(value),
+        Ok(Err(error)) => {
+            warn!(event = "login_failed", reason = "io_error", phase = phase.as_str(), error = %error, "I/O error while connecting to Radiator");
+            Err(Error::Handshake { phase, error })
+        },
+        Err(_elapsed) => {
+            warn!(event = "login_failed", reason = "timeout", phase = phase.as_str(), "timed out while connecting to Radiator");
+            Err(Error::HandshakeTimeout { phase })
+        },
+    }
+}
+
+
+/// Applies the configured TCP options to the connection to Radiator.
+fn set_socket_options(connection: &TcpStream, config: &RadiatorConfig) -> io::Result<()> {
+    // commands are small and latency-sensitive; do not wait to coalesce them
+    connection.set_nodelay(config.tcp_nodelay)?;
+    if let Some(keepalive_secs) = config.tcp_keepalive_secs {
+        let keepalive = TcpKeepalive::new()
+            .with_time(Duration::from_secs(keepalive_secs));
+        SockRef::from(connection).set_tcp_keepalive(&keepalive)?;
+    }
+    Ok(())
+}
+
+
+/// Orders the given addresses alternating between IPv6 and IPv4, starting with IPv6 (as RFC 8305
+/// recommends), while keeping the order within each family.
+fn interleave_families(addresses: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (mut ipv6, mut ipv4): (Vec<SocketAddr>, Vec<SocketAddr>) = addresses.into_iter()
+        .partition(|address| address.is_ipv6());
+    let mut interleaved = Vec::with_capacity(ipv6.len() + ipv4.len());
+    ipv6.reverse();
+    ipv4.reverse();
+    loop {
+        match (ipv6.pop(), ipv4.pop()) {
+            (None, None) => break,
+            (first, second) => interleaved.extend(first.into_iter().chain(second)),
+        }
+    }
+    interleaved
+}
+
+
+/// Connects to whichever of the given addresses accepts the connection first ("happy eyeballs").
+///
+/// Each attempt gets a head start of [`CONNECTION_ATTEMPT_DELAY`] before the next address is tried
+/// in parallel; if an attempt fails, the next one starts right away. This way, an address family
+/// that is broken (e.g. IPv6 packets silently being dropped) only delays the connection instead of
+/// preventing it. The remaining attempts are abandoned once one of them succeeds.
+async fn connect_happy_eyeballs(addresses: Vec<SocketAddr>) -> io::Result<TcpStream> {
+    let mut remaining_addresses = interleave_families(addresses).into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        if let Some(address) = remaining_addresses.next() {
+            debug!(event = "connect_attempt", %address, "trying to connect to Radiator");
+            attempts.spawn(TcpStream::connect(address));
+        } else if attempts.is_empty() {
+            return Err(last_error.unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no address to connect to")));
+        }
+
+        let finished = if remaining_addresses.len() > 0 {
+            match tokio::time::timeout(CONNECTION_ATTEMPT_DELAY, attempts.join_next()).await {
+                Ok(finished) => finished,
+                // give the next address a chance as well
+                Err(_elapsed) => continue,
+            }
+        } else {
+            attempts.join_next().await
+        };
+        match finished {
+            Some(Ok(Ok(connection))) => return Ok(connection),
+            Some(Ok(Err(e))) => last_error = Some(e),
+            Some(Err(join_error)) => last_error = Some(io::Error::other(join_error)),
+            None => {},
+        }
+    }
+}
+
+
+/// A connection to Radiator that has been logged into but not yet handed to the reader.
+#[derive(Debug)]
+struct LoggedInConnection {
+    writer: OwnedWriteHalf,
+    reader: BufReader<OwnedReadHalf>,
+    logged_in_at: Instant,
+}
+
+
+/// Connects and logs into Radiator.
+async fn log_in(config: &RadiatorConfig) -> Result<LoggedInConnection, Error> {
     // connect
-    let connection = TcpStream::connect((config.target, config.mgmt_port)).await?;
+    let connect_deadline = Instant::now() + Duration::from_millis(config.connect_timeout_ms);
+    let connection = handshake_phase(
+        HandshakePhase::Connect,
+        connect_deadline,
+        async {
+            // an address as the target resolves to itself; this only races once there are several
+            let addresses = lookup_host((config.target, config.mgmt_port)).await?.collect();
+            connect_happy_eyeballs(addresses).await
+        },
+    ).await?;
+    info!(event = "connected", target = %config.target, port = config.mgmt_port, "connected to Radiator management port");
+    if let Err(e) = set_socket_options(&connection, config) {
+        // the connection works nonetheless
+        warn!(event = "socket_options_failed", error = %e, "failed to set socket options on Radiator connection");
+    }
     let (read_half, mut write_half) = connection.into_split();
     let mut buffered_reader = BufReader::new(read_half);
 
-    // switch to binary mode and log in
-    let login_string = format!("BINARY\r\nLOGIN {} {}\0", config.username, config.password);
-    let login_bytes = login_string.as_bytes(); // UTF-8
-    write_half.write_all(&login_bytes).await?;
-    write_half.flush().await?;
+    // a Radiator that accepts the connection but never answers must not stall us forever
+    let login_deadline = Instant::now() + Duration::from_millis(config.login_timeout_ms);
+
+    // switch to binary mode (unless disabled) and log in
+    let mut login_bytes = Vec::new();
+    if config.binary_mode {
+        login_bytes.extend_from_slice(b"BINARY\r\n");
+    }
+    login_bytes.extend_from_slice(format!("LOGIN {} {}", config.username, config.password).as_bytes()); // UTF-8
+    login_bytes.extend_from_slice(config.command_terminator());
+    handshake_phase(HandshakePhase::WriteCredentials, login_deadline, async {
+        write_half.write_all(&login_bytes).await?;
+        write_half.flush().await
+    }).await?;
 
     // read login response
+    let response_delimiter = config.response_delimiter_byte();
     let mut buf = Vec::new();
-    buffered_reader.read_until(b'\0', &mut buf).await?;
-    if buf == b"LOGGEDIN\0" {
-        // store writing socket
-        state.socket_writer = Some(write_half);
-
-        // send fresh reading socket to reading task
-        state.new_socket_sender.send(buffered_reader)
-            .expect("sending new socket failed");
-
-        Ok(())
-    } else if buf == b"BADLOGIN\0" {
+    handshake_phase(
+        HandshakePhase::ReadLoginResponse,
+        login_deadline,
+        buffered_reader.read_until(response_delimiter, &mut buf),
+    ).await?;
+    if buf.last() == Some(&response_delimiter) {
+        buf.pop();
+    }
+    if buf == b"LOGGEDIN" {
+        info!(event = "login_ok", username = %config.username, "logged into Radiator");
+        Ok(LoggedInConnection {
+            writer: write_half,
+            reader: buffered_reader,
+            logged_in_at: Instant::now(),
+        })
+    } else if buf == b"BADLOGIN" {
+        warn!(event = "login_failed", reason = "invalid_credentials", username = %config.username, "Radiator rejected login");
         Err(Error::InvalidCredentials)
     } else {
+        warn!(event = "login_failed", reason = "unexpected_response", username = %config.username, "unexpected Radiator login response");
+        debug!(event = "login_failed", response = %hex_dump(&buf), "unexpected Radiator login response bytes");
         Err(Error::UnexpectedLoginResponse { response: buf })
     }
 }
 
-async fn write_command(writer: &mut OwnedWriteHalf, command: &[u8]) -> Result<(), Error> {
-    // no NUL byte in command
-    assert!(command.iter().all(|b| *b != 0x00), "no NUL byte in command");
 
-    let mut terminated_command = Vec::with_capacity(command.len() + 1);
-    terminated_command.extend_from_slice(command);
-    terminated_command.push(b'\0');
+/// Makes the given connection the one through which commands are exchanged.
+async fn install_connection(state: &mut SocketState, connection: LoggedInConnection) {
+    CONNECTION_GENERATION.fetch_add(1, Ordering::SeqCst);
+    *CONNECTED_AT.lock().expect("CONNECTED_AT poisoned") = Some(connection.logged_in_at);
+    *LAST_ACTIVITY.lock().expect("LAST_ACTIVITY poisoned") = Some(Instant::now());
+
+    // store writing socket
+    state.socket_writer = Some(connection.writer);
+
+    // send fresh reading socket to reading task
+    // (replacing the closer makes the reader abandon the previous socket if it still reads it)
+    let (reader_closer, close_receiver) = oneshot::channel();
+    state.reader_closer = Some(reader_closer);
+    state.new_socket_sender.send((connection.reader, close_receiver)).await
+        .expect("sending new socket failed");
+}
+
+
+pub(crate) async fn connect_to_radiator(config: &RadiatorConfig, state: &mut SocketState) -> Result<(), Error> {
+    let connection = log_in(config).await?;
+    install_connection(state, connection).await;
+    Ok(())
+}
+
+
+/// Whether a standby connection (`radiator.hot_standby`) is currently ready to take over.
+pub(crate) fn standby_ready() -> bool {
+    STANDBY_READY.load(Ordering::SeqCst)
+}
+
+
+/// Starts maintaining a standby connection to Radiator that replaces the primary connection as soon
+/// as it is lost.
+pub(crate) fn start_standby() {
+    let (request_sender, request_receiver) = mpsc::unbounded_channel();
+    STANDBY_REQUESTS
+        .set(request_sender).expect("STANDBY_REQUESTS already set?!");
+    tokio::spawn(keep_standby(request_receiver));
+}
+
+
+/// Establishes the standby connection and keeps it until it is requested, re-establishing it
+/// whenever it has been handed over or lost.
+///
+/// The standby connection is independent of the primary one: it does not count towards the circuit
+/// breaker (but pauses while the breaker is open) and it is closed by simply dropping it.
+async fn keep_standby(mut requests: UnboundedReceiver<oneshot::Sender<LoggedInConnection>>) {
+    // changing the delimiter requires a restart
+    let response_delimiter = CONFIG
+        .get().expect("CONFIG not set?!")
+        .radiator.response_delimiter_byte();
+    loop {
+        // requests made while no standby connection was ready are turned down
+        while requests.try_recv().is_ok() {}
+
+        // the credentials may have been reloaded in the meantime
+        let config_guard = CONFIG
+            .get().expect("CONFIG not set?!");
+        let config = &config_guard.radiator;
+
+        if breaker_state(config) == BreakerState::Open {
+            tokio::time::sleep(STANDBY_RETRY_INTERVAL).await;
+            continue;
+        }
+        let mut connection = match log_in(config).await {
+            Ok(c) => c,
+            Err(e) => {
+                warn!(event = "standby_failed", error = %e, "failed to establish standby connection to Radiator");
+                tokio::time::sleep(STANDBY_RETRY_INTERVAL).await;
+                continue;
+            },
+        };
+        info!(event = "standby_ready", "standby connection to Radiator established");
+        STANDBY_READY.store(true, Ordering::SeqCst);
+
+        // wait until the standby connection is needed, noticing if Radiator closes it meanwhile
+        // (a message that has only partially arrived is read to its end before handing the
+        // connection over; the reader would otherwise take its rest for a message of its own)
+        let mut buf = Vec::new();
+        let request = loop {
+            tokio::select! {
+                request = requests.recv(), if buf.is_empty() => break request,
+                read_result = connection.reader.read_until(response_delimiter, &mut buf) => {
+                    match read_result {
+                        Ok(_) if buf.last() == Some(&response_delimiter) => {
+                            // a log message; nobody is interested in it
+                            buf.clear();
+                        },
+                        Ok(_) | Err(_) => {
+                            warn!(event = "standby_lost", "Radiator closed the standby connection");
+                            break None;
+                        },
+                    }
+                },
+            }
+        };
+        STANDBY_READY.store(false, Ordering::SeqCst);
+        if let Some(reply_sender) = request {
+            // if the requester has given up in the meantime, the connection is simply dropped
+            let _ = reply_sender.send(connection);
+        }
+    }
+}
+
+
+/// Obtains the standby connection if one is ready.
+async fn take_standby() -> Option<LoggedInConnection> {
+    if !standby_ready() {
+        return None;
+    }
+    let requests = STANDBY_REQUESTS.get()?;
+    let (reply_sender, reply_receiver) = oneshot::channel();
+    requests.send(reply_sender).ok()?;
+    match tokio::time::timeout(STANDBY_HANDOVER_TIMEOUT, reply_receiver).await {
+        Ok(reply) => reply.ok(),
+        Err(_elapsed) => {
+            warn!(event = "standby_handover_timeout", "the standby connection was not handed over in time; connecting anew");
+            None
+        },
+    }
+}
+
+
+/// Discards any responses that are still queued, so that they are not taken for the response to
+/// the next command.
+fn drain_messages(state: &mut SocketState) {
+    let mut drained = 0;
+    while state.message_receiver.try_recv().is_ok() {
+        drained += 1;
+    }
+    if drained > 0 {
+        debug!(event = "messages_drained", count = drained, "discarded stale responses from Radiator");
+    }
+}
+
+
+/// Logs out of Radiator and closes the connection for the exporter shutting down.
+///
+/// Waits at most `deadline` for a command in progress to finish and for logging out.
+pub(crate) async fn shut_down_connection(deadline: Duration) {
+    let deadline_at = Instant::now() + deadline;
+    let Some(socket_state) = SOCKET_STATE.get() else { return };
+    let Ok(mut state_guard) = tokio::time::timeout_at(deadline_at.into(), socket_state.lock()).await else {
+        warn!(event = "shutdown_timeout", "a command to Radiator is still in progress; not waiting for it");
+        return;
+    };
+    drain_messages(&mut state_guard);
+    log_out(&mut state_guard, deadline_at).await;
+}
+
+
+/// Logs out of Radiator (unless the connection has already been lost) and closes the connection,
+/// giving up at `deadline_at`.
+async fn log_out(state: &mut SocketState, deadline_at: Instant) {
+    // stop reading; Radiator closing the connection in response is expected
+    state.reader_closer = None;
+    let Some(mut writer) = state.socket_writer.take() else { return };
+    *CONNECTED_AT.lock().expect("CONNECTED_AT poisoned") = None;
+    if socket_gone() {
+        // Radiator has already closed the connection
+        return;
+    }
+    let config = &CONFIG
+        .get().expect("CONFIG not set?!")
+        .radiator;
+    let log_out = async {
+        if !config.logout_command.is_empty() {
+            write_command(&mut writer, config.logout_command.as_bytes(), config).await?;
+        }
+        writer.shutdown().await?;
+        Ok::<(), Error>(())
+    };
+    match tokio::time::timeout_at(deadline_at.into(), log_out).await {
+        Ok(Ok(())) => info!(event = "logged_out", "logged out of Radiator and closed the connection"),
+        Ok(Err(e)) => warn!(event = "logout_failed", error = %e, "failed to log out of Radiator"),
+        Err(_) => warn!(event = "logout_failed", "timed out logging out of Radiator"),
+    }
+}
+
+
+/// Returns the command as it is sent to Radiator, i.e. wrapped in the command template (if any).
+pub(crate) fn wrap_command(command: &[u8], command_template: Option<&str>) -> Vec<u8> {
+    let (prefix, suffix) = match command_template {
+        Some(ct) => ct.split_once("{command}").expect("{command} missing from command template"),
+        None => ("", ""),
+    };
+
+    let mut wrapped_command = Vec::with_capacity(prefix.len() + command.len() + suffix.len() + 1);
+    wrapped_command.extend_from_slice(prefix.as_bytes());
+    wrapped_command.extend_from_slice(command);
+    wrapped_command.extend_from_slice(suffix.as_bytes());
+    wrapped_command
+}
+
 
+async fn write_command(writer: &mut OwnedWriteHalf, command: &[u8], config: &RadiatorConfig) -> Result<(), Error> {
+    // a NUL byte or the terminator would end the command prematurely
+    let terminator = config.command_terminator();
+    if command.iter().any(|b| *b == 0x00 || terminator.contains(b)) {
+        return Err(Error::InvalidCommand { command: command.to_vec() });
+    }
+
+    // wrap the command if required (the template has been checked for NUL bytes and newlines)
+    let wrapped_command = wrap_command(command, config.command_template.as_deref());
+    let mut terminated_command = wrapped_command.clone();
+    terminated_command.extend_from_slice(terminator);
+    *OUTSTANDING_COMMAND.lock().expect("OUTSTANDING_COMMAND poisoned") = Some(wrapped_command);
+
+    COMMANDS.fetch_add(1, Ordering::Relaxed);
+    *LAST_ACTIVITY.lock().expect("LAST_ACTIVITY poisoned") = Some(Instant::now());
     writer.write_all(&terminated_command).await?;
     writer.flush().await?;
 
     Ok(())
 }
 
-async fn communicate_inner(command: &[u8]) -> Result<Vec<u8>, Error> {
+/// Establishes a new connection to Radiator after the previous one was lost for the given reason,
+/// unless the circuit breaker forbids it.
+///
+/// If a standby connection is ready, it is used instead.
+async fn reconnect(config: &RadiatorConfig, state: &mut SocketState, reason: DisconnectReason) -> Result<(), Error> {
+    if let Some(standby) = take_standby().await {
+        info!(event = "standby_promoted", "replacing the connection to Radiator with the standby connection");
+        record_reconnect(reason);
+        install_connection(state, standby).await;
+        // anything still queued belongs to the previous connection
+        drain_messages(state);
+        return Ok(());
+    }
+
+    // do not try if that has failed too often recently
+    if breaker_state(config) == BreakerState::Open {
+        return Err(Error::CircuitOpen);
+    }
+    record_reconnect(reason);
+
+    delay_connection_attempt(config).await;
+    connect_guarded(config, state).await
+}
+
+
+/// Connects to Radiator, recording the outcome in the circuit breaker.
+async fn connect_guarded(config: &RadiatorConfig, state: &mut SocketState) -> Result<(), Error> {
+    let connect_result = connect_to_radiator(config, state).await;
+    {
+        let mut breaker_guard = CIRCUIT_BREAKER
+            .lock().expect("CIRCUIT_BREAKER poisoned");
+        match connect_result {
+            Ok(()) => breaker_guard.record_success(),
+            Err(_) => breaker_guard.record_failure(config),
+        }
+    }
+    connect_result?;
+
+    // anything still queued belongs to the previous connection
+    drain_messages(state);
+    Ok(())
+}
+
+
+/// Reconnects to Radiator if the reader has noticed that the connection has been lost, e.g. because
+/// Radiator closed it while it was idle.
+///
+/// Called before a scrape so that its first command does not have to fail before reconnecting.
+pub(crate) async fn ensure_connected() -> Result<(), Error> {
+    if !socket_gone() {
+        return Ok(());
+    }
+
     let mut state_guard = SOCKET_STATE
         .get().expect("SOCKET_STATE not set?!")
         .lock().await;
-    let writer = state_guard.socket_writer
-        .as_mut().expect("SOCKET_STATE.socket_writer not set?!");
+    // another task might have reconnected while we were waiting for the lock
+    if !socket_gone() {
+        return Ok(());
+    }
+    let config_guard = CONFIG
+        .get().expect("CONFIG not set?!");
 
-    // try sending
-    if let Err(_) = write_command(writer, command).await {
-        warn!("initial writing attempt failed; reconnecting");
+    debug!(event = "reconnect", proactive = true, "connection to Radiator was lost while idle; reconnecting before scraping");
+    let reason = last_disconnect_reason()
+        .unwrap_or(DisconnectReason::ReaderEof);
+    reconnect(&config_guard.radiator, &mut state_guard, reason).await?;
+
+    // the reader is waiting for the new socket; do not have the next command retried
+    SOCKET_GONE.store(false, Ordering::SeqCst);
+    Ok(())
+}
 
-        // that failed; try making a new connection
+
+/// Starts closing the connection to Radiator whenever no command has been sent for the given time.
+///
+/// The connection is re-established by the next command.
+pub(crate) fn start_idle_reaper(idle_timeout: Duration) {
+    tokio::spawn(reap_idle_connection(idle_timeout));
+}
+
+
+async fn reap_idle_connection(idle_timeout: Duration) {
+    loop {
+        let last_activity = *LAST_ACTIVITY
+            .lock().expect("LAST_ACTIVITY poisoned");
+        let Some(last_activity) = last_activity else {
+            tokio::time::sleep(idle_timeout).await;
+            continue;
+        };
+        tokio::time::sleep_until((last_activity + idle_timeout).into()).await;
+
+        let mut state_guard = SOCKET_STATE
+            .get().expect("SOCKET_STATE not set?!")
+            .lock().await;
+        let still_idle = LAST_ACTIVITY
+            .lock().expect("LAST_ACTIVITY poisoned")
+            .is_some_and(|la| la.elapsed() >= idle_timeout);
+        if !still_idle {
+            // a command has been sent in the meantime
+            continue;
+        }
+        if state_guard.socket_writer.is_none() || socket_gone() {
+            // already closed (or lost, in which case the next scrape reconnects anyway)
+            drop(state_guard);
+            tokio::time::sleep(idle_timeout).await;
+            continue;
+        }
+
+        info!(event = "idle_disconnect", idle_timeout_ms = u64::try_from(idle_timeout.as_millis()).unwrap_or(u64::MAX), "closing idle connection to Radiator");
+        drain_messages(&mut state_guard);
+        log_out(&mut state_guard, Instant::now() + IDLE_LOGOUT_TIMEOUT).await;
+        record_disconnect(DisconnectReason::Idle);
+    }
+}
+
+
+/// A connection to Radiator established for a single scrape, if `radiator.connection_mode` is
+/// `per_scrape`.
+///
+/// Scrapes wait for each other, since each of them closes the connection once it is done.
+pub(crate) struct ScrapeConnection {
+    _guard: MutexGuard<'static, ()>,
+}
+impl ScrapeConnection {
+    /// Waits for other scrapes to finish, then connects to Radiator, unless the circuit breaker
+    /// forbids it.
+    pub async fn open() -> Result<Self, Error> {
+        let guard = SCRAPE_CONNECTION.lock().await;
+
+        let mut state_guard = SOCKET_STATE
+            .get().expect("SOCKET_STATE not set?!")
+            .lock().await;
         let config_guard = CONFIG
             .get().expect("CONFIG not set?!");
+        if breaker_state(&config_guard.radiator) == BreakerState::Open {
+            return Err(Error::CircuitOpen);
+        }
+        connect_guarded(&config_guard.radiator, &mut state_guard).await?;
+        SOCKET_GONE.store(false, Ordering::SeqCst);
+
+        Ok(Self {
+            _guard: guard,
+        })
+    }
+
+    /// Closes the connection to Radiator.
+    pub async fn close(self) {
+        let mut state_guard = SOCKET_STATE
+            .get().expect("SOCKET_STATE not set?!")
+            .lock().await;
+        state_guard.socket_writer = None;
+        state_guard.reader_closer = None;
+        *CONNECTED_AT.lock().expect("CONNECTED_AT poisoned") = None;
+        drain_messages(&mut state_guard);
+        debug!(event = "disconnected", "closed connection to Radiator after scrape");
+    }
+}
+
+
+async fn communicate_inner(command: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut state_guard = SOCKET_STATE
+        .get().expect("SOCKET_STATE not set?!")
+        .lock().await;
+    let config_guard = CONFIG
+        .get().expect("CONFIG not set?!");
+    if state_guard.socket_writer.is_none() {
+        // closed after being idle; reconnect now that it is needed again
+        debug!(event = "reconnect", idle = true, "reconnecting to Radiator after closing the idle connection");
+        reconnect(&config_guard.radiator, &mut state_guard, DisconnectReason::Idle).await?;
+    } else if socket_gone() {
+        // lost during a previous attempt; writing to it would not be noticed as failing
+        debug!(event = "reconnect", "connection to Radiator was lost; reconnecting before sending the command");
+        let reason = last_disconnect_reason()
+            .unwrap_or(DisconnectReason::ReaderEof);
+        reconnect(&config_guard.radiator, &mut state_guard, reason).await?;
+        SOCKET_GONE.store(false, Ordering::SeqCst);
+    }
+    let writer = state_guard.socket_writer
+        .as_mut().expect("SOCKET_STATE.socket_writer not set?!");
 
-        // if this fails as well, fail the whole call
-        connect_to_radiator(&config_guard.radiator, &mut *state_guard).await?;
+    // try sending
+    let mut sent_at = Instant::now();
+    match write_command(writer, command, &config_guard.radiator).await {
+        Ok(()) => {},
+        Err(e @ Error::InvalidCommand { .. }) => {
+            // a new connection would not help
+            return Err(e);
+        },
+        Err(e) => {
+            // that failed; try making a new connection
+            // (if this fails as well, fail the whole call)
+            warn!(event = "reconnect", "initial writing attempt failed; reconnecting");
+            // if the reader has already noticed that the connection is gone, it knows better why
+            let reason = match (last_disconnect_reason(), &e) {
+                (Some(reader_reason), _) if socket_gone() => reader_reason,
+                (_, Error::Io(io_error)) => DisconnectReason::from_io_error(io_error, false),
+                _ => DisconnectReason::WriteFailed,
+            };
+            record_disconnect(reason);
+            reconnect(&config_guard.radiator, &mut state_guard, reason).await?;
 
-        // try sending again (give up if it fails)
-        let new_writer = state_guard.socket_writer
-            .as_mut().expect("SOCKET_STATE.socket_writer not set?!");
-        write_command(new_writer, command).await?;
+            // try sending again (give up if it fails)
+            let new_writer = state_guard.socket_writer
+                .as_mut().expect("SOCKET_STATE.socket_writer not set?!");
+            sent_at = Instant::now();
+            write_command(new_writer, command, &config_guard.radiator).await?;
+        },
     }
 
-    if SOCKET_GONE.swap(false, Ordering::SeqCst) {
+    if socket_gone() {
         // the socket has been torn down in the meantime
         return Err(Error::ReaderGone);
     }
 
-    // receive a response
-    state_guard.message_receiver
-        .recv().await
-        .ok_or(Error::ReaderGone)
+    // receive a response, unless the socket is torn down while waiting for it
+    let response = loop {
+        tokio::select! {
+            biased;
+            message = state_guard.message_receiver.recv() => break message.ok_or(Error::ReaderGone)?,
+            _ = SOCKET_GONE_NOTIFY.notified() => {
+                // (the notification might be left over from an earlier connection)
+                if socket_gone() {
+                    return Err(Error::ReaderGone);
+                }
+            },
+        }
+    };
+    record_command_duration(command, sent_at.elapsed());
+    Ok(response)
+}
+
+/// Sends a command and receives its response, trying again on a new connection up to
+/// `max_retries` times if the connection is lost in the meantime.
+async fn communicate(command: &[u8], max_retries: u32) -> Result<Vec<u8>, Error> {
+    retry_when_reader_gone(max_retries, || communicate_inner(command)).await
 }
 
-pub(crate) async fn communicate(command: &[u8]) -> Result<Vec<u8>, Error> {
-    for _ in 0..3 {
-        match communicate_inner(command).await {
+/// Makes the given attempt at exchanging a command and its response, and up to `max_retries` more
+/// if the connection is lost in the meantime.
+async fn retry_when_reader_gone<F, A>(max_retries: u32, mut attempt: F) -> Result<Vec<u8>, Error>
+where
+    F: FnMut() -> A,
+    A: Future<Output = Result<Vec<u8>, Error>>,
+{
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        match attempt().await {
             Ok(rr) => return Ok(rr),
-            Err(Error::ReaderGone) => continue,
+            Err(Error::ReaderGone) if attempts <= max_retries => {
+                debug!(event = "retry", attempt = attempts, "connection lost while waiting for a response; trying again");
+            },
+            Err(Error::ReaderGone) if attempts > 1 => return Err(Error::RetriesExhausted { attempts }),
             Err(e) => return Err(e),
         }
     }
+}
+
+
+/// A means of exchanging commands and responses with Radiator's management interface.
+#[async_trait]
+pub(crate) trait RadiatorTransport: fmt::Debug + Send + Sync {
+    /// Sends a command (without terminator) to Radiator and returns its response (without
+    /// delimiter), ignoring any log messages in between.
+    async fn communicate(&self, command: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+
+/// The TCP connection to Radiator's management port, shared by the whole exporter.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct TcpTransport;
+#[async_trait]
+impl RadiatorTransport for TcpTransport {
+    async fn communicate(&self, command: &[u8]) -> Result<Vec<u8>, Error> {
+        let max_retries = CONFIG
+            .get().expect("CONFIG not set?!")
+            .radiator.max_retries;
+        communicate(command, max_retries).await
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::{mpsc, Mutex, oneshot};
+    use tokio::task::JoinHandle;
+
+    use crate::config::RadiatorConfig;
+    use crate::failure::FailureCategory;
+    use super::{
+        drain_messages, Error, message_processor, MESSAGE_QUEUE_CAPACITY, NEW_SOCKET_QUEUE_CAPACITY,
+        OUTSTANDING_COMMAND, retry_when_reader_gone, SocketState, write_command,
+    };
+
+
+    const BASE_CONFIG: &str = r#"
+[www]
+bind_address = "127.0.0.1"
+port = 10014
+
+[radiator]
+target = "127.0.0.1"
+mgmt_port = 9000
+username = "prom"
+password = "secret"
+"#;
+
+    // the tests share the outstanding command
+    static EXCLUSIVE: Mutex<()> = Mutex::const_new(());
+
+
+    /// Returns the Radiator configuration with the given additional settings.
+    fn radiator_config(settings: &str) -> RadiatorConfig {
+        crate::config::parse(&format!("{}{}", BASE_CONFIG, settings))
+            .unwrap_or_else(|e| panic!("error in configuration: {}", e))
+            .radiator
+    }
+
+    /// Starts a reader for the given configuration, returning its state (without a connection) and
+    /// its task.
+    fn start_reader(config: &RadiatorConfig) -> (SocketState, JoinHandle<()>) {
+        let (new_socket_sender, new_socket_receiver) = mpsc::channel(NEW_SOCKET_QUEUE_CAPACITY);
+        let (message_sender, message_receiver) = mpsc::channel(MESSAGE_QUEUE_CAPACITY);
+        let reader = tokio::spawn(message_processor(
+            new_socket_receiver,
+            message_sender,
+            config.response_delimiter_byte(),
+            config.log_prefix.as_bytes().to_vec(),
+        ));
+        let state = SocketState {
+            socket_writer: None,
+            new_socket_sender,
+            message_receiver,
+            reader_closer: None,
+        };
+        (state, reader)
+    }
+
+    /// Connects the given state to a fake Radiator over the loopback interface and returns
+    /// Radiator's end of the connection.
+    async fn connect(state: &mut SocketState) -> TcpStream {
+        let listener = TcpListener::bind("127.0.0.1:0").await
+            .expect("failed to listen");
+        let address = listener.local_addr()
+            .expect("failed to obtain listening address");
+        let (exporter_end, accepted) = tokio::join!(TcpStream::connect(address), listener.accept());
+        let (read_half, write_half) = exporter_end
+            .expect("failed to connect")
+            .into_split();
 
-    Err(Error::ReaderGone)
+        let (reader_closer, close_receiver) = oneshot::channel();
+        state.socket_writer = Some(write_half);
+        state.reader_closer = Some(reader_closer);
+        state.new_socket_sender.send((BufReader::new(read_half), close_receiver)).await
+            .expect("sending new socket failed");
+        accepted.expect("failed to accept").0
+    }
+
+    fn command_outstanding() -> bool {
+        OUTSTANDING_COMMAND
+            .lock().expect("OUTSTANDING_COMMAND poisoned")
+            .is_some()
+    }
+
+    /// Sends the command and has Radiator answer it with the given response.
+    async fn respond(state: &mut SocketState, radiator: &mut TcpStream, config: &RadiatorConfig, command: &str, response: &str) {
+        let writer = state.socket_writer
+            .as_mut().expect("not connected");
+        write_command(writer, command.as_bytes(), config).await
+            .expect("failed to send command");
+        let mut response_bytes = response.as_bytes().to_vec();
+        response_bytes.push(config.response_delimiter_byte());
+        radiator.write_all(&response_bytes).await
+            .expect("failed to send response");
+    }
+
+    /// Like [`respond`], but returns only once the reader has taken the response.
+    async fn exchange(state: &mut SocketState, radiator: &mut TcpStream, config: &RadiatorConfig, command: &str, response: &str) {
+        respond(state, radiator, config, command, response).await;
+        wait_until_answered().await;
+    }
+
+    /// Waits until the reader has taken the response to the outstanding command.
+    async fn wait_until_answered() {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while command_outstanding() {
+                tokio::time::sleep(Duration::from_millis(1)).await;
+            }
+        }).await.expect("the reader did not take the response");
+    }
+
+    /// Answers one command more than fit into the message queue, leaving the reader waiting for
+    /// room in the queue.
+    async fn fill_queue(state: &mut SocketState, radiator: &mut TcpStream, config: &RadiatorConfig) {
+        for i in 0..=MESSAGE_QUEUE_CAPACITY {
+            let command = format!("STATS Handler.{}", i);
+            exchange(state, radiator, config, &command, &format!("{}\nresponse {}", command, i)).await;
+        }
+    }
+
+    /// Waits up to the given time for the reader to finish and returns whether it has.
+    async fn reader_finished(reader: &mut JoinHandle<()>, within: Duration) -> bool {
+        match tokio::time::timeout(within, reader).await {
+            Ok(result) => {
+                result.expect("reader panicked");
+                true
+            },
+            Err(_elapsed) => false,
+        }
+    }
+
+
+    /// Exchanges a command with a reader that dies during the given number of attempts, returning
+    /// the outcome and the number of attempts made.
+    async fn communicate_with_dying_reader(max_retries: u32, deaths: u32) -> (Result<Vec<u8>, Error>, u32) {
+        let mut attempts = 0;
+        let result = retry_when_reader_gone(max_retries, || {
+            attempts += 1;
+            let outcome = if attempts <= deaths {
+                Err(Error::ReaderGone)
+            } else {
+                Ok(b"STATS .\nAccess requests:5".to_vec())
+            };
+            async { outcome }
+        }).await;
+        (result, attempts)
+    }
+
+
+    #[tokio::test]
+    async fn stale_responses_are_drained() {
+        let _exclusive = EXCLUSIVE.lock().await;
+        let config = radiator_config("");
+        let (mut state, _reader) = start_reader(&config);
+        let mut radiator = connect(&mut state).await;
+
+        // responses to commands whose senders have stopped waiting for them
+        for object_kind in ["Handler", "Client", "Realm"] {
+            let command = format!("STATS {}.0", object_kind);
+            exchange(&mut state, &mut radiator, &config, &command, &format!("{}\nstale", command)).await;
+        }
+
+        drain_messages(&mut state);
+        exchange(&mut state, &mut radiator, &config, "STATS .", "STATS .\nfresh").await;
+        let response = state.message_receiver.recv().await
+            .expect("reader is gone");
+        assert_eq!(response, b"STATS .\nfresh");
+        assert!(state.message_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn newline_framed_responses() {
+        let _exclusive = EXCLUSIVE.lock().await;
+        let config = radiator_config("response_delimiter = \"\\n\"\n");
+        let (mut state, _reader) = start_reader(&config);
+        let mut radiator = connect(&mut state).await;
+
+        exchange(&mut state, &mut radiator, &config, "STATS .", "LOG 2026-10-16 12:00:00 request received\nAccess requests:5\u{1}Access accepts:3").await;
+        let response = state.message_receiver.recv().await
+            .expect("reader is gone");
+        assert_eq!(response, b"Access requests:5\x01Access accepts:3");
+        assert!(state.message_receiver.try_recv().is_err());
+
+        // a server that echoes the command sends the echo as a line of its own
+        // (with binary_mode = false, the echo keeps the carriage return)
+        for echo in ["STATS .", "STATS .\r"] {
+            exchange(&mut state, &mut radiator, &config, "STATS .", &format!("{}\nAccess requests:6", echo)).await;
+            let response = state.message_receiver.recv().await
+                .expect("reader is gone");
+            assert_eq!(response, b"Access requests:6");
+            assert!(state.message_receiver.try_recv().is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn full_queue_pauses_reading() {
+        let _exclusive = EXCLUSIVE.lock().await;
+        let config = radiator_config("");
+        let (mut state, _reader) = start_reader(&config);
+        let mut radiator = connect(&mut state).await;
+        fill_queue(&mut state, &mut radiator, &config).await;
+
+        // the response to the next command is not read while the queue is full
+        let command = format!("STATS Handler.{}", MESSAGE_QUEUE_CAPACITY + 1);
+        respond(&mut state, &mut radiator, &config, &command, &format!("{}\nlast response", command)).await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(command_outstanding());
+
+        // ...until a response has been taken out
+        assert_eq!(state.message_receiver.recv().await.expect("reader is gone"), b"STATS Handler.0\nresponse 0");
+        wait_until_answered().await;
+        for i in 1..=MESSAGE_QUEUE_CAPACITY {
+            let expected = format!("STATS Handler.{}\nresponse {}", i, i);
+            assert_eq!(state.message_receiver.recv().await.expect("reader is gone"), expected.as_bytes());
+        }
+        let expected = format!("{}\nlast response", command);
+        assert_eq!(state.message_receiver.recv().await.expect("reader is gone"), expected.as_bytes());
+        assert!(state.message_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn closing_stops_paused_reader() {
+        let _exclusive = EXCLUSIVE.lock().await;
+        let config = radiator_config("");
+        let (mut state, mut reader) = start_reader(&config);
+        let mut radiator = connect(&mut state).await;
+        fill_queue(&mut state, &mut radiator, &config).await;
+
+        // once there are no more sockets to come, the reader only finishes if it abandons this one
+        drop(state.new_socket_sender);
+        assert!(!reader_finished(&mut reader, Duration::from_millis(100)).await);
+        drop(state.reader_closer);
+        assert!(reader_finished(&mut reader, Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn dropping_receiver_stops_paused_reader() {
+        let _exclusive = EXCLUSIVE.lock().await;
+        let config = radiator_config("");
+        let (mut state, mut reader) = start_reader(&config);
+        let mut radiator = connect(&mut state).await;
+        fill_queue(&mut state, &mut radiator, &config).await;
+
+        // the reader is still connected, but nobody takes its responses anymore
+        drop(state.message_receiver);
+        assert!(reader_finished(&mut reader, Duration::from_secs(5)).await);
+    }
+
+    #[tokio::test]
+    async fn command_with_nul_is_refused() {
+        let _exclusive = EXCLUSIVE.lock().await;
+        let config = radiator_config("");
+        let (mut state, _reader) = start_reader(&config);
+        let mut radiator = connect(&mut state).await;
+
+        let writer = state.socket_writer
+            .as_mut().expect("not connected");
+        let error = write_command(writer, b"STATS Handler\0.0", &config).await
+            .expect_err("command with NUL byte was sent");
+        assert!(matches!(&error, Error::InvalidCommand { command } if command == b"STATS Handler\0.0"), "unexpected error {:?}", error);
+        assert_eq!(error.to_string(), "command \"STATS Handler\\x00.0\" contains a NUL byte or line break");
+        assert!(!command_outstanding());
+
+        // nothing has been sent
+        state.socket_writer = None;
+        let mut received = Vec::new();
+        radiator.read_to_end(&mut received).await
+            .expect("failed to read from the connection");
+        assert_eq!(received, b"");
+    }
+
+    #[tokio::test]
+    async fn response_starting_with_log_prefix() {
+        let _exclusive = EXCLUSIVE.lock().await;
+        let config = radiator_config("");
+        let (mut state, _reader) = start_reader(&config);
+        let mut radiator = connect(&mut state).await;
+
+        // the log message is discarded; the response starts with the echo of the command
+        exchange(&mut state, &mut radiator, &config, "LOG LEVEL", "LOG 2026-10-16 12:00:00 level queried\0LOG LEVEL\n4").await;
+        let response = state.message_receiver.recv().await
+            .expect("reader is gone");
+        assert_eq!(response, b"LOG LEVEL\n4");
+        assert!(state.message_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn retries_exhausted() {
+        let (result, attempts) = communicate_with_dying_reader(3, u32::MAX).await;
+        assert_eq!(attempts, 4);
+        let error = result.expect_err("communication succeeded");
+        assert!(matches!(error, Error::RetriesExhausted { attempts: 4 }), "unexpected error {:?}", error);
+        assert_eq!(FailureCategory::from_radiator_error(&error), FailureCategory::ConnectionUnstable);
+    }
+
+    #[tokio::test]
+    async fn reader_gone_without_retries() {
+        let (result, attempts) = communicate_with_dying_reader(0, u32::MAX).await;
+        assert_eq!(attempts, 1);
+        let error = result.expect_err("communication succeeded");
+        assert!(matches!(error, Error::ReaderGone), "unexpected error {:?}", error);
+        assert_eq!(FailureCategory::from_radiator_error(&error), FailureCategory::RadiatorUnreachable);
+    }
+
+    #[tokio::test]
+    async fn retry_succeeds() {
+        let (result, attempts) = communicate_with_dying_reader(3, 2).await;
+        assert_eq!(attempts, 3);
+        assert_eq!(result.expect("communication failed"), b"STATS .\nAccess requests:5");
+    }
 }
@@ -0,0 +1,105 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use http_body_util::Full;
+use hyper::{Method, Request, Response};
+use hyper::body::{Bytes, Incoming};
+use tracing::{error, info, warn};
+
+use crate::config::{CONFIG, Config};
+use crate::debug::{is_authorized, plain_response};
+use crate::identifier_cache::IDENTIFIER_CACHE;
+use crate::stats_cache::STATS_CACHE;
+
+
+/// The configuration file the exporter was started with, unless it was read from standard input.
+pub(crate) static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+
+/// Reads and checks the configuration file again, returning the new configuration or a description
+/// of the problem.
+fn load(config: &Config) -> Result<Config, String> {
+    let config_path = CONFIG_PATH.get()
+        .ok_or_else(|| "the configuration was read from standard input and cannot be reloaded".to_owned())?;
+    let config_string = std::fs::read_to_string(config_path)
+        .map_err(|e| format!("failed to read config file {}: {}", config_path.display(), e))?;
+    let new_config = crate::config::parse(&config_string)
+        .map_err(|e| format!("error in configuration: {}", e))?;
+
+    // rather than having them silently ignored
+    let changed = config.startup_settings_changed(&new_config);
+    if !changed.is_empty() {
+        return Err(format!("changing {} requires restarting the exporter", changed.join(", ")));
+    }
+    Ok(new_config)
+}
+
+
+/// Handles `POST /-/reload`, replacing the configuration with the one currently in the
+/// configuration file if it is valid.
+pub(crate) fn handle_reload(
+    request: &Request<Incoming>,
+    remote_addr: SocketAddr,
+    client_subject: Option<&str>,
+    config: &Config,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !config.www.enable_reload {
+        return plain_response(404, "not found".to_owned());
+    }
+    if request.method() != Method::POST {
+        let response_res = Response::builder()
+            .status(405)
+            .header("Content-Type", "text/plain; charset=utf-8")
+            .header("Allow", "POST")
+            .body(Full::new(Bytes::from("HTTP method must be POST")));
+        return match response_res {
+            Ok(r) => Ok(r),
+            Err(e) => {
+                error!("failed to construct 405 response: {}", e);
+                plain_response(500, "internal server error".to_owned())
+            },
+        };
+    }
+    if !is_authorized(remote_addr, client_subject) {
+        return plain_response(403, "reloading requires a TLS client certificate or a local connection".to_owned());
+    }
+
+    let new_config = match load(config) {
+        Ok(nc) => nc,
+        Err(message) => {
+            warn!(event = "reload_failed", "not reloading configuration: {}", message);
+            return plain_response(400, message);
+        },
+    };
+    if new_config == *config {
+        info!(event = "reload_unchanged", "configuration reloaded; nothing has changed");
+        return plain_response(200, "configuration unchanged\n".to_owned());
+    }
+
+    let per_object_metric_count: usize = new_config.per_object_metrics.iter()
+        .map(|pom| pom.metrics.len())
+        .sum();
+    let summary = format!(
+        "configuration reloaded: {} metrics, {} per-object metrics, {} info metrics\n",
+        new_config.metrics.len(), per_object_metric_count, new_config.info_metrics.len(),
+    );
+    info!(
+        event = "reloaded",
+        metrics = new_config.metrics.len(),
+        per_object_metrics = per_object_metric_count,
+        info_metrics = new_config.info_metrics.len(),
+        "configuration reloaded",
+    );
+    CONFIG.replace(new_config);
+
+    // objects named by object_keys are known by the position of their key, which may have changed
+    IDENTIFIER_CACHE
+        .lock().expect("IDENTIFIER_CACHE poisoned")
+        .clear();
+    STATS_CACHE
+        .lock().expect("STATS_CACHE poisoned")
+        .clear();
+    plain_response(200, summary)
+}
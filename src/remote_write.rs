@@ -0,0 +1,152 @@
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::Method;
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, warn};
+
+use crate::config::{CONFIG, RemoteWriteConfig};
+use crate::openmetrics::FlatSample;
+use crate::push::{PushCredentials, PushTarget};
+use crate::radiator::RadiatorTransport;
+use crate::self_metrics::{REMOTE_WRITE_FAILURES, REMOTE_WRITE_PUSHES};
+
+
+// the version of the remote-write protocol spoken by the exporter
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+
+// protocol buffer wire types
+const WIRE_TYPE_VARINT: u64 = 0;
+const WIRE_TYPE_FIXED64: u64 = 1;
+const WIRE_TYPE_LENGTH_DELIMITED: u64 = 2;
+
+
+/// Prepares pushing to the remote-write endpoint in the given (checked) configuration.
+pub(crate) fn make_target(config: &RemoteWriteConfig) -> Result<PushTarget, crate::tls::Error> {
+    let uri = crate::push::check_url(&config.url)
+        .expect("remote_write.url not checked?!");
+    let credentials = PushCredentials {
+        username: config.username.as_deref(),
+        password: config.password.as_deref(),
+        bearer_token: config.bearer_token.as_deref(),
+    };
+    PushTarget::new(uri, config.ca_certificate.as_deref(), credentials)
+}
+
+
+fn put_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(((value & 0x7F) as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn put_tag(buffer: &mut Vec<u8>, field_number: u64, wire_type: u64) {
+    put_varint(buffer, (field_number << 3) | wire_type);
+}
+
+fn put_length_delimited(buffer: &mut Vec<u8>, field_number: u64, bytes: &[u8]) {
+    put_tag(buffer, field_number, WIRE_TYPE_LENGTH_DELIMITED);
+    put_varint(buffer, bytes.len().try_into().unwrap_or(u64::MAX));
+    buffer.extend_from_slice(bytes);
+}
+
+
+/// Encodes the given samples as a remote-write `WriteRequest` protocol buffer message, with one time
+/// series per sample.
+///
+/// Remote-write requires a timestamp for each sample; samples without one are stamped with
+/// `default_timestamp`.
+fn encode_write_request(samples: &[FlatSample], default_timestamp: SystemTime) -> Vec<u8> {
+    // message WriteRequest { repeated TimeSeries timeseries = 1; }
+    // message TimeSeries { repeated Label labels = 1; repeated Sample samples = 2; }
+    // message Label { string name = 1; string value = 2; }
+    // message Sample { double value = 1; int64 timestamp = 2; }
+    let mut request = Vec::new();
+    let mut series = Vec::new();
+    let mut field = Vec::new();
+    for sample in samples {
+        series.clear();
+        for (name, value) in &sample.labels {
+            field.clear();
+            put_length_delimited(&mut field, 1, name.as_bytes());
+            put_length_delimited(&mut field, 2, value.as_bytes());
+            put_length_delimited(&mut series, 1, &field);
+        }
+
+        let timestamp_ms: i64 = sample.timestamp
+            .unwrap_or(default_timestamp)
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_millis().try_into().unwrap_or(i64::MAX))
+            .unwrap_or(0);
+        field.clear();
+        put_tag(&mut field, 1, WIRE_TYPE_FIXED64);
+        field.extend_from_slice(&sample.value.to_le_bytes());
+        put_tag(&mut field, 2, WIRE_TYPE_VARINT);
+        put_varint(&mut field, timestamp_ms as u64);
+        put_length_delimited(&mut series, 2, &field);
+
+        put_length_delimited(&mut request, 1, &series);
+    }
+    request
+}
+
+
+/// Collects the metrics at the configured interval and pushes them to the remote-write endpoint.
+///
+/// Pushes that fail are not retried; the next push delivers current values anyway.
+pub(crate) async fn run_pushes(target: PushTarget, transport: &dyn RadiatorTransport) {
+    let config = CONFIG
+        .get().expect("CONFIG not set?!");
+    let remote_write = config.remote_write.as_ref()
+        .expect("remote_write not configured?!");
+    let timeout = Duration::from_millis(remote_write.timeout_ms);
+    let mut ticker = tokio::time::interval(Duration::from_millis(remote_write.interval_ms));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+
+        // the metrics may have been reloaded in the meantime
+        let config = CONFIG
+            .get().expect("CONFIG not set?!");
+        let (metric_database, _scrape_info) = match crate::collect_metrics(&config, transport).await {
+            Ok(collected) => collected,
+            Err((_status, _category, message)) => {
+                warn!(event = "remote_write_skipped", "not pushing metrics: {}", message);
+                continue;
+            },
+        };
+        let samples = metric_database.flat_samples(&config.www.counter_suffix);
+        let encoded = encode_write_request(&samples, SystemTime::now());
+        let compressed = match snap::raw::Encoder::new().compress_vec(&encoded) {
+            Ok(c) => c,
+            Err(e) => {
+                warn!(event = "remote_write_failed", "failed to compress metrics: {}", e);
+                REMOTE_WRITE_FAILURES.fetch_add(1, Ordering::Relaxed);
+                continue;
+            },
+        };
+        let headers = [
+            ("Content-Encoding", "snappy"),
+            ("Content-Type", "application/x-protobuf"),
+            ("X-Prometheus-Remote-Write-Version", REMOTE_WRITE_VERSION),
+        ];
+        let push = target.send(Method::POST, &headers, compressed);
+
+        match tokio::time::timeout(timeout, push).await {
+            Ok(Ok(())) => {
+                debug!("pushed {} samples to {}", samples.len(), target.uri());
+                REMOTE_WRITE_PUSHES.fetch_add(1, Ordering::Relaxed);
+            },
+            Ok(Err(e)) => {
+                warn!(event = "remote_write_failed", "failed to push metrics to {}: {}", target.uri(), e);
+                REMOTE_WRITE_FAILURES.fetch_add(1, Ordering::Relaxed);
+            },
+            Err(_) => {
+                warn!(event = "remote_write_failed", "pushing metrics to {} timed out", target.uri());
+                REMOTE_WRITE_FAILURES.fetch_add(1, Ordering::Relaxed);
+            },
+        }
+    }
+}
@@ -0,0 +1,222 @@
+// Replaying canned responses of Radiator's management interface, so that the whole path from the
+// HTTP request to the metrics output can be tested without Radiator.
+
+use std::collections::{BTreeMap, HashSet};
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use tracing::debug;
+
+use crate::client::NO_SUCH_OBJECT_RESPONSE;
+use crate::radiator::{Error, RadiatorTransport};
+
+
+/// Canned responses of Radiator's management interface, as stored in a responses file.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+pub(crate) struct CannedResponses {
+    /// The response to each command, keyed by the command as issued by the exporter (e.g.
+    /// `STATS Handler.0`, without `radiator.command_template` applied).
+    #[serde(default)]
+    pub responses: BTreeMap<String, String>,
+}
+
+
+/// A transport that answers commands from memory instead of asking Radiator.
+///
+/// Commands without a canned response are answered with `NOSUCHOBJECT`, just as Radiator answers
+/// commands about objects that do not exist; this ends the walks over per-object statistics.
+#[derive(Debug)]
+pub(crate) struct ReplayTransport {
+    responses: BTreeMap<Vec<u8>, Vec<u8>>,
+
+    /// The commands received so far, in order.
+    commands: Mutex<Vec<String>>,
+}
+impl ReplayTransport {
+    pub fn new(canned: CannedResponses) -> Self {
+        let responses = canned.responses.into_iter()
+            .map(|(command, response)| (command.into_bytes(), response.into_bytes()))
+            .collect();
+        Self {
+            responses,
+            commands: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Creates a transport answering each of the given commands with the given response.
+    pub fn from_pairs<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(pairs: I) -> Self {
+        let responses = pairs.into_iter()
+            .map(|(command, response)| (command.to_owned(), response.to_owned()))
+            .collect();
+        Self::new(CannedResponses { responses })
+    }
+
+    /// Returns the commands received so far, in order.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands
+            .lock().expect("commands poisoned")
+            .clone()
+    }
+}
+#[async_trait]
+impl RadiatorTransport for ReplayTransport {
+    async fn communicate(&self, command: &[u8]) -> Result<Vec<u8>, Error> {
+        self.commands
+            .lock().expect("commands poisoned")
+            .push(String::from_utf8_lossy(command).into_owned());
+        match self.responses.get(command) {
+            Some(response) => Ok(response.clone()),
+            None => {
+                debug!("no canned response for command \"{}\"; answering NOSUCHOBJECT", command.escape_ascii());
+                Ok(NO_SUCH_OBJECT_RESPONSE.to_vec())
+            },
+        }
+    }
+}
+
+
+/// Removes the metric families with the given names from OpenMetrics output.
+fn remove_families(output: &str, names: &HashSet<String>) -> String {
+    let mut kept = String::with_capacity(output.len());
+    let mut skipping = false;
+    for line in output.split_inclusive('\n') {
+        if let Some(type_line) = line.strip_prefix("# TYPE ") {
+            let name = type_line.split(' ').next().unwrap_or("");
+            skipping = names.contains(name);
+        } else if line.starts_with("# EOF") {
+            skipping = false;
+        }
+        if !skipping {
+            kept.push_str(line);
+        }
+    }
+    kept
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashSet};
+    use std::net::SocketAddr;
+    use std::path::Path;
+
+    use hyper_util::rt::TokioIo;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::config::{CONFIG, HttpVersion};
+    use crate::openmetrics::{ExpositionFormat, MetricDatabase};
+    use crate::scrape::StatisticCoverage;
+    use crate::self_metrics::ScrapeInfo;
+    use super::{CannedResponses, remove_families, ReplayTransport};
+
+
+    /// Returns the names of the metric families the exporter adds about itself, whose values
+    /// depend on what else the test process has done.
+    fn self_metric_names(config: &crate::config::Config) -> HashSet<String> {
+        let scrape_info = ScrapeInfo {
+            truncated: false,
+            snapshot_age: None,
+            group_snapshot_ages: BTreeMap::new(),
+            radiator_up: true,
+            statistic_coverage: Some(StatisticCoverage::default()),
+        };
+        let mut database = MetricDatabase::with_label_order(config.www.label_order);
+        crate::self_metrics::collect(&mut database, config, Some(&scrape_info));
+        let mut output = String::new();
+        database.write(&mut output, ExpositionFormat::OpenMetrics, &config.www.counter_suffix, config.www.help_newlines)
+            .expect("failed to write self-metrics");
+        output.lines()
+            .filter_map(|line| line.strip_prefix("# TYPE "))
+            .filter_map(|type_line| type_line.split(' ').next())
+            .map(|name| name.to_owned())
+            .collect()
+    }
+
+    /// Requests `/metrics` over an in-memory HTTP connection and returns the response body.
+    async fn request_metrics(transport: &'static ReplayTransport) -> String {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let remote_addr: SocketAddr = "127.0.0.1:40000".parse().unwrap();
+        let serve = crate::serve_connection(TokioIo::new(server), remote_addr, None, None, HttpVersion::Http1, transport);
+        let fetch = async move {
+            let mut client = client;
+            client.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n").await
+                .expect("failed to send request");
+            let mut response = Vec::new();
+            client.read_to_end(&mut response).await
+                .expect("failed to read response");
+            response
+        };
+        let ((), response) = tokio::join!(serve, fetch);
+
+        let response = String::from_utf8(response).expect("response is not UTF-8");
+        let (head, body) = response.split_once("\r\n\r\n").expect("response has no body");
+        assert!(head.starts_with("HTTP/1.1 200 "), "unexpected response: {}", head);
+        body.to_owned()
+    }
+
+    /// Runs each case in `golden/` through the request handler and compares the metrics output
+    /// (without the exporter's own metrics) with the expected output.
+    ///
+    /// With `UPDATE_GOLDEN=1`, the expected output is overwritten with the actual output instead.
+    #[tokio::test]
+    async fn golden_cases() {
+        let update = std::env::var_os("UPDATE_GOLDEN").is_some_and(|v| v == "1");
+        let golden_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden");
+        let mut case_dirs: Vec<_> = std::fs::read_dir(&golden_dir)
+            .expect("failed to list golden cases")
+            .map(|entry| entry.expect("failed to list golden cases").path())
+            .filter(|path| path.is_dir())
+            .collect();
+        case_dirs.sort();
+        assert!(!case_dirs.is_empty(), "no golden cases found");
+
+        let mut failed_cases = Vec::new();
+        for case_dir in &case_dirs {
+            let case_name = case_dir.file_name().unwrap().to_string_lossy().into_owned();
+            let config_string = std::fs::read_to_string(case_dir.join("config.toml"))
+                .expect("failed to read config.toml");
+            let config = crate::config::parse(&config_string)
+                .unwrap_or_else(|e| panic!("{}: error in configuration: {}", case_name, e));
+            let responses_string = std::fs::read_to_string(case_dir.join("responses.toml"))
+                .expect("failed to read responses.toml");
+            let canned: CannedResponses = toml::from_str(&responses_string)
+                .unwrap_or_else(|e| panic!("{}: failed to parse responses.toml: {}", case_name, e));
+            let self_metrics = self_metric_names(&config);
+
+            // the request handler serves whatever configuration is current
+            CONFIG.replace(config);
+            let transport: &'static ReplayTransport = Box::leak(Box::new(ReplayTransport::new(canned)));
+            let actual = remove_families(&request_metrics(transport).await, &self_metrics);
+
+            let expected_path = case_dir.join("expected.txt");
+            if update {
+                std::fs::write(&expected_path, &actual).expect("failed to write expected.txt");
+                continue;
+            }
+            let expected = std::fs::read_to_string(&expected_path)
+                .expect("failed to read expected.txt");
+            if actual != expected {
+                eprintln!("{}: output differs\n--- expected\n{}--- actual\n{}", case_name, expected, actual);
+                failed_cases.push(case_name);
+            }
+        }
+        assert!(failed_cases.is_empty(), "golden cases with differing output: {:?}", failed_cases);
+    }
+
+    #[test]
+    fn remove_families_keeps_others() {
+        let output = "\
+# TYPE a gauge
+a 1
+# TYPE radiator_up gauge
+# HELP radiator_up Whether Radiator could be queried (1) or not (0).
+radiator_up 1
+# TYPE b counter
+b_total 2
+# EOF
+";
+        let names = HashSet::from(["radiator_up".to_owned()]);
+        assert_eq!(remove_families(output, &names), "# TYPE a gauge\na 1\n# TYPE b counter\nb_total 2\n# EOF\n");
+    }
+}
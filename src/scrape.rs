@@ -0,0 +1,1478 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+use tokio::time::MissedTickBehavior;
+use tracing::{debug, error, info, warn};
+
+use crate::client::{self, RadiatorClient};
+use crate::config::{CONFIG, Config, ConnectionMode, CounterValues, InfoMetricConfig, MetricConfig, NegativeValueAction, ObjectSelection, PerObjectMetricConfig, SampleConfig};
+use crate::decode::{extract_identifiers, field_types, Statistics};
+use crate::failure::FailureCategory;
+use crate::identifier_cache::IDENTIFIER_CACHE;
+use crate::openmetrics::{Histogram, Metric, MetricDatabase, MetricKind, Number, stable_hash, truncate_label_value, Value};
+use crate::radiator::{RadiatorTransport, ScrapeConnection};
+use crate::self_metrics::{INVALID_COUNTER_VALUES, NEGATIVE_VALUES};
+use crate::stats_cache::STATS_CACHE;
+
+
+/// The most recent result of the background scrape of each scrape group (`None` being the default
+/// group), if background scraping is enabled.
+pub(crate) static SNAPSHOTS: RwLock<BTreeMap<Option<String>, Snapshot>> = RwLock::new(BTreeMap::new());
+static LAST_SUCCESSFUL_SCRAPE: Mutex<Option<Instant>> = Mutex::new(None);
+static WARMING_UP: AtomicBool = AtomicBool::new(false);
+
+
+const MAX_PLAUSIBLE_TIMESTAMP: f64 = 10_000_000_000.0;
+
+
+#[derive(Clone, Debug)]
+pub(crate) struct DescribedObject {
+    /// The values of the identifying fields, keyed by field name.
+    pub identifiers: BTreeMap<String, String>,
+    pub field_types: HashMap<String, String>,
+}
+
+
+#[derive(Clone, Debug)]
+struct PerObjectStats {
+    pub identifiers: BTreeMap<String, String>,
+    pub stats: HashMap<String, Number>,
+
+    /// The start of an earlier scrape if the statistics were taken from the stats cache.
+    pub queried_at: Option<SystemTime>,
+}
+
+
+/// The statistics of the objects of one kind.
+#[derive(Clone, Debug)]
+struct ObjectKindStats {
+    /// The number of objects discovered, including those left out due to `max_objects`.
+    discovered: usize,
+
+    index_to_statistics: HashMap<usize, PerObjectStats>,
+}
+
+
+/// Limits the number of Radiator commands issued during a single scrape.
+#[derive(Clone, Debug)]
+struct CommandBudget {
+    remaining: Option<usize>,
+    pub truncated: bool,
+}
+impl CommandBudget {
+    pub fn new(max_commands: Option<usize>) -> Self {
+        Self {
+            remaining: max_commands,
+            truncated: false,
+        }
+    }
+
+    /// Attempts to take one command from the budget. If the budget is exhausted, marks the scrape as
+    /// truncated and returns `false`.
+    pub fn take(&mut self, object_kind: &str) -> bool {
+        self.take_several(object_kind, 1)
+    }
+
+    /// Attempts to take the given number of commands from the budget at once. If fewer are left,
+    /// takes none of them, marks the scrape as truncated and returns `false`.
+    pub fn take_several(&mut self, object_kind: &str, count: usize) -> bool {
+        match self.remaining.as_mut() {
+            None => true,
+            Some(remaining) if *remaining < count => {
+                if !self.truncated {
+                    warn!("maximum number of Radiator commands per scrape reached while querying {:?}; truncating scrape", object_kind);
+                    self.truncated = true;
+                }
+                false
+            },
+            Some(remaining) => {
+                *remaining -= count;
+                true
+            },
+        }
+    }
+}
+
+
+/// The result of a scrape of Radiator's statistics.
+#[derive(Clone, Debug)]
+pub(crate) struct Scrape {
+    pub database: MetricDatabase,
+    pub truncated: bool,
+    pub statistic_coverage: StatisticCoverage,
+}
+
+/// How many of the statistics used by the configured metrics Radiator reported during a scrape.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct StatisticCoverage {
+    pub configured: usize,
+    pub collected: usize,
+}
+impl StatisticCoverage {
+    /// Adds the coverage of another scrape (of a different scrape group) to this one.
+    pub fn add(&mut self, other: StatisticCoverage) {
+        self.configured += other.configured;
+        self.collected += other.collected;
+    }
+}
+
+/// A scrape that has been performed in the background.
+#[derive(Clone, Debug)]
+pub(crate) struct Snapshot {
+    pub scrape: Scrape,
+    pub taken_at: Instant,
+}
+
+
+pub(crate) fn record_successful_scrape() {
+    *LAST_SUCCESSFUL_SCRAPE.lock().expect("LAST_SUCCESSFUL_SCRAPE poisoned") = Some(Instant::now());
+}
+
+/// Returns the time since the last successful scrape, i.e. one that was neither truncated nor
+/// skipped any object kind because querying it failed (or since startup, if no scrape has succeeded
+/// yet).
+pub(crate) fn last_successful_scrape_age() -> Duration {
+    LAST_SUCCESSFUL_SCRAPE
+        .lock().expect("LAST_SUCCESSFUL_SCRAPE poisoned")
+        .map(|i| i.elapsed())
+        .unwrap_or(Duration::MAX)
+}
+
+
+/// Checks a statistic's value against the constraints of the metric it is exported as.
+///
+/// Returns the value to export or `None` (after logging the reason) if the value is to be skipped.
+fn check_value(metric_config: &MetricConfig, counter_values: CounterValues, statistic: &str, value: Number) -> Option<Number> {
+    if metric_config.is_timestamp {
+        // Unix time in seconds; anything negative or beyond the year 2286 is probably in a
+        // different unit (e.g. milliseconds) or garbage
+        let value_f64 = value.as_f64();
+        if !(0.0..MAX_PLAUSIBLE_TIMESTAMP).contains(&value_f64) {
+            warn!("value {} of statistic {:?} for timestamp metric {:?} is not a plausible Unix timestamp; skipping", value, statistic, metric_config.metric);
+            return None;
+        }
+    }
+
+    if metric_config.non_negative && value.as_f64() < 0.0 {
+        NEGATIVE_VALUES.fetch_add(1, Ordering::Relaxed);
+        match metric_config.on_negative {
+            NegativeValueAction::Skip => {
+                warn!("value {} of statistic {:?} for non-negative metric {:?} is negative; skipping", value, statistic, metric_config.metric);
+                return None;
+            },
+            NegativeValueAction::Clamp => {
+                warn!("value {} of statistic {:?} for non-negative metric {:?} is negative; outputting 0", value, statistic, metric_config.metric);
+                return Some(match value {
+                    Number::Integer(_) => Number::Integer(0),
+                    Number::Float(_) => Number::Float(0.0),
+                });
+            },
+        }
+    }
+
+    if metric_config.kind == MetricKind::Counter && counter_values != CounterValues::AsIs {
+        return check_counter_value(metric_config, counter_values, statistic, value);
+    }
+
+    Some(value)
+}
+
+
+/// Ensures that a counter's value is a non-negative whole number (`www.counter_values`).
+fn check_counter_value(metric_config: &MetricConfig, counter_values: CounterValues, statistic: &str, value: Number) -> Option<Number> {
+    let value_f64 = match value {
+        Number::Integer(i) if i >= 0 => return Some(value),
+        Number::Integer(_) => value.as_f64(),
+        Number::Float(f) if !f.is_finite() => {
+            INVALID_COUNTER_VALUES.fetch_add(1, Ordering::Relaxed);
+            warn!("value {} of statistic {:?} for counter {:?} is not a finite number; skipping", value, statistic, metric_config.metric);
+            return None;
+        },
+        Number::Float(f) if f >= 0.0 && f.fract() == 0.0 => {
+            // a whole number that merely looks fractional (e.g. "3.0")
+            return Some(float_to_whole_number(f));
+        },
+        Number::Float(f) => f,
+    };
+
+    INVALID_COUNTER_VALUES.fetch_add(1, Ordering::Relaxed);
+    match counter_values {
+        CounterValues::AsIs => Some(value),
+        CounterValues::Reject => {
+            warn!("value {} of statistic {:?} for counter {:?} is not a non-negative whole number; skipping", value, statistic, metric_config.metric);
+            None
+        },
+        CounterValues::Coerce => {
+            let coerced = float_to_whole_number(value_f64.max(0.0).floor());
+            warn!("value {} of statistic {:?} for counter {:?} is not a non-negative whole number; outputting {}", value, statistic, metric_config.metric, coerced);
+            Some(coerced)
+        },
+    }
+}
+
+
+/// Converts a float without fractional part to an integer if it is within range.
+fn float_to_whole_number(value: f64) -> Number {
+    // i64::MAX as f64 rounds up to 2**63, which is out of range
+    if value >= i64::MIN as f64 && value < i64::MAX as f64 {
+        Number::Integer(value as i64)
+    } else {
+        Number::Float(value)
+    }
+}
+
+
+/// Sets the unit and help text of a metric unless they have already been set.
+///
+/// If several entries of the configuration output the same metric, the first one to specify a unit
+/// or help text determines it; differing values from later entries are ignored with a warning.
+fn set_metadata(metric: &mut Metric, unit: Option<String>, help: Option<String>) {
+    match (metric.unit(), unit.as_deref()) {
+        (None, _) => metric.set_unit(unit),
+        (Some(existing), Some(new)) if existing != new => {
+            warn!("conflicting units configured for metric {:?}; keeping {:?}", metric.name(), existing);
+        },
+        _ => {},
+    }
+
+    match (metric.help(), help.as_deref()) {
+        (None, _) => metric.set_help(help),
+        (Some(existing), Some(new)) if existing != new => {
+            warn!("conflicting help texts configured for metric {:?}; keeping {:?}", metric.name(), existing);
+        },
+        _ => {},
+    }
+}
+
+
+/// Adds a metric with the given samples to the database.
+///
+/// Labels are registered only once the samples are known, and metrics without any samples (e.g.
+/// because no object offers the statistic) are left out entirely instead of being output as a
+/// family header without samples.
+///
+/// `identifier_labels` are the labels identifying the object (for per-object metrics); they are
+/// considered to be declared before the labels of the samples.
+///
+/// If there are more than `max_series` samples, only those with the lowest label values are kept
+/// (so that the same ones are kept in every scrape) and `true` is returned.
+fn insert_metric(
+    database: &mut MetricDatabase,
+    metric_config: &MetricConfig,
+    identifier_labels: &[&str],
+    unit: Option<String>,
+    help: Option<String>,
+    mut samples: Vec<(BTreeMap<String, String>, Value, Option<SystemTime>)>,
+    max_series: Option<usize>,
+) -> bool {
+    if samples.is_empty() {
+        return false;
+    }
+
+    let mut capped = false;
+    if let Some(max_series) = max_series {
+        if samples.len() > max_series {
+            warn!(
+                event = "cardinality_capped",
+                "metric {:?} has {} series, more than the maximum of {}; leaving out the rest",
+                metric_config.metric, samples.len(), max_series,
+            );
+            samples.sort_unstable_by(|(labels_a, _, _), (labels_b, _, _)| labels_a.cmp(labels_b));
+            samples.truncate(max_series);
+            capped = true;
+        }
+    }
+
+    let metric = database.get_or_insert(&metric_config.metric, metric_config.kind);
+    set_metadata(metric, unit, help);
+    metric.set_precision(metric_config.precision);
+    metric.set_sample_order(metric_config.sort_samples.clone());
+    let sample_labels: BTreeSet<&str> = samples.iter()
+        .flat_map(|(labels, _value, _timestamp)| labels.keys())
+        .map(|label_name| label_name.as_str())
+        .collect();
+    // in declaration order, in case the labels are output in that order
+    let declared_labels = identifier_labels.iter()
+        .copied()
+        .chain(metric_config.samples.iter().flat_map(|sample| sample.labels.declared_keys()))
+        .chain(sample_labels.iter().copied());
+    for label_name in declared_labels {
+        if sample_labels.contains(label_name) && !metric.has_label(label_name) {
+            metric.add_label(label_name.to_owned());
+        }
+    }
+    for (labels, value, timestamp) in samples {
+        metric.add_value_at(&labels, value, timestamp);
+    }
+    capped
+}
+
+
+/// Adds an info metric whose labels are taken from the given string statistics.
+///
+/// Statistics that Radiator did not report yield empty label values; if it reported none of them,
+/// the metric is skipped.
+fn insert_info_metric(
+    database: &mut MetricDatabase,
+    info_metric_config: &InfoMetricConfig,
+    string_statistics: &HashMap<String, String>,
+) {
+    let mut labels = BTreeMap::new();
+    let mut any_reported = false;
+    for (label, statistic) in info_metric_config.labels.iter() {
+        let value = match string_statistics.get(statistic) {
+            Some(v) => {
+                any_reported = true;
+                v.clone()
+            },
+            None => String::new(),
+        };
+        labels.insert(label.clone(), value);
+    }
+    if !any_reported {
+        debug!("none of the statistics of info metric {:?} were reported; skipping it", info_metric_config.metric);
+        return;
+    }
+
+    let metric = database.get_or_insert(&info_metric_config.metric, MetricKind::Info);
+    set_metadata(metric, None, info_metric_config.help.clone());
+    for label_name in info_metric_config.labels.declared_keys() {
+        if !metric.has_label(label_name) {
+            metric.add_label(label_name.to_owned());
+        }
+    }
+    metric.add_value(&labels, Value::Number(Number::Integer(1)));
+}
+
+
+/// Returns the value of the statistic of the given sample, falling back to the sample's
+/// `default_value` if Radiator did not report the statistic.
+fn sample_value(sample: &SampleConfig, statistics: &HashMap<String, Number>) -> Option<Number> {
+    statistics.get(&sample.statistic)
+        .copied()
+        .or(sample.default_value.map(Number::Integer))
+}
+
+
+/// Collects the samples of the given metric from the given statistics.
+///
+/// `extra_labels` (e.g. the object identifier) are added to the labels configured for each sample;
+/// `timestamp` is attached to each sample.
+fn collect_samples(
+    metric_config: &MetricConfig,
+    counter_values: CounterValues,
+    statistics: &HashMap<String, Number>,
+    extra_labels: &BTreeMap<String, String>,
+    timestamp: Option<SystemTime>,
+    samples: &mut Vec<(BTreeMap<String, String>, Value, Option<SystemTime>)>,
+) {
+    if metric_config.kind == MetricKind::GaugeHistogram {
+        // all samples are buckets of the same series (ensured by config::check)
+        let Some(first_sample) = metric_config.samples.first() else { return };
+        let mut all_labels = (*first_sample.labels).clone();
+        all_labels.extend(extra_labels.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if metric_config.when.as_ref().is_some_and(|when| !when.is_match(&all_labels)) {
+            return;
+        }
+
+        // Radiator counts each bucket separately; OpenMetrics buckets are cumulative
+        let mut buckets = Vec::with_capacity(metric_config.samples.len());
+        let mut cumulative = Number::Integer(0);
+        for sample in &metric_config.samples {
+            let Some(value) = sample_value(sample, statistics) else {
+                // an incomplete histogram would be misleading
+                debug!("statistic {:?} for bucket of {:?} is missing; skipping histogram", sample.statistic, metric_config.metric);
+                return;
+            };
+            cumulative = cumulative + value;
+            let upper_bound = sample.le.clone()
+                .expect("gauge histogram bucket without upper bound");
+            buckets.push((upper_bound, cumulative));
+        }
+        let sum = metric_config.sum_statistic.as_ref()
+            .and_then(|sum_statistic| statistics.get(sum_statistic))
+            .copied();
+        let histogram = Histogram {
+            buckets,
+            count: cumulative,
+            sum,
+        };
+        samples.push((all_labels, Value::Histogram(histogram), timestamp));
+        return;
+    }
+
+    for sample in &metric_config.samples {
+        let value = match sample_value(sample, statistics) {
+            Some(v) => v,
+            None => continue,
+        };
+        let mut all_labels = (*sample.labels).clone();
+        all_labels.extend(extra_labels.iter().map(|(k, v)| (k.clone(), v.clone())));
+        if metric_config.when.as_ref().is_some_and(|when| !when.is_match(&all_labels)) {
+            continue;
+        }
+        let Some(value) = check_value(metric_config, counter_values, &sample.statistic, value) else { continue };
+        samples.push((all_labels, Value::Number(value), timestamp));
+    }
+}
+
+
+/// Counts the distinct statistics used by the configured metrics and how many of them Radiator
+/// reported.
+///
+/// A per-object statistic counts as reported if at least one object of the metrics' kinds reported
+/// it; the statistics of object kinds that were not queried count as missing.
+fn statistic_coverage(
+    config: &Config,
+    statistics: &Statistics,
+    object_type_to_statistics: &HashMap<String, HashMap<usize, PerObjectStats>>,
+) -> StatisticCoverage {
+    let mut coverage = StatisticCoverage::default();
+
+    let top_level_statistics: BTreeSet<&str> = config.metrics.iter()
+        .flat_map(|metric_config| metric_config.statistics())
+        .chain(config.string_statistics())
+        .collect();
+    for statistic in top_level_statistics {
+        coverage.configured += 1;
+        if statistics.numbers.contains_key(statistic) || statistics.strings.contains_key(statistic) {
+            coverage.collected += 1;
+        }
+    }
+
+    for per_object_metrics in &config.per_object_metrics {
+        let object_statistics: BTreeSet<&str> = per_object_metrics.metrics.iter()
+            .flat_map(|metric_config| metric_config.statistics())
+            .collect();
+        for statistic in object_statistics {
+            coverage.configured += 1;
+            let reported = per_object_metrics.all_kinds()
+                .filter_map(|kind| object_type_to_statistics.get(kind))
+                .flat_map(|index_to_statistics| index_to_statistics.values())
+                .any(|per_object_statistics| per_object_statistics.stats.contains_key(statistic));
+            if reported {
+                coverage.collected += 1;
+            }
+        }
+    }
+
+    coverage
+}
+
+
+/// Restricts the discovered objects to at most `max_objects` according to the configured selection
+/// strategy.
+fn select_objects(index_to_object: &mut HashMap<usize, DescribedObject>, max_objects: usize, selection: ObjectSelection) {
+    if index_to_object.len() <= max_objects {
+        return;
+    }
+
+    let mut indexes: Vec<usize> = index_to_object.keys().copied().collect();
+    match selection {
+        ObjectSelection::FirstN => indexes.sort_unstable(),
+        ObjectSelection::StableSample => {
+            // hash the identifiers rather than the index so that objects stay selected when other
+            // objects are added or removed; the index only breaks ties
+            indexes.sort_unstable_by_key(|index| {
+                let mut identifier_bytes = Vec::new();
+                for (field, value) in &index_to_object[index].identifiers {
+                    identifier_bytes.extend_from_slice(field.as_bytes());
+                    identifier_bytes.push(b'\0');
+                    identifier_bytes.extend_from_slice(value.as_bytes());
+                    identifier_bytes.push(b'\0');
+                }
+                (stable_hash(&identifier_bytes), *index)
+            });
+        },
+    }
+    for index in &indexes[max_objects..] {
+        index_to_object.remove(index);
+    }
+}
+
+
+/// Discovers the objects of the given kind and queries their statistics.
+///
+/// Objects that do not exist or may not be queried are skipped; any other error is returned and
+/// stops the querying of this kind. Returns `None` if Radiator does not recognize the object kind,
+/// i.e. rejects describing its first object with something other than `NOSUCHOBJECT`.
+async fn scrape_object_kind(
+    config: &Config,
+    client: &RadiatorClient<'_>,
+    budget: &mut CommandBudget,
+    per_object_metrics: &PerObjectMetricConfig,
+    object_kind: &str,
+    started_at: SystemTime,
+) -> Result<Option<ObjectKindStats>, client::Error> {
+    let identifier_labels = per_object_metrics.identifier_labels();
+    // query the identifiers
+    let mut index_to_object: HashMap<usize, DescribedObject> = HashMap::new();
+    // some servers leave gaps between the indexes of their objects
+    let mut consecutive_missing = 0;
+    // named objects are known by the position of their key
+    let key_count = per_object_metrics.object_keys.as_ref().map(|keys| keys.len());
+    for i in 0.. {
+        if key_count.is_some_and(|count| i >= count) {
+            break;
+        }
+        if config.radiator.identifier_cache_max.is_some() {
+            let cached_object = IDENTIFIER_CACHE
+                .lock().expect("IDENTIFIER_CACHE poisoned")
+                .get(object_kind, i);
+            if let Some(described_object) = cached_object {
+                index_to_object.insert(i, described_object);
+                consecutive_missing = 0;
+                continue;
+            }
+        }
+
+        if !budget.take(object_kind) {
+            break;
+        }
+        let object = per_object_metrics.object_name(object_kind, i);
+        let fields = match client.describe(&object).await {
+            Ok(f) => f,
+            Err(client::Error::NoSuchObject) if key_count.is_some() => {
+                // the other keys may exist nonetheless
+                debug!("Radiator object {} does not exist", object);
+                continue;
+            },
+            Err(client::Error::NoSuchObject) => {
+                consecutive_missing += 1;
+                if consecutive_missing > per_object_metrics.gap_tolerance {
+                    // that is all
+                    break;
+                }
+                continue;
+            },
+            Err(client::Error::PermissionDenied) => {
+                // we cannot enumerate the objects of this kind
+                warn!("Radiator denied permission to describe {}; skipping the remaining objects of this kind", object);
+                break;
+            },
+            Err(client::Error::Rejected(response)) if i == 0 => {
+                warn!("Radiator rejected describing {} with {:?}; it does not seem to support object kind {:?}", object, response, object_kind);
+                return Ok(None);
+            },
+            Err(e) => {
+                error!("failed to query Radiator info for {}: {}", object, e);
+                return Err(e);
+            },
+        };
+        consecutive_missing = 0;
+        let identifiers = extract_identifiers(&fields, identifier_labels.values().copied());
+        if identifiers.is_empty() {
+            warn!("Radiator object {} has none of the identifier fields {:?}; skipping", object, identifier_labels.values());
+            continue;
+        }
+        let described_object = DescribedObject {
+            identifiers,
+            field_types: field_types(&fields),
+        };
+        if let Some(identifier_cache_max) = config.radiator.identifier_cache_max {
+            IDENTIFIER_CACHE
+                .lock().expect("IDENTIFIER_CACHE poisoned")
+                .insert(object_kind, i, described_object.clone(), identifier_cache_max);
+        }
+        index_to_object.insert(i, described_object);
+    }
+    let discovered = index_to_object.len();
+    if budget.truncated {
+        // we cannot ask for any statistics anymore
+        return Ok(Some(ObjectKindStats {
+            discovered,
+            index_to_statistics: HashMap::new(),
+        }));
+    }
+    if let Some(max_objects) = per_object_metrics.max_objects {
+        select_objects(&mut index_to_object, max_objects, per_object_metrics.object_selection);
+    }
+
+    // pull statistics for each object
+    let mut index_to_statistics = HashMap::new();
+    for (&index, described_object) in &index_to_object {
+        if let Some(stats_every_scrapes) = per_object_metrics.stats_every_scrapes {
+            let cached_stats = STATS_CACHE
+                .lock().expect("STATS_CACHE poisoned")
+                .get(object_kind, index, &described_object.identifiers, stats_every_scrapes);
+            if let Some((stats, queried_at)) = cached_stats {
+                let per_object_stats = PerObjectStats {
+                    identifiers: described_object.identifiers.clone(),
+                    stats,
+                    queried_at: Some(queried_at),
+                };
+                index_to_statistics.insert(index, per_object_stats);
+                continue;
+            }
+        }
+
+        if !budget.take(object_kind) {
+            break;
+        }
+        let object = per_object_metrics.object_name(object_kind, index);
+        let mut object_field_types = described_object.field_types.clone();
+        let mut stats = match client.stats(&object, &object_field_types).await {
+            Ok(s) => s,
+            Err(client::Error::NoSuchObject) => {
+                // only possible if the object was taken from the identifier cache
+                debug!("cached Radiator object {} no longer exists", object);
+                IDENTIFIER_CACHE
+                    .lock().expect("IDENTIFIER_CACHE poisoned")
+                    .remove(object_kind, index);
+                STATS_CACHE
+                    .lock().expect("STATS_CACHE poisoned")
+                    .remove(object_kind, index);
+                continue;
+            },
+            Err(client::Error::PermissionDenied) => {
+                warn!("Radiator denied permission to query the statistics of {}; skipping it", object);
+                continue;
+            },
+            Err(e) => {
+                error!("failed to query Radiator stats for {}: {}", object, e);
+                return Err(e);
+            },
+        };
+
+        // Radiator occasionally returns no statistics at all for an existing object under load;
+        // make sure the object still exists and ask again
+        let mut skip_object = false;
+        let mut retries_left = config.radiator.empty_stats_retries;
+        while stats.is_empty() && retries_left > 0 {
+            retries_left -= 1;
+
+            // a retry costs two commands
+            if !budget.take_several(object_kind, 2) {
+                break;
+            }
+
+            match client.describe(&object).await {
+                Ok(fields) => {
+                    object_field_types = field_types(&fields);
+                },
+                Err(client::Error::NoSuchObject) => {
+                    debug!("Radiator object {} vanished while retrying empty stats", object);
+                    IDENTIFIER_CACHE
+                        .lock().expect("IDENTIFIER_CACHE poisoned")
+                        .remove(object_kind, index);
+                    STATS_CACHE
+                        .lock().expect("STATS_CACHE poisoned")
+                        .remove(object_kind, index);
+                    skip_object = true;
+                    break;
+                },
+                Err(client::Error::UndecodableResponse | client::Error::PermissionDenied) => {
+                    // keep the previous field types
+                },
+                Err(e) => {
+                    error!("failed to re-query Radiator info for {}: {}", object, e);
+                    return Err(e);
+                },
+            }
+
+            stats = match client.stats(&object, &object_field_types).await {
+                Ok(s) => s,
+                Err(client::Error::NoSuchObject) => {
+                    debug!("Radiator object {} vanished while retrying empty stats", object);
+                    IDENTIFIER_CACHE
+                        .lock().expect("IDENTIFIER_CACHE poisoned")
+                        .remove(object_kind, index);
+                    STATS_CACHE
+                        .lock().expect("STATS_CACHE poisoned")
+                        .remove(object_kind, index);
+                    skip_object = true;
+                    break;
+                },
+                Err(client::Error::PermissionDenied) => {
+                    warn!("Radiator denied permission to re-query the statistics of {}; skipping it", object);
+                    skip_object = true;
+                    break;
+                },
+                Err(e) => {
+                    error!("failed to re-query Radiator stats for {}: {}", object, e);
+                    return Err(e);
+                },
+            };
+            if !stats.is_empty() {
+                debug!("retrying empty stats for Radiator object {} succeeded", object);
+            }
+        }
+        if skip_object {
+            continue;
+        }
+
+        // an empty response is most likely a glitch; do not keep serving it
+        if per_object_metrics.stats_every_scrapes.is_some() && !stats.numbers.is_empty() {
+            STATS_CACHE
+                .lock().expect("STATS_CACHE poisoned")
+                .insert(object_kind, index, described_object.identifiers.clone(), stats.numbers.clone(), started_at);
+        }
+
+        let per_object_stats = PerObjectStats {
+            identifiers: described_object.identifiers.clone(),
+            stats: stats.numbers,
+            queried_at: None,
+        };
+        index_to_statistics.insert(index, per_object_stats);
+    }
+
+    Ok(Some(ObjectKindStats {
+        discovered,
+        index_to_statistics,
+    }))
+}
+
+
+/// Queries Radiator and collects the configured metrics.
+///
+/// Returns the category of the failure (after logging the reason) if the scrape failed.
+pub(crate) async fn scrape_radiator(config: &Config, transport: &dyn RadiatorTransport) -> Result<Scrape, FailureCategory> {
+    match config.radiator.connection_mode {
+        ConnectionMode::Persistent => {
+            // rather than having the first command fail if the connection was lost since the last
+            // scrape
+            if let Err(e) = crate::radiator::ensure_connected().await {
+                error!("failed to reconnect to Radiator: {}", e);
+                return Err(FailureCategory::from_radiator_error(&e));
+            }
+            scrape_connected(config, transport).await
+        },
+        ConnectionMode::PerScrape => {
+            let connection = match ScrapeConnection::open().await {
+                Ok(c) => c,
+                Err(e) => {
+                    error!("failed to connect to Radiator: {}", e);
+                    return Err(FailureCategory::from_radiator_error(&e));
+                },
+            };
+            let scrape = scrape_connected(config, transport).await;
+            connection.close().await;
+            scrape
+        },
+    }
+}
+
+
+/// Collects the configured metrics over the given transport, which must be ready to exchange
+/// commands with Radiator.
+pub(crate) async fn scrape_connected(config: &Config, transport: &dyn RadiatorTransport) -> Result<Scrape, FailureCategory> {
+    let started_at = SystemTime::now();
+    let mut metric_database = MetricDatabase::with_label_order(config.www.label_order);
+    let mut budget = CommandBudget::new(config.radiator.max_commands_per_scrape);
+
+    let client = RadiatorClient::new(config, transport);
+
+    STATS_CACHE
+        .lock().expect("STATS_CACHE poisoned")
+        .start_scrape(config.per_object_metrics.iter().flat_map(|pom| pom.all_kinds()));
+
+    let query_top_level = config.queries_top_level_statistics();
+    let statistics = if query_top_level {
+        // reserve a command for the top-level statistics
+        // (the budget is at least 1, so this always passes)
+        budget.take(".");
+
+        // find out the types of the top-level statistics (if the budget allows it)
+        let top_level_field_types = if budget.take(".") {
+            match client.describe(".").await {
+                Ok(fields) => field_types(&fields),
+                Err(client::Error::PermissionDenied) => {
+                    warn!("Radiator denied permission to describe the top-level statistics; guessing their types");
+                    HashMap::new()
+                },
+                Err(e) => {
+                    // the statistics themselves might still be obtainable
+                    error!("failed to query Radiator global info: {}; guessing the types of the top-level statistics", e);
+                    HashMap::new()
+                },
+            }
+        } else {
+            HashMap::new()
+        };
+
+        // ask Radiator for top-level statistics
+        match client.stats(".", &top_level_field_types).await {
+            Ok(s) => s,
+            Err(client::Error::PermissionDenied) => {
+                warn!("Radiator denied permission to query the top-level statistics; skipping them");
+                Statistics::default()
+            },
+            Err(e) => {
+                error!("failed to query Radiator global stats: {}", e);
+                return Err(FailureCategory::from_client_error(&e));
+            },
+        }
+    } else {
+        // only per-object metrics are configured
+        Statistics::default()
+    };
+
+    // run through per-object statistics
+    let mut object_type_to_statistics: HashMap<String, HashMap<usize, PerObjectStats>> = HashMap::new();
+    let mut object_kinds: Vec<(&PerObjectMetricConfig, &str)> = Vec::new();
+    for per_object_metrics in &config.per_object_metrics {
+        for object_kind in per_object_metrics.all_kinds() {
+            object_kinds.push((per_object_metrics, object_kind));
+        }
+    }
+    let mut object_kind_failed: BTreeMap<&str, bool> = BTreeMap::new();
+    let mut object_kind_present: BTreeMap<&str, bool> = BTreeMap::new();
+    let mut object_kind_counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    let mut object_kind_skipped: BTreeMap<&str, bool> = BTreeMap::new();
+    let mut last_object_kind_failure = None;
+    for (per_object_metrics, object_kind) in object_kinds {
+        if let Some(precondition) = per_object_metrics.precondition.as_ref() {
+            // if the statistic is unavailable, err on the side of querying the objects
+            let met = statistics.numbers.get(&precondition.statistic)
+                .map(|value| value.as_f64() >= precondition.min as f64)
+                .unwrap_or(true);
+            object_kind_skipped.insert(object_kind, !met);
+            if !met {
+                debug!(
+                    "top-level statistic {:?} is below {}; not querying the objects of kind {:?}",
+                    precondition.statistic, precondition.min, object_kind,
+                );
+                object_kind_failed.insert(object_kind, false);
+                continue;
+            }
+        }
+        match scrape_object_kind(config, &client, &mut budget, per_object_metrics, object_kind, started_at).await {
+            Ok(Some(kind_stats)) => {
+                object_kind_counts.insert(object_kind, (kind_stats.discovered, kind_stats.index_to_statistics.len()));
+                object_type_to_statistics.insert(object_kind.to_owned(), kind_stats.index_to_statistics);
+                object_kind_failed.insert(object_kind, false);
+                object_kind_present.insert(object_kind, true);
+            },
+            Ok(None) => {
+                object_kind_failed.insert(object_kind, false);
+                object_kind_present.insert(object_kind, false);
+            },
+            Err(e) => {
+                // error already output; the other kinds may well work
+                last_object_kind_failure = Some(FailureCategory::from_client_error(&e));
+                warn!("skipping the objects of kind {:?} in this scrape", object_kind);
+                object_kind_failed.insert(object_kind, true);
+            },
+        }
+        if budget.truncated {
+            break;
+        }
+    }
+    if !query_top_level && object_kind_failed.values().all(|failed| *failed) {
+        // nothing at all could be queried
+        error!("querying the objects of every kind failed");
+        return Err(last_object_kind_failure.unwrap_or(FailureCategory::RadiatorError));
+    }
+
+    // populate metrics database
+    // (statistics have been queried once per object; any number of metrics may use each of them)
+    let mut capped_metrics: BTreeSet<&str> = BTreeSet::new();
+    for metric_config in &config.metrics {
+        let mut samples = Vec::with_capacity(metric_config.samples.len());
+        collect_samples(metric_config, config.www.counter_values, &statistics.numbers, &BTreeMap::new(), None, &mut samples);
+        let capped = insert_metric(
+            &mut metric_database,
+            metric_config,
+            &[],
+            metric_config.unit.clone(),
+            metric_config.help.clone(),
+            samples,
+            metric_config.max_series.or(config.www.max_series_per_metric),
+        );
+        if capped {
+            capped_metrics.insert(&metric_config.metric);
+        }
+    }
+    for per_object_metrics in &config.per_object_metrics {
+        // metrics shared between multiple kinds collect the objects of all of them
+        let kind_statistics: Vec<(&str, &HashMap<usize, PerObjectStats>)> = per_object_metrics.all_kinds()
+            .filter_map(|kind| object_type_to_statistics.get(kind).map(|stats| (kind, stats)))
+            .collect();
+        let identifier_labels: Vec<&str> = per_object_metrics.kind_label.as_deref()
+            .into_iter()
+            .chain(per_object_metrics.identifier_labels().into_keys())
+            .collect();
+        for metric_config in &per_object_metrics.metrics {
+            let mut samples = Vec::new();
+            let all_statistics = kind_statistics.iter()
+                .flat_map(|(kind, index_to_statistics)| index_to_statistics.values().map(move |stats| (*kind, stats)));
+            for (kind, per_object_statistics) in all_statistics {
+                let mut identifier_labels = BTreeMap::new();
+                if let Some(kind_label) = per_object_metrics.kind_label.as_ref() {
+                    identifier_labels.insert(kind_label.clone(), kind.to_owned());
+                }
+                for (label, field) in per_object_metrics.identifier_labels() {
+                    // objects lacking some (but not all) identifier fields get an empty value
+                    let full_identifier = per_object_statistics.identifiers.get(field)
+                        .map(|i| i.as_str())
+                        .unwrap_or("");
+                    let mut identifier = Cow::Borrowed(full_identifier);
+                    if let Some(max_label_value_len) = config.www.max_label_value_len {
+                        identifier = truncate_label_value(full_identifier, max_label_value_len);
+                        if let Cow::Owned(truncated) = &identifier {
+                            debug!("truncated identifier {:?} to {:?}", full_identifier, truncated);
+                        }
+                    }
+                    identifier_labels.insert(label.to_owned(), identifier.into_owned());
+                }
+                // remembered values keep the time they were queried instead of the current one
+                let timestamp = per_object_statistics.queried_at
+                    .filter(|_| config.www.emit_scrape_timestamp);
+                collect_samples(metric_config, config.www.counter_values, &per_object_statistics.stats, &identifier_labels, timestamp, &mut samples);
+            }
+            let capped = insert_metric(
+                &mut metric_database,
+                metric_config,
+                &identifier_labels,
+                metric_config.unit.as_ref().map(|u| per_object_metrics.render_template(u)),
+                metric_config.help.as_ref().map(|h| per_object_metrics.render_template(h)),
+                samples,
+                metric_config.max_series.or(config.www.max_series_per_metric),
+            );
+            if capped {
+                capped_metrics.insert(&metric_config.metric);
+            }
+        }
+    }
+
+    for info_metric_config in &config.info_metrics {
+        insert_info_metric(&mut metric_database, info_metric_config, &statistics.strings);
+    }
+
+    let degraded = object_kind_failed.values().any(|failed| *failed);
+    if !object_kind_failed.is_empty() {
+        let metric = metric_database.get_or_insert("radiator_object_scrape_failed", MetricKind::Gauge);
+        metric.set_help(Some("Whether querying the objects of the given kind failed during this scrape (1) or not (0).".to_owned()));
+        metric.add_label("kind".to_owned());
+        for (object_kind, failed) in object_kind_failed {
+            let mut labels = BTreeMap::new();
+            labels.insert("kind".to_owned(), object_kind.to_owned());
+            metric.add_sample(&labels, Number::Integer(if failed { 1 } else { 0 }));
+        }
+    }
+    if !object_kind_counts.is_empty() {
+        let discovered_metric = metric_database.get_or_insert("radiator_objects_discovered", MetricKind::Gauge);
+        discovered_metric.set_help(Some("Number of objects of the given kind found during this scrape.".to_owned()));
+        discovered_metric.add_label("kind".to_owned());
+        for (object_kind, (discovered, _collected)) in &object_kind_counts {
+            let mut labels = BTreeMap::new();
+            labels.insert("kind".to_owned(), (*object_kind).to_owned());
+            discovered_metric.add_sample(&labels, Number::Integer((*discovered).try_into().unwrap_or(i64::MAX)));
+        }
+
+        let collected_metric = metric_database.get_or_insert("radiator_objects_collected", MetricKind::Gauge);
+        collected_metric.set_help(Some("Number of objects of the given kind whose statistics were collected during this scrape.".to_owned()));
+        collected_metric.add_label("kind".to_owned());
+        for (object_kind, (_discovered, collected)) in object_kind_counts {
+            let mut labels = BTreeMap::new();
+            labels.insert("kind".to_owned(), object_kind.to_owned());
+            collected_metric.add_sample(&labels, Number::Integer(collected.try_into().unwrap_or(i64::MAX)));
+        }
+    }
+    if !capped_metrics.is_empty() {
+        let metric = metric_database.get_or_insert("radiator_exporter_cardinality_capped", MetricKind::Gauge);
+        metric.set_help(Some("Present (with a value of 1) for each metric whose samples were cut down during this scrape because it had too many series.".to_owned()));
+        metric.add_label("metric".to_owned());
+        for capped_metric in capped_metrics {
+            let mut labels = BTreeMap::new();
+            labels.insert("metric".to_owned(), capped_metric.to_owned());
+            metric.add_sample(&labels, Number::Integer(1));
+        }
+    }
+    if !object_kind_skipped.is_empty() {
+        let metric = metric_database.get_or_insert("radiator_object_walk_skipped", MetricKind::Gauge);
+        metric.set_help(Some("Whether querying the objects of the given kind was skipped during this scrape because its precondition was not met (1) or not (0).".to_owned()));
+        metric.add_label("kind".to_owned());
+        for (object_kind, skipped) in object_kind_skipped {
+            let mut labels = BTreeMap::new();
+            labels.insert("kind".to_owned(), object_kind.to_owned());
+            metric.add_sample(&labels, Number::Integer(if skipped { 1 } else { 0 }));
+        }
+    }
+    if !object_kind_present.is_empty() {
+        let metric = metric_database.get_or_insert("radiator_object_kind_present", MetricKind::Gauge);
+        metric.set_help(Some("Whether Radiator recognizes the given object kind (1) or not (0).".to_owned()));
+        metric.add_label("kind".to_owned());
+        for (object_kind, present) in object_kind_present {
+            let mut labels = BTreeMap::new();
+            labels.insert("kind".to_owned(), object_kind.to_owned());
+            metric.add_sample(&labels, Number::Integer(if present { 1 } else { 0 }));
+        }
+    }
+
+    // incomplete data must not keep the exporter looking ready
+    if budget.truncated || degraded {
+        debug!("scrape was truncated or skipped failed object kinds; not recording it as successful");
+    } else {
+        record_successful_scrape();
+    }
+
+    if config.www.emit_scrape_timestamp {
+        metric_database.set_timestamps(started_at);
+    }
+
+    let statistic_coverage = statistic_coverage(config, &statistics, &object_type_to_statistics);
+
+    Ok(Scrape {
+        database: metric_database,
+        truncated: budget.truncated,
+        statistic_coverage,
+    })
+}
+
+
+/// Whether the warm-up scrape is still running.
+pub(crate) fn warming_up() -> bool {
+    WARMING_UP.load(Ordering::SeqCst)
+}
+
+/// Marks the exporter as warming up; call before spawning [`warm_up`].
+pub(crate) fn start_warm_up() {
+    WARMING_UP.store(true, Ordering::SeqCst);
+}
+
+/// Performs a scrape whose result is discarded, filling the identifier cache so that the first
+/// actual scrape is fast.
+///
+/// The exporter is ready afterwards even if the scrape fails.
+pub(crate) async fn warm_up(transport: &dyn RadiatorTransport) {
+    let config = CONFIG
+        .get().expect("CONFIG not set?!");
+    let started = Instant::now();
+    match scrape_radiator(&config, transport).await {
+        Ok(_) => info!(
+            event = "warmup_done",
+            duration_ms = u64::try_from(started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            "warm-up scrape finished",
+        ),
+        Err(_) => warn!(event = "warmup_failed", "warm-up scrape failed; becoming ready anyway"),
+    }
+    WARMING_UP.store(false, Ordering::SeqCst);
+}
+
+
+/// Scrapes the metrics of the given scrape group (`None` being the default group) at the given
+/// interval, storing each successful result in [`SNAPSHOTS`].
+pub(crate) async fn run_background_scrapes(interval: Duration, group: Option<String>, transport: &dyn RadiatorTransport) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        ticker.tick().await;
+
+        // the metrics may have been reloaded in the meantime
+        let group_config = CONFIG
+            .get().expect("CONFIG not set?!")
+            .for_scrape_group(group.as_deref());
+        let Ok(scrape) = scrape_radiator(&group_config, transport).await else {
+            // error already output; keep serving the previous snapshot
+            continue;
+        };
+        let snapshot = Snapshot {
+            scrape,
+            taken_at: Instant::now(),
+        };
+        SNAPSHOTS
+            .write().expect("SNAPSHOTS poisoned")
+            .insert(group.clone(), snapshot);
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use crate::openmetrics::{ExpositionFormat, LabelOrder, Metric, MetricKind};
+    use crate::replay::ReplayTransport;
+    use super::{scrape_connected, set_metadata};
+
+
+    const BASE_CONFIG: &str = r#"
+[www]
+bind_address = "127.0.0.1"
+port = 10014
+
+[radiator]
+target = "127.0.0.1"
+mgmt_port = 9000
+username = "prom"
+password = "secret"
+"#;
+
+    /// Collects the log output of a test.
+    #[derive(Clone, Debug, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+    impl LogBuffer {
+        /// Runs the given function, collecting its log output.
+        fn capture<R, F: FnOnce() -> R>(f: F) -> (R, String) {
+            let buffer = Self::default();
+            let writer_buffer = buffer.clone();
+            let subscriber = tracing_subscriber::fmt()
+                .with_writer(move || writer_buffer.clone())
+                .with_ansi(false)
+                .finish();
+            let result = tracing::subscriber::with_default(subscriber, f);
+            let log = buffer.0
+                .lock().expect("log buffer poisoned")
+                .clone();
+            (result, String::from_utf8(log).expect("log is not UTF-8"))
+        }
+    }
+    impl io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0
+                .lock().expect("log buffer poisoned")
+                .extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Scrapes the given canned responses using the given metric configuration and returns the
+    /// output.
+    async fn scrape(metrics_config: &str, responses: &[(&str, &str)]) -> String {
+        scrape_with_commands(metrics_config, responses).await.0
+    }
+
+    /// Like [`scrape`], but also returns the commands sent to Radiator.
+    async fn scrape_with_commands(metrics_config: &str, responses: &[(&str, &str)]) -> (String, Vec<String>) {
+        scrape_with_www_settings("", metrics_config, responses).await
+    }
+
+    /// Like [`scrape_with_commands`], but with additional settings in the `[www]` section.
+    async fn scrape_with_www_settings(www_settings: &str, metrics_config: &str, responses: &[(&str, &str)]) -> (String, Vec<String>) {
+        let base_config = BASE_CONFIG.replacen("[www]\n", &format!("[www]\n{}", www_settings), 1);
+        let config = crate::config::parse(&format!("{}{}", base_config, metrics_config))
+            .unwrap_or_else(|e| panic!("error in configuration: {}", e));
+        let transport = ReplayTransport::from_pairs(responses.iter().copied());
+        let scrape = scrape_connected(&config, &transport).await
+            .expect("scrape failed");
+        let mut output = String::new();
+        scrape.database.write(&mut output, ExpositionFormat::OpenMetrics, &config.www.counter_suffix, config.www.help_newlines)
+            .expect("failed to write metrics");
+        (output, transport.commands())
+    }
+
+    #[tokio::test]
+    async fn zero_label_metric_has_no_braces() {
+        let output = scrape(
+            r#"
+[[metrics]]
+metric = "radiator_requests"
+kind = "counter"
+[[metrics.samples]]
+labels = {}
+statistic = "Access requests"
+"#,
+            &[("STATS .", "Access requests:30")],
+        ).await;
+        assert_eq!(output, "# TYPE radiator_requests counter\nradiator_requests_total 30\n# EOF\n");
+    }
+
+    #[tokio::test]
+    async fn per_object_metric_without_statistic_is_left_out() {
+        let output = scrape(
+            r#"
+[[per_object_metrics]]
+kind = "Client"
+identifier_label = "client"
+
+[[per_object_metrics.metrics]]
+metric = "radiator_client_requests"
+kind = "counter"
+[[per_object_metrics.metrics.samples]]
+labels = {}
+statistic = "Access requests"
+
+[[per_object_metrics.metrics]]
+metric = "radiator_client_drops"
+kind = "counter"
+[[per_object_metrics.metrics.samples]]
+labels = { reason = "bad_authenticator" }
+statistic = "Bad authenticators"
+"#,
+            &[
+                ("DESCRIBE Client.0", "Identifier:string:a\x01Access requests:integer:0"),
+                ("STATS Client.0", "Access requests:3"),
+                ("DESCRIBE Client.1", "Identifier:string:b\x01Access requests:integer:0"),
+                ("STATS Client.1", "Access requests:4"),
+            ],
+        ).await;
+        assert!(output.contains("radiator_client_requests_total{client=\"a\"} 3\n"), "{}", output);
+        assert!(output.contains("radiator_client_requests_total{client=\"b\"} 4\n"), "{}", output);
+        assert!(!output.contains("radiator_client_drops"), "{}", output);
+    }
+
+    #[tokio::test]
+    async fn label_filters_split_objects() {
+        let output = scrape(
+            r#"
+[[per_object_metrics]]
+kind = "Realm"
+identifier_label = "realm"
+
+[[per_object_metrics.metrics]]
+metric = "radiator_local_realm_requests"
+kind = "counter"
+when = { label = "realm", matches = "local\\..*" }
+[[per_object_metrics.metrics.samples]]
+labels = {}
+statistic = "Access requests"
+
+[[per_object_metrics.metrics]]
+metric = "radiator_remote_realm_requests"
+kind = "counter"
+when = { label = "realm", matches = "remote\\..*" }
+[[per_object_metrics.metrics.samples]]
+labels = {}
+statistic = "Access requests"
+"#,
+            &[
+                ("DESCRIBE Realm.0", "Identifier:string:local.example\x01Access requests:integer:0"),
+                ("STATS Realm.0", "Access requests:1"),
+                ("DESCRIBE Realm.1", "Identifier:string:remote.example\x01Access requests:integer:0"),
+                ("STATS Realm.1", "Access requests:2"),
+                ("DESCRIBE Realm.2", "Identifier:string:other.example\x01Access requests:integer:0"),
+                ("STATS Realm.2", "Access requests:3"),
+            ],
+        ).await;
+        let metric_lines: Vec<&str> = output.lines()
+            .filter(|line| line.starts_with("radiator_local_") || line.starts_with("radiator_remote_"))
+            .collect();
+        assert_eq!(metric_lines, [
+            "radiator_local_realm_requests_total{realm=\"local.example\"} 1",
+            "radiator_remote_realm_requests_total{realm=\"remote.example\"} 2",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn one_statistic_feeds_several_metrics() {
+        let (output, commands) = scrape_with_commands(
+            r#"
+[[metrics]]
+metric = "radiator_requests"
+kind = "counter"
+[[metrics.samples]]
+labels = {}
+statistic = "Access requests"
+
+[[metrics]]
+metric = "radiator_requests_since_start"
+kind = "gauge"
+[[metrics.samples]]
+labels = {}
+statistic = "Access requests"
+
+[[per_object_metrics]]
+kind = "Host"
+identifier_label = "host"
+
+[[per_object_metrics.metrics]]
+metric = "radiator_host_requests"
+kind = "counter"
+[[per_object_metrics.metrics.samples]]
+labels = {}
+statistic = "Access requests"
+
+[[per_object_metrics.metrics]]
+metric = "radiator_host_busy"
+kind = "gauge"
+[[per_object_metrics.metrics.samples]]
+labels = {}
+statistic = "Access requests"
+"#,
+            &[
+                ("DESCRIBE .", "Access requests:integer:0"),
+                ("STATS .", "Access requests:30"),
+                ("DESCRIBE Host.0", "Identifier:string:h0\x01Access requests:integer:0"),
+                ("STATS Host.0", "Access requests:7"),
+            ],
+        ).await;
+        assert!(output.contains("\nradiator_requests_total 30\n"), "{}", output);
+        assert!(output.contains("\nradiator_requests_since_start 30\n"), "{}", output);
+        assert!(output.contains("\nradiator_host_requests_total{host=\"h0\"} 7\n"), "{}", output);
+        assert!(output.contains("\nradiator_host_busy{host=\"h0\"} 7\n"), "{}", output);
+
+        // each object is queried once, no matter how many metrics use its statistics
+        assert_eq!(commands, ["DESCRIBE .", "STATS .", "DESCRIBE Host.0", "DESCRIBE Host.1", "STATS Host.0"]);
+    }
+
+    #[tokio::test]
+    async fn reused_index_is_not_served_from_stats_cache() {
+        const SESSION_CONFIG: &str = r#"
+[[per_object_metrics]]
+kind = "Session"
+identifier_label = "session"
+stats_every_scrapes = 5
+
+[[per_object_metrics.metrics]]
+metric = "radiator_session_requests"
+kind = "counter"
+[[per_object_metrics.metrics.samples]]
+labels = {}
+statistic = "Access requests"
+"#;
+        let (output, _commands) = scrape_with_commands(
+            SESSION_CONFIG,
+            &[
+                ("DESCRIBE Session.0", "Identifier:string:a\x01Access requests:integer:0"),
+                ("STATS Session.0", "Access requests:3"),
+            ],
+        ).await;
+        assert!(output.contains("\nradiator_session_requests_total{session=\"a\"} 3\n"), "{}", output);
+
+        // the same object again is served from the cache
+        let (output, commands) = scrape_with_commands(
+            SESSION_CONFIG,
+            &[
+                ("DESCRIBE Session.0", "Identifier:string:a\x01Access requests:integer:0"),
+                ("STATS Session.0", "Access requests:4"),
+            ],
+        ).await;
+        assert!(output.contains("\nradiator_session_requests_total{session=\"a\"} 3\n"), "{}", output);
+        assert!(!commands.iter().any(|c| c == "STATS Session.0"), "{:?}", commands);
+
+        // a different object at the same index is not
+        let (output, commands) = scrape_with_commands(
+            SESSION_CONFIG,
+            &[
+                ("DESCRIBE Session.0", "Identifier:string:b\x01Access requests:integer:0"),
+                ("STATS Session.0", "Access requests:9"),
+            ],
+        ).await;
+        assert!(output.contains("\nradiator_session_requests_total{session=\"b\"} 9\n"), "{}", output);
+        assert!(commands.iter().any(|c| c == "STATS Session.0"), "{:?}", commands);
+    }
+
+    #[tokio::test]
+    async fn stats_cache_keeps_query_timestamp() {
+        const REALM_CONFIG: &str = r#"
+[[per_object_metrics]]
+kind = "Realm"
+identifier_label = "realm"
+stats_every_scrapes = 5
+
+[[per_object_metrics.metrics]]
+metric = "radiator_realm_requests"
+kind = "counter"
+[[per_object_metrics.metrics.samples]]
+labels = {}
+statistic = "Access requests"
+"#;
+        const RESPONSES: &[(&str, &str)] = &[
+            ("DESCRIBE Realm.0", "Identifier:string:r\x01Access requests:integer:0"),
+            ("STATS Realm.0", "Access requests:3"),
+        ];
+        fn timestamp_of<'a>(output: &'a str, sample_prefix: &str) -> &'a str {
+            let line = output.lines()
+                .find(|line| line.starts_with(sample_prefix))
+                .unwrap_or_else(|| panic!("no sample {:?} in {}", sample_prefix, output));
+            line.rsplit(' ').next().unwrap()
+        }
+
+        let (first_output, _commands) = scrape_with_www_settings("emit_scrape_timestamp = true\n", REALM_CONFIG, RESPONSES).await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let (second_output, commands) = scrape_with_www_settings("emit_scrape_timestamp = true\n", REALM_CONFIG, RESPONSES).await;
+        assert!(!commands.iter().any(|c| c == "STATS Realm.0"), "{:?}", commands);
+
+        // the remembered value keeps the time it was queried; everything else is current
+        let realm_sample = "radiator_realm_requests_total{realm=\"r\"} 3 ";
+        assert_eq!(timestamp_of(&first_output, realm_sample), timestamp_of(&second_output, realm_sample));
+        let kind_sample = "radiator_object_kind_present{kind=\"Realm\"} 1 ";
+        assert_ne!(timestamp_of(&first_output, kind_sample), timestamp_of(&second_output, kind_sample));
+    }
+
+    #[tokio::test]
+    async fn default_values_of_absent_statistics() {
+        let output = scrape(
+            r#"
+[[metrics]]
+metric = "radiator_requests"
+kind = "counter"
+[[metrics.samples]]
+labels = { result = "accepted" }
+statistic = "Access accepts"
+default_value = 0
+[[metrics.samples]]
+labels = { result = "rejected" }
+statistic = "Access rejects"
+default_value = 0
+[[metrics.samples]]
+labels = { result = "challenged" }
+statistic = "Access challenges"
+"#,
+            &[("STATS .", "Access accepts:12")],
+        ).await;
+        assert_eq!(output, "# TYPE radiator_requests counter\nradiator_requests_total{result=\"accepted\"} 12\nradiator_requests_total{result=\"rejected\"} 0\n# EOF\n");
+    }
+
+    /// Scrapes a counter fed a fractional and a negative value with the given `www.counter_values`.
+    async fn scrape_invalid_counter_values(counter_values: &str) -> String {
+        let (output, _commands) = scrape_with_www_settings(
+            &format!("counter_values = \"{}\"\n", counter_values),
+            r#"
+[[metrics]]
+metric = "radiator_requests"
+kind = "counter"
+[[metrics.samples]]
+labels = { result = "accepted" }
+statistic = "Access accepts"
+[[metrics.samples]]
+labels = { result = "rejected" }
+statistic = "Access rejects"
+[[metrics.samples]]
+labels = { result = "total" }
+statistic = "Access requests"
+"#,
+            &[("STATS .", "Access accepts:2.5\x01Access rejects:-5\x01Access requests:7.0")],
+        ).await;
+        output
+    }
+
+    #[tokio::test]
+    async fn invalid_counter_values_as_is() {
+        assert_eq!(
+            scrape_invalid_counter_values("as_is").await,
+            "# TYPE radiator_requests counter\nradiator_requests_total{result=\"accepted\"} 2.5\nradiator_requests_total{result=\"rejected\"} -5\nradiator_requests_total{result=\"total\"} 7\n# EOF\n",
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_counter_values_coerced() {
+        assert_eq!(
+            scrape_invalid_counter_values("coerce").await,
+            "# TYPE radiator_requests counter\nradiator_requests_total{result=\"accepted\"} 2\nradiator_requests_total{result=\"rejected\"} 0\nradiator_requests_total{result=\"total\"} 7\n# EOF\n",
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_counter_values_rejected() {
+        assert_eq!(
+            scrape_invalid_counter_values("reject").await,
+            "# TYPE radiator_requests counter\nradiator_requests_total{result=\"total\"} 7\n# EOF\n",
+        );
+    }
+
+    #[test]
+    fn first_metadata_wins() {
+        let mut metric = Metric::new("radiator_requests".to_owned(), MetricKind::Counter, LabelOrder::default());
+        let ((), log) = LogBuffer::capture(|| {
+            set_metadata(&mut metric, None, Some("Requests handled.".to_owned()));
+            set_metadata(&mut metric, Some("requests".to_owned()), Some("Requests received.".to_owned()));
+            set_metadata(&mut metric, Some("packets".to_owned()), Some("Requests handled.".to_owned()));
+        });
+        assert_eq!(metric.help(), Some("Requests handled."));
+        assert_eq!(metric.unit(), Some("requests"));
+        assert!(log.contains("conflicting help texts configured for metric \"radiator_requests\"; keeping \"Requests handled.\""), "unexpected log: {}", log);
+        assert!(log.contains("conflicting units configured for metric \"radiator_requests\"; keeping \"requests\""), "unexpected log: {}", log);
+        assert_eq!(log.matches("conflicting").count(), 2, "unexpected log: {}", log);
+    }
+}
@@ -0,0 +1,492 @@
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+use http_body_util::Full;
+use hyper::Response;
+use hyper::body::Bytes;
+use tracing::error;
+
+use crate::config::{Config, CounterValues};
+use crate::decode::INVALID_UTF8_FIELDS;
+use crate::identifier_cache::IDENTIFIER_CACHE;
+use crate::openmetrics::{ExpositionFormat, MetricDatabase, MetricKind, Number};
+use crate::radiator::{breaker_state, connection_age, DisconnectReason, last_disconnect_reason, standby_ready};
+use crate::scrape::StatisticCoverage;
+
+
+pub(crate) static COMMANDS: AtomicU64 = AtomicU64::new(0);
+pub(crate) static BYTES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+pub(crate) static IDENTIFIER_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+pub(crate) static IDENTIFIER_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+pub(crate) static NEGATIVE_VALUES: AtomicU64 = AtomicU64::new(0);
+pub(crate) static INVALID_COUNTER_VALUES: AtomicU64 = AtomicU64::new(0);
+pub(crate) static REMOTE_WRITE_PUSHES: AtomicU64 = AtomicU64::new(0);
+pub(crate) static REMOTE_WRITE_FAILURES: AtomicU64 = AtomicU64::new(0);
+pub(crate) static PUSHGATEWAY_PUSHES: AtomicU64 = AtomicU64::new(0);
+pub(crate) static PUSHGATEWAY_FAILURES: AtomicU64 = AtomicU64::new(0);
+/// Number of reconnections to Radiator, by the reason the previous connection was lost.
+pub(crate) static RECONNECTS: Mutex<BTreeMap<DisconnectReason, u64>> = Mutex::new(BTreeMap::new());
+/// Number of commands refused by Radiator for lack of permission, by command verb.
+pub(crate) static PERMISSION_DENIED: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+/// How long Radiator took to respond to commands, by command verb.
+pub(crate) static COMMAND_DURATIONS: Mutex<BTreeMap<String, CommandDurations>> = Mutex::new(BTreeMap::new());
+
+
+/// Round-trip times of the commands with a given verb.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct CommandDurations {
+    pub last: Duration,
+    pub max: Duration,
+    pub total: Duration,
+
+    /// The number of commands whose times are added up in `total`.
+    pub count: u64,
+}
+
+
+/// Information about the current scrape that is exported as self-metrics.
+#[derive(Clone, Debug)]
+pub(crate) struct ScrapeInfo {
+    pub truncated: bool,
+    pub snapshot_age: Option<Duration>,
+    pub group_snapshot_ages: BTreeMap<String, Duration>,
+    pub radiator_up: bool,
+
+    /// How many configured statistics Radiator reported, if Radiator was scraped.
+    pub statistic_coverage: Option<StatisticCoverage>,
+}
+
+
+pub(crate) fn add_simple(
+    database: &mut MetricDatabase,
+    instance_labels: &BTreeMap<String, String>,
+    name: &str,
+    kind: MetricKind,
+    help: &str,
+    value: Number,
+) {
+    let metric = database.get_or_insert(name, kind);
+    metric.set_help(Some(help.to_owned()));
+    for label in instance_labels.keys() {
+        if !metric.has_label(label) {
+            metric.add_label(label.clone());
+        }
+    }
+    metric.add_sample(instance_labels, value);
+}
+
+fn load_counter(counter: &AtomicU64) -> Number {
+    Number::Integer(counter.load(Ordering::Relaxed).try_into().unwrap_or(i64::MAX))
+}
+
+
+/// Records a reconnection to Radiator after the previous connection was lost for the given reason.
+pub(crate) fn record_reconnect(reason: DisconnectReason) {
+    *RECONNECTS
+        .lock().expect("RECONNECTS poisoned")
+        .entry(reason).or_insert(0) += 1;
+}
+
+
+/// Records the time between sending a command and receiving its response.
+pub(crate) fn record_command_duration(command: &[u8], duration: Duration) {
+    let verb_bytes = command.split(|b| *b == b' ').next().unwrap_or(command);
+    let verb = String::from_utf8_lossy(verb_bytes).into_owned();
+
+    let mut durations = COMMAND_DURATIONS
+        .lock().expect("COMMAND_DURATIONS poisoned");
+    let command_durations = durations.entry(verb).or_default();
+    command_durations.last = duration;
+    command_durations.max = command_durations.max.max(duration);
+    command_durations.total += duration;
+    command_durations.count += 1;
+}
+
+
+fn add_command_duration_metric(
+    database: &mut MetricDatabase,
+    instance_labels: &BTreeMap<String, String>,
+    durations: &BTreeMap<String, CommandDurations>,
+    name: &str,
+    kind: MetricKind,
+    help: &str,
+    get_value: fn(&CommandDurations) -> Number,
+) {
+    let metric = database.get_or_insert(name, kind);
+    metric.set_help(Some(help.to_owned()));
+    for label in instance_labels.keys() {
+        metric.add_label(label.clone());
+    }
+    metric.add_label("command".to_owned());
+    for (command, command_durations) in durations {
+        let mut labels = instance_labels.clone();
+        labels.insert("command".to_owned(), command.clone());
+        metric.add_sample(&labels, get_value(command_durations));
+    }
+}
+
+
+fn add_command_durations(database: &mut MetricDatabase, instance_labels: &BTreeMap<String, String>) {
+    let durations = COMMAND_DURATIONS
+        .lock().expect("COMMAND_DURATIONS poisoned")
+        .clone();
+    if durations.is_empty() {
+        return;
+    }
+
+    add_command_duration_metric(
+        database,
+        instance_labels,
+        &durations,
+        "radiator_exporter_command_duration_seconds",
+        MetricKind::Counter,
+        "Total time spent waiting for Radiator to respond to commands.",
+        |d| Number::Float(d.total.as_secs_f64()),
+    );
+    add_command_duration_metric(
+        database,
+        instance_labels,
+        &durations,
+        "radiator_exporter_command_responses",
+        MetricKind::Counter,
+        "Number of responses to commands received from Radiator, whose waiting times radiator_exporter_command_duration_seconds adds up.",
+        |d| Number::Integer(i64::try_from(d.count).unwrap_or(i64::MAX)),
+    );
+    add_command_duration_metric(
+        database,
+        instance_labels,
+        &durations,
+        "radiator_exporter_last_command_duration_seconds",
+        MetricKind::Gauge,
+        "Time Radiator took to respond to the most recent command.",
+        |d| Number::Float(d.last.as_secs_f64()),
+    );
+    add_command_duration_metric(
+        database,
+        instance_labels,
+        &durations,
+        "radiator_exporter_max_command_duration_seconds",
+        MetricKind::Gauge,
+        "Longest time Radiator has taken to respond to a command since the exporter was started.",
+        |d| Number::Float(d.max.as_secs_f64()),
+    );
+}
+
+
+fn add_connection_metrics(database: &mut MetricDatabase, instance_labels: &BTreeMap<String, String>) {
+    let reconnects = RECONNECTS
+        .lock().expect("RECONNECTS poisoned")
+        .clone();
+    let metric = database.get_or_insert("radiator_exporter_reconnects", MetricKind::Counter);
+    metric.set_help(Some("Number of times the exporter has reconnected to the Radiator management port, by the reason the previous connection was lost.".to_owned()));
+    for label in instance_labels.keys() {
+        metric.add_label(label.clone());
+    }
+    metric.add_label("reason".to_owned());
+    for reason in DisconnectReason::ALL {
+        let mut labels = instance_labels.clone();
+        labels.insert("reason".to_owned(), reason.as_str().to_owned());
+        let count = reconnects.get(&reason).copied().unwrap_or(0);
+        metric.add_sample(&labels, Number::Integer(count.try_into().unwrap_or(i64::MAX)));
+    }
+
+    if let Some(last_reason) = last_disconnect_reason() {
+        let metric = database.get_or_insert("radiator_exporter_last_disconnect_reason", MetricKind::Gauge);
+        metric.set_help(Some("Whether the given reason is why the connection to Radiator was most recently lost (1) or not (0).".to_owned()));
+        for label in instance_labels.keys() {
+            metric.add_label(label.clone());
+        }
+        metric.add_label("reason".to_owned());
+        for reason in DisconnectReason::ALL {
+            let mut labels = instance_labels.clone();
+            labels.insert("reason".to_owned(), reason.as_str().to_owned());
+            metric.add_sample(&labels, Number::Integer(if reason == last_reason { 1 } else { 0 }));
+        }
+    }
+
+    // only while connected
+    if let Some(age) = connection_age() {
+        add_simple(
+            database,
+            instance_labels,
+            "radiator_exporter_connection_age_seconds",
+            MetricKind::Gauge,
+            "Time since the current connection to the Radiator management port was logged into.",
+            Number::Float(age.as_secs_f64()),
+        );
+    }
+}
+
+
+fn add_permission_denied(database: &mut MetricDatabase, instance_labels: &BTreeMap<String, String>) {
+    let metric = database.get_or_insert("radiator_exporter_permission_denied", MetricKind::Counter);
+    metric.set_help(Some("Number of commands Radiator refused to execute for lack of permission.".to_owned()));
+    for label in instance_labels.keys() {
+        metric.add_label(label.clone());
+    }
+    metric.add_label("command".to_owned());
+
+    let mut denials = PERMISSION_DENIED
+        .lock().expect("PERMISSION_DENIED poisoned")
+        .clone();
+    // the commands we issue are always present
+    for command in ["DESCRIBE", "STATS"] {
+        denials.entry(command.to_owned()).or_insert(0);
+    }
+    for (command, count) in denials {
+        let mut labels = instance_labels.clone();
+        labels.insert("command".to_owned(), command);
+        metric.add_sample(&labels, Number::Integer(count.try_into().unwrap_or(i64::MAX)));
+    }
+}
+
+
+/// Adds the exporter's own metrics to the database.
+///
+/// Each of them is labeled with the exporter instance to tell apart multiple exporters scraping the
+/// same Radiator server. Without `scrape_info`, the metrics describing the current scrape are left
+/// out.
+pub(crate) fn collect(database: &mut MetricDatabase, config: &Config, scrape_info: Option<&ScrapeInfo>) {
+    let mut instance_labels = BTreeMap::new();
+    instance_labels.insert(config.exporter.instance_label.clone(), config.exporter.instance_value());
+
+    if let Some(scrape_info) = scrape_info {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_up",
+            MetricKind::Gauge,
+            "Whether Radiator could be queried (1) or not (0).",
+            Number::Integer(if scrape_info.radiator_up { 1 } else { 0 }),
+        );
+    }
+    add_connection_metrics(database, &instance_labels);
+    add_simple(
+        database,
+        &instance_labels,
+        "radiator_exporter_commands",
+        MetricKind::Counter,
+        "Number of commands sent to the Radiator management port.",
+        load_counter(&COMMANDS),
+    );
+    add_simple(
+        database,
+        &instance_labels,
+        "radiator_exporter_bytes_received",
+        MetricKind::Counter,
+        "Number of bytes received from the Radiator management port.",
+        load_counter(&BYTES_RECEIVED),
+    );
+    add_simple(
+        database,
+        &instance_labels,
+        "radiator_exporter_negative_values",
+        MetricKind::Counter,
+        "Number of negative values of metrics declared as non-negative that were skipped or clamped to 0.",
+        load_counter(&NEGATIVE_VALUES),
+    );
+    add_simple(
+        database,
+        &instance_labels,
+        "radiator_exporter_invalid_utf8",
+        MetricKind::Counter,
+        "Number of statistics or fields in Radiator responses that were skipped because they were not valid UTF-8.",
+        load_counter(&INVALID_UTF8_FIELDS),
+    );
+    if config.www.counter_values != CounterValues::AsIs {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_invalid_counter_values",
+            MetricKind::Counter,
+            "Number of counter values that were not non-negative whole numbers and were skipped or coerced.",
+            load_counter(&INVALID_COUNTER_VALUES),
+        );
+    }
+    if let Some(scrape_info) = scrape_info {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_scrape_truncated",
+            MetricKind::Gauge,
+            "Whether the scrape was cut short because radiator.max_commands_per_scrape was reached (1) or not (0).",
+            Number::Integer(if scrape_info.truncated { 1 } else { 0 }),
+        );
+    }
+    if let Some(statistic_coverage) = scrape_info.and_then(|si| si.statistic_coverage) {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_configured_statistics",
+            MetricKind::Gauge,
+            "Number of distinct Radiator statistics used by the configured metrics.",
+            Number::Integer(statistic_coverage.configured.try_into().unwrap_or(i64::MAX)),
+        );
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_collected_statistics",
+            MetricKind::Gauge,
+            "Number of distinct Radiator statistics used by the configured metrics that Radiator reported during the scrape.",
+            Number::Integer(statistic_coverage.collected.try_into().unwrap_or(i64::MAX)),
+        );
+    }
+    add_permission_denied(database, &instance_labels);
+    add_command_durations(database, &instance_labels);
+    if config.radiator.breaker_failure_threshold.is_some() {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_circuit_breaker_state",
+            MetricKind::Gauge,
+            "State of the circuit breaker guarding reconnection attempts (0 = closed, 1 = open, 2 = half-open).",
+            Number::Integer(breaker_state(&config.radiator).as_number()),
+        );
+    }
+    if config.radiator.hot_standby {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_standby_ready",
+            MetricKind::Gauge,
+            "Whether a standby connection to Radiator is ready to take over (1) or not (0).",
+            Number::Integer(if standby_ready() { 1 } else { 0 }),
+        );
+    }
+    if config.remote_write.is_some() {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_remote_write_pushes",
+            MetricKind::Counter,
+            "Number of times metrics were successfully pushed to the remote-write endpoint.",
+            load_counter(&REMOTE_WRITE_PUSHES),
+        );
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_remote_write_failures",
+            MetricKind::Counter,
+            "Number of times pushing metrics to the remote-write endpoint failed.",
+            load_counter(&REMOTE_WRITE_FAILURES),
+        );
+    }
+    if config.pushgateway.is_some() {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_pushgateway_pushes",
+            MetricKind::Counter,
+            "Number of times metrics were successfully pushed to the Pushgateway.",
+            load_counter(&PUSHGATEWAY_PUSHES),
+        );
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_pushgateway_failures",
+            MetricKind::Counter,
+            "Number of attempts to push metrics to the Pushgateway that failed (including retries).",
+            load_counter(&PUSHGATEWAY_FAILURES),
+        );
+    }
+    if config.radiator.identifier_cache_max.is_some() {
+        let cache_entries = IDENTIFIER_CACHE
+            .lock().expect("IDENTIFIER_CACHE poisoned")
+            .len();
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_identifier_cache_entries",
+            MetricKind::Gauge,
+            "Number of Radiator objects whose identifiers are currently cached.",
+            Number::Integer(cache_entries.try_into().unwrap_or(i64::MAX)),
+        );
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_identifier_cache_hits",
+            MetricKind::Counter,
+            "Number of Radiator object lookups answered from the identifier cache.",
+            load_counter(&IDENTIFIER_CACHE_HITS),
+        );
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_identifier_cache_misses",
+            MetricKind::Counter,
+            "Number of Radiator object lookups that required a DESCRIBE command.",
+            load_counter(&IDENTIFIER_CACHE_MISSES),
+        );
+    }
+    if let Some(snapshot_age) = scrape_info.and_then(|si| si.snapshot_age) {
+        add_simple(
+            database,
+            &instance_labels,
+            "radiator_exporter_snapshot_age_seconds",
+            MetricKind::Gauge,
+            "Time since the served background scrape was taken.",
+            Number::Float(snapshot_age.as_secs_f64()),
+        );
+    }
+    if let Some(scrape_info) = scrape_info.filter(|si| !si.group_snapshot_ages.is_empty()) {
+        let metric = database.get_or_insert("radiator_exporter_group_snapshot_age_seconds", MetricKind::Gauge);
+        metric.set_help(Some("Time since the served background scrape of the given scrape group was taken.".to_owned()));
+        for label in instance_labels.keys() {
+            metric.add_label(label.clone());
+        }
+        metric.add_label("group".to_owned());
+        for (group, snapshot_age) in &scrape_info.group_snapshot_ages {
+            let mut labels = instance_labels.clone();
+            labels.insert("group".to_owned(), group.clone());
+            metric.add_sample(&labels, Number::Float(snapshot_age.as_secs_f64()));
+        }
+    }
+    if config.exporter.process_metrics {
+        crate::process_metrics::collect(database, &instance_labels);
+    }
+}
+
+
+/// Handles `/self-metrics`, returning only the exporter's own metrics without querying Radiator.
+pub(crate) fn handle_self_metrics(config: &Config) -> Result<Response<Full<Bytes>>, Infallible> {
+    let mut database = MetricDatabase::with_label_order(config.www.label_order);
+    collect(&mut database, config, None);
+
+    // no scrape is performed, so report how long ago the last one succeeded
+    let mut instance_labels = BTreeMap::new();
+    instance_labels.insert(config.exporter.instance_label.clone(), config.exporter.instance_value());
+    add_simple(
+        &mut database,
+        &instance_labels,
+        "radiator_exporter_last_successful_scrape_age_seconds",
+        MetricKind::Gauge,
+        "Time since a scrape of Radiator last succeeded (or since the exporter was started).",
+        Number::Float(crate::scrape::last_successful_scrape_age().as_secs_f64()),
+    );
+
+    if config.www.emit_scrape_timestamp {
+        database.set_timestamps(SystemTime::now());
+    }
+
+    let format = ExpositionFormat::OpenMetrics;
+    let mut output = String::new();
+    if let Err(e) = database.write(&mut output, format, &config.www.counter_suffix, config.www.help_newlines) {
+        error!("error collecting self-metrics output: {}", e);
+        return crate::return_500();
+    }
+
+    let response_res = Response::builder()
+        .status(200)
+        .header("Content-Type", format.content_type())
+        .body(Full::new(Bytes::from(output)));
+    match response_res {
+        Ok(r) => Ok(r),
+        Err(e) => {
+            error!("failed to construct 200 response: {}", e);
+            crate::return_500()
+        },
+    }
+}
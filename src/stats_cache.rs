@@ -0,0 +1,107 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::openmetrics::Number;
+
+
+pub(crate) static STATS_CACHE: Mutex<StatsCache> = Mutex::new(StatsCache::new());
+
+
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    stats: HashMap<String, Number>,
+
+    /// The identifiers of the object whose statistics these are; Radiator may reuse the index for a
+    /// different object.
+    identifiers: BTreeMap<String, String>,
+
+    /// The start of the scrape that queried the statistics.
+    queried_at: SystemTime,
+
+    queried_in_scrape: u64,
+}
+
+
+/// Remembers the statistics of Radiator objects by kind and index for object kinds that are only
+/// queried every few scrapes (`stats_every_scrapes`).
+///
+/// Scrapes are counted per object kind, since scrape groups query different kinds at different
+/// intervals. Like the identifier cache, the cache is emptied whenever the connection to Radiator is
+/// re-established.
+#[derive(Debug)]
+pub(crate) struct StatsCache {
+    entries: BTreeMap<(String, usize), CacheEntry>,
+    scrape_counters: BTreeMap<String, u64>,
+    connection_generation: u64,
+}
+impl StatsCache {
+    pub const fn new() -> Self {
+        Self {
+            entries: BTreeMap::new(),
+            scrape_counters: BTreeMap::new(),
+            connection_generation: 0,
+        }
+    }
+
+    fn check_generation(&mut self) {
+        let connection_generation = crate::radiator::connection_generation();
+        if self.connection_generation != connection_generation {
+            self.entries.clear();
+            self.connection_generation = connection_generation;
+        }
+    }
+
+    fn scrape_counter(&self, kind: &str) -> u64 {
+        self.scrape_counters.get(kind).copied().unwrap_or(0)
+    }
+
+    /// Counts a new scrape of the given object kinds, aging their entries.
+    pub fn start_scrape<'a, I: IntoIterator<Item = &'a str>>(&mut self, kinds: I) {
+        self.check_generation();
+        for kind in kinds {
+            *self.scrape_counters.entry(kind.to_owned()).or_insert(0) += 1;
+        }
+    }
+
+    /// Returns the statistics of the given object, along with the time they were queried, if they
+    /// have been queried less than `every_scrapes` scrapes ago.
+    ///
+    /// If the object at that index now has different identifiers, it is a different object; the
+    /// statistics of the previous one are forgotten.
+    pub fn get(&mut self, kind: &str, index: usize, identifiers: &BTreeMap<String, String>, every_scrapes: usize) -> Option<(HashMap<String, Number>, SystemTime)> {
+        self.check_generation();
+        let key = (kind.to_owned(), index);
+        let entry = self.entries.get(&key)?;
+        if entry.identifiers != *identifiers {
+            self.entries.remove(&key);
+            return None;
+        }
+        let age = self.scrape_counter(kind) - entry.queried_in_scrape;
+        if age < u64::try_from(every_scrapes).unwrap_or(u64::MAX) {
+            Some((entry.stats.clone(), entry.queried_at))
+        } else {
+            None
+        }
+    }
+
+    pub fn insert(&mut self, kind: &str, index: usize, identifiers: BTreeMap<String, String>, stats: HashMap<String, Number>, queried_at: SystemTime) {
+        self.check_generation();
+        self.entries.insert((kind.to_owned(), index), CacheEntry {
+            stats,
+            identifiers,
+            queried_at,
+            queried_in_scrape: self.scrape_counter(kind),
+        });
+    }
+
+    pub fn remove(&mut self, kind: &str, index: usize) {
+        self.entries.remove(&(kind.to_owned(), index));
+    }
+
+    /// Forgets all objects, e.g. because the configuration has been reloaded and the indexes may
+    /// now refer to different objects.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
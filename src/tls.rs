@@ -0,0 +1,226 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio_rustls::{TlsAcceptor, TlsConnector};
+use tokio_rustls::rustls::{self, ClientConfig, DigitallySignedStruct, RootCertStore, ServerConfig, SignatureScheme};
+use tokio_rustls::rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::crypto::{verify_tls12_signature, verify_tls13_signature, WebPkiSupportedAlgorithms};
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use tokio_rustls::rustls::server::{ServerConnection, VerifierBuilderError, WebPkiClientVerifier};
+
+use crate::config::{HttpVersion, TlsConfig};
+
+
+#[derive(Debug)]
+pub(crate) enum Error {
+    Io(io::Error),
+    NoCertificates,
+    NoPrivateKey,
+    Tls(rustls::Error),
+    ClientVerifier(VerifierBuilderError),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::NoCertificates => write!(f, "no certificates found"),
+            Self::NoPrivateKey => write!(f, "no private key found"),
+            Self::Tls(e) => write!(f, "TLS error: {}", e),
+            Self::ClientVerifier(e) => write!(f, "error setting up client certificate verification: {}", e),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::NoCertificates => None,
+            Self::NoPrivateKey => None,
+            Self::Tls(e) => Some(e),
+            Self::ClientVerifier(e) => Some(e),
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(value: io::Error) -> Self {
+        Self::Io(value)
+    }
+}
+impl From<rustls::Error> for Error {
+    fn from(value: rustls::Error) -> Self {
+        Self::Tls(value)
+    }
+}
+impl From<VerifierBuilderError> for Error {
+    fn from(value: VerifierBuilderError) -> Self {
+        Self::ClientVerifier(value)
+    }
+}
+
+
+fn load_certificates(path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let certificates = rustls_pemfile::certs(&mut reader)
+        .collect::<Result<Vec<_>, _>>()?;
+    if certificates.is_empty() {
+        return Err(Error::NoCertificates);
+    }
+    Ok(certificates)
+}
+
+fn load_private_key(path: &Path) -> Result<PrivateKeyDer<'static>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    rustls_pemfile::private_key(&mut reader)?
+        .ok_or(Error::NoPrivateKey)
+}
+
+
+/// Creates a TLS acceptor from the given configuration, offering the given HTTP versions.
+///
+/// If a client CA is configured, clients must present a certificate signed by that CA; otherwise,
+/// the TLS handshake fails.
+pub(crate) fn make_acceptor(config: &TlsConfig, http_version: HttpVersion) -> Result<TlsAcceptor, Error> {
+    let certificates = load_certificates(&config.certificate)?;
+    let private_key = load_private_key(&config.private_key)?;
+
+    let builder = ServerConfig::builder();
+    let builder = match config.client_ca.as_ref() {
+        Some(client_ca) => {
+            let mut roots = RootCertStore::empty();
+            for ca_certificate in load_certificates(client_ca)? {
+                roots.add(ca_certificate)?;
+            }
+            let verifier = WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        None => builder.with_no_client_auth(),
+    };
+    let mut server_config = builder.with_single_cert(certificates, private_key)?;
+    server_config.alpn_protocols = http_version.alpn_protocols();
+
+    Ok(TlsAcceptor::from(Arc::new(server_config)))
+}
+
+
+/// Accepts only the given server certificate.
+///
+/// The health check connects to the exporter itself, whose certificate is known but is rarely issued
+/// for the loopback address.
+#[derive(Debug)]
+struct PinnedServerCertificate {
+    certificate: CertificateDer<'static>,
+    algorithms: WebPkiSupportedAlgorithms,
+}
+impl ServerCertVerifier for PinnedServerCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if end_entity.as_ref() == self.certificate.as_ref() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(rustls::CertificateError::ApplicationVerificationFailure))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+
+/// Creates a TLS connector with which the health check connects to the exporter serving with the
+/// given configuration.
+///
+/// The server must present the configured certificate. If a health check client certificate is
+/// configured, it is presented to the server. The given HTTP versions are offered via ALPN.
+pub(crate) fn make_healthcheck_connector(config: &TlsConfig, http_version: HttpVersion) -> Result<TlsConnector, Error> {
+    // the first certificate of the chain is the server's own
+    let server_certificate = load_certificates(&config.certificate)?.swap_remove(0);
+    let verifier = PinnedServerCertificate {
+        certificate: server_certificate,
+        algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+    };
+
+    let builder = ClientConfig::builder()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier));
+    let mut client_config = match (config.healthcheck_certificate.as_ref(), config.healthcheck_private_key.as_ref()) {
+        (Some(certificate), Some(private_key)) => builder.with_client_auth_cert(
+            load_certificates(certificate)?,
+            load_private_key(private_key)?,
+        )?,
+        _ => builder.with_no_client_auth(),
+    };
+    client_config.alpn_protocols = http_version.alpn_protocols();
+
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+
+/// Creates a TLS connector with which the exporter pushes metrics to an endpoint (remote-write or
+/// Pushgateway).
+///
+/// The server certificate is verified against the given CA certificates or, if none are given, the
+/// certificates trusted by the operating system.
+pub(crate) fn make_push_connector(ca_certificate: Option<&Path>) -> Result<TlsConnector, Error> {
+    let mut roots = RootCertStore::empty();
+    match ca_certificate {
+        Some(ca_certificate) => {
+            for certificate in load_certificates(ca_certificate)? {
+                roots.add(certificate)?;
+            }
+        },
+        None => {
+            // certificates that fail to load or parse are skipped, as long as some remain
+            let native_certificates = rustls_native_certs::load_native_certs();
+            roots.add_parsable_certificates(native_certificates.certs);
+            if roots.is_empty() {
+                return Err(Error::NoCertificates);
+            }
+        },
+    }
+
+    let mut client_config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+
+/// Returns the subject of the certificate presented by the client, if any.
+pub(crate) fn client_subject(connection: &ServerConnection) -> Option<String> {
+    let certificate = connection.peer_certificates()?.first()?;
+    match x509_parser::parse_x509_certificate(certificate) {
+        Ok((_rest, parsed)) => Some(parsed.subject().to_string()),
+        Err(_) => None,
+    }
+}